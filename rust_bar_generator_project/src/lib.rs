@@ -1,12 +1,17 @@
-use chrono::{Datelike, Duration, Timelike, DateTime, NaiveDate, NaiveDateTime, TimeZone};
+use chrono::{Datelike, Duration, Timelike, DateTime, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone, Weekday};
 use chrono_tz::Asia::Shanghai;
 use once_cell::sync::Lazy;
-use pyo3::exceptions::PyValueError;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::buffer::PyBuffer;
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyModule, PyTuple, PyDateTime};
+use pyo3::sync::PyOnceLock;
+use pyo3::types::{PyCFunction, PyDate, PyDict, PyFrozenSet, PyList, PyModule, PyTuple, PyDateTime, PyType, PyWeakrefMethods, PyWeakrefReference};
+use pyo3::types::{PyDateAccess, PyDelta, PyDeltaAccess, PyTimeAccess, PyTzInfo, PyTzInfoAccess};
 use regex::Regex;
-use std::sync::RwLock;
-use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::thread::ThreadId;
 // ================================================================================================
 // 时区常量
 // ================================================================================================
@@ -14,9 +19,10 @@ static TZ_INFO: Lazy<chrono_tz::Tz> = Lazy::new(|| Shanghai);
 
 // ================================================================================================
 // RustInterval 枚举 - 时间周期
+// 变体按时间跨度从小到大声明，比较运算按跨度排序（TICK < MINUTE < ... < MONTHLY）
 // ================================================================================================
-#[pyclass(eq, eq_int, module = "rust_bar_generator")]
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[pyclass(eq, ord, eq_int, module = "rust_bar_generator")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum RustInterval {
     #[pyo3(name = "TICK")]
     TICK,
@@ -251,14 +257,339 @@ pub enum RustExchange {
     LOCAL,
 }
 
+/// 交易所分类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ExchangeCategory {
+    Chinese,
+    GlobalStock,
+    GlobalFuture,
+    Crypto,
+    Other,
+}
+
+impl ExchangeCategory {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s.to_lowercase().as_str() {
+            "chinese" => Ok(ExchangeCategory::Chinese),
+            "global_stock" => Ok(ExchangeCategory::GlobalStock),
+            "global_future" => Ok(ExchangeCategory::GlobalFuture),
+            "crypto" => Ok(ExchangeCategory::Crypto),
+            "other" => Ok(ExchangeCategory::Other),
+            _ => Err(PyValueError::new_err(format!(
+                "无效的交易所分类: {}，可选 chinese、global_stock、global_future、crypto、other", s
+            ))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ExchangeCategory::Chinese => "chinese",
+            ExchangeCategory::GlobalStock => "global_stock",
+            ExchangeCategory::GlobalFuture => "global_future",
+            ExchangeCategory::Crypto => "crypto",
+            ExchangeCategory::Other => "other",
+        }
+    }
+}
+
+/// 全部交易所，供 RustExchange.all() 使用
+const ALL_EXCHANGES: [RustExchange; 72] = [
+    RustExchange::CFFEX,
+    RustExchange::SHFE,
+    RustExchange::CZCE,
+    RustExchange::DCE,
+    RustExchange::GFEX,
+    RustExchange::INE,
+    RustExchange::SSE,
+    RustExchange::SZSE,
+    RustExchange::BSE,
+    RustExchange::SGE,
+    RustExchange::WXE,
+    RustExchange::CFETS,
+    RustExchange::SMART,
+    RustExchange::NYSE,
+    RustExchange::NASDAQ,
+    RustExchange::ARCA,
+    RustExchange::EDGEA,
+    RustExchange::ISLAND,
+    RustExchange::BATS,
+    RustExchange::IEX,
+    RustExchange::NYMEX,
+    RustExchange::COMEX,
+    RustExchange::GLOBEX,
+    RustExchange::IDEALPRO,
+    RustExchange::CME,
+    RustExchange::ICE,
+    RustExchange::SEHK,
+    RustExchange::HKFE,
+    RustExchange::HKSE,
+    RustExchange::SGX,
+    RustExchange::CBOT,
+    RustExchange::CBOE,
+    RustExchange::CFE,
+    RustExchange::DME,
+    RustExchange::EUREX,
+    RustExchange::APEX,
+    RustExchange::LME,
+    RustExchange::BMD,
+    RustExchange::TOCOM,
+    RustExchange::EUNX,
+    RustExchange::KRX,
+    RustExchange::OTC,
+    RustExchange::IBKRATS,
+    RustExchange::TSE,
+    RustExchange::AMEX,
+    RustExchange::BITMEX,
+    RustExchange::OKX,
+    RustExchange::HUOBI,
+    RustExchange::HUOBIP,
+    RustExchange::HUOBIM,
+    RustExchange::HUOBIF,
+    RustExchange::HUOBISWAP,
+    RustExchange::BITGETS,
+    RustExchange::BITFINEX,
+    RustExchange::BITHUMB,
+    RustExchange::BINANCE,
+    RustExchange::BINANCEF,
+    RustExchange::BINANCES,
+    RustExchange::COINBASE,
+    RustExchange::BYBIT,
+    RustExchange::BYBITSPOT,
+    RustExchange::KRAKEN,
+    RustExchange::DERIBIT,
+    RustExchange::GATEIO,
+    RustExchange::BITSTAMP,
+    RustExchange::BINGXS,
+    RustExchange::ORANGEX,
+    RustExchange::KUCOIN,
+    RustExchange::DYDX,
+    RustExchange::HYPE,
+    RustExchange::HYPESPOT,
+    RustExchange::LOCAL,
+];
+
+/// register_exchange 注册的自定义交易所，内置枚举中没有的交易所以名称、代码和分类表示
+#[pyclass(frozen, eq, hash, module = "rust_bar_generator")]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CustomExchange {
+    name: String,
+    value: String,
+    category: ExchangeCategory,
+}
+
+/// 已注册的自定义交易所，按名称或代码查找
+static EXCHANGE_REGISTRY: RwLock<Vec<Arc<CustomExchange>>> = RwLock::new(Vec::new());
+
+impl CustomExchange {
+    /// 注册交易所并返回注册表中的实例；同名且代码、分类一致时返回已有实例
+    fn register(name: &str, value: Option<&str>, category: &str) -> PyResult<Arc<CustomExchange>> {
+        let name = name.to_uppercase();
+        let value = value.map_or_else(|| name.clone(), str::to_uppercase);
+        let category = ExchangeCategory::parse(category)?;
+        for code in [&name, &value] {
+            if let Ok(exchange) = RustExchange::parse_builtin(code) {
+                return Err(PyValueError::new_err(format!("{} 与内置交易所 {:?} 重复", code, exchange)));
+            }
+        }
+        let mut registry = write_lock(&EXCHANGE_REGISTRY, "交易所注册表");
+        if let Some(custom) = registry.iter().find(|custom| custom.name == name || custom.value == value) {
+            if custom.name != name || custom.value != value || custom.category != category {
+                return Err(PyValueError::new_err(format!(
+                    "交易所 {} 已注册为 {}（{}），与 {}（{}）冲突", custom.name, custom.value, custom.category.as_str(), value, category.as_str()
+                )));
+            }
+            return Ok(custom.clone());
+        }
+        let custom = Arc::new(CustomExchange { name, value, category });
+        registry.push(custom.clone());
+        Ok(custom)
+    }
+
+    /// 按名称或代码查找已注册的交易所
+    fn lookup(code: &str) -> Option<Arc<CustomExchange>> {
+        let code = code.to_uppercase();
+        read_lock(&EXCHANGE_REGISTRY, "交易所注册表")
+            .iter()
+            .find(|custom| custom.name == code || custom.value == code)
+            .cloned()
+    }
+}
+
+#[pymethods]
+impl CustomExchange {
+    /// 等同于 register_exchange，pickle 反序列化时经此重新注册，不依赖子进程预先注册
+    #[new]
+    #[pyo3(signature = (name, value=None, category="crypto"))]
+    fn py_new(name: &str, value: Option<&str>, category: &str) -> PyResult<Self> {
+        Ok(CustomExchange::clone(&*CustomExchange::register(name, value, category)?))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("CustomExchange('{}', '{}', '{}')", self.name, self.value, self.category.as_str())
+    }
+
+    fn __str__(&self) -> &str {
+        &self.value
+    }
+
+    fn __reduce__<'py>(&self, py: Python<'py>) -> (Bound<'py, PyType>, (&str, &str, &'static str)) {
+        (py.get_type::<CustomExchange>(), (&self.name, &self.value, self.category.as_str()))
+    }
+
+    /// 注册的交易所代码，用于 vt_symbol
+    #[getter]
+    fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// 注册的交易所名称
+    #[getter]
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 与 RustExchange.canonical_name() 对应，返回注册的名称
+    fn canonical_name(&self) -> &str {
+        &self.name
+    }
+
+    fn category(&self) -> &'static str {
+        self.category.as_str()
+    }
+
+    fn is_chinese(&self) -> bool {
+        self.category == ExchangeCategory::Chinese
+    }
+
+    fn is_crypto(&self) -> bool {
+        self.category == ExchangeCategory::Crypto
+    }
+}
+
+/// 运行时注册内置枚举中没有的交易所，返回 CustomExchange；此后 name 和 value 都能被解析为该交易所，
+/// vt_symbol 使用 value。同名重复注册时返回相等的实例，代码或分类不同时抛出 ValueError。
+/// 注册只在当前进程有效，pickle 保存名称、代码和分类，反序列化时自动注册
+#[pyfunction]
+#[pyo3(signature = (name, value=None, category="crypto"))]
+fn register_exchange(name: &str, value: Option<&str>, category: &str) -> PyResult<CustomExchange> {
+    CustomExchange::py_new(name, value, category)
+}
+
+/// 行情数据和配置中的交易所：内置枚举或自定义交易所
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AnyExchange {
+    Builtin(RustExchange),
+    Custom(Arc<CustomExchange>),
+}
+
+impl AnyExchange {
+    /// 交易所代码，用于 vt_symbol
+    fn value(&self) -> &str {
+        match self {
+            AnyExchange::Builtin(exchange) => exchange.value(),
+            AnyExchange::Custom(custom) => &custom.value,
+        }
+    }
+
+    fn canonical_name(&self) -> String {
+        match self {
+            AnyExchange::Builtin(exchange) => exchange.canonical_name(),
+            AnyExchange::Custom(custom) => custom.name.clone(),
+        }
+    }
+
+    fn is_crypto(&self) -> bool {
+        match self {
+            AnyExchange::Builtin(exchange) => exchange.is_crypto(),
+            AnyExchange::Custom(custom) => custom.is_crypto(),
+        }
+    }
+
+    /// 内置交易所，自定义交易所返回 None
+    fn builtin(&self) -> Option<RustExchange> {
+        match self {
+            AnyExchange::Builtin(exchange) => Some(*exchange),
+            AnyExchange::Custom(_) => None,
+        }
+    }
+
+    /// 可传入 RustExchange、CustomExchange、vnpy Exchange 或交易所名称/代码字符串
+    fn from_py_any(obj: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(re) = obj.extract::<RustExchange>() {
+            Ok(AnyExchange::Builtin(re))
+        } else if let Ok(custom) = obj.cast::<CustomExchange>() {
+            let custom = custom.get();
+            Ok(AnyExchange::Custom(CustomExchange::register(&custom.name, Some(&custom.value), custom.category.as_str())?))
+        } else if let Ok(s) = obj.extract::<String>() {
+            Self::parse_string(&s)
+        } else if let Ok(name_attr) = obj.getattr("name") {
+            let s = name_attr.extract::<String>()?;
+            Self::parse_string(&s)
+        } else if let Ok(value_attr) = obj.getattr("value") {
+            let s = value_attr.extract::<String>()?;
+            Self::parse_string(&s)
+        } else if let Ok(str_method) = obj.getattr("__str__") {
+            let result = str_method.call0()?;
+            let s = result.extract::<String>()?;
+            Self::parse_string(&s)
+        } else {
+            Err(PyValueError::new_err("无法转换为 RustExchange"))
+        }
+    }
+
+    /// 解析交易所名称或代码，内置枚举中没有时查找 register_exchange 注册的交易所
+    fn parse_string(s: &str) -> PyResult<Self> {
+        match RustExchange::parse_builtin(s) {
+            Ok(exchange) => Ok(AnyExchange::Builtin(exchange)),
+            Err(err) => CustomExchange::lookup(s).map(AnyExchange::Custom).ok_or(err),
+        }
+    }
+}
+
+impl<'py> IntoPyObject<'py> for &AnyExchange {
+    type Target = PyAny;
+    type Output = Bound<'py, PyAny>;
+    type Error = PyErr;
+
+    fn into_pyobject(self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        match self {
+            AnyExchange::Builtin(exchange) => Ok(Bound::new(py, *exchange)?.into_any()),
+            AnyExchange::Custom(custom) => Ok(Bound::new(py, CustomExchange::clone(custom))?.into_any()),
+        }
+    }
+}
+
+impl<'py> IntoPyObject<'py> for AnyExchange {
+    type Target = PyAny;
+    type Output = Bound<'py, PyAny>;
+    type Error = PyErr;
+
+    fn into_pyobject(self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        (&self).into_pyobject(py)
+    }
+}
+
 #[pymethods]
 impl RustExchange {
     fn __repr__(&self) -> String {
-        format!("RustExchange.{:?}", self)
+        format!("RustExchange.{}", self.canonical_name())
+    }
+
+    /// 按枚举名或交易所代码解析，包括 register_exchange 注册的自定义交易所（返回 CustomExchange），无法识别时抛出 ValueError
+    #[staticmethod]
+    fn parse(exchange: &Bound<'_, PyAny>) -> PyResult<AnyExchange> {
+        AnyExchange::from_py_any(exchange)
+    }
+
+    /// 按交易所代码 pickle，反序列化时重新解析
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<(Bound<'py, PyAny>, (&'static str,))> {
+        Ok((py.get_type::<RustExchange>().getattr("parse")?, (self.value(),)))
     }
     fn __str__(&self) -> &str {
         self.value()
     }
+    /// 交易所代码，与 vnpy Exchange 的 value 一致，用于 vt_symbol 和序列化；
+    /// 少数交易所与枚举名不同：CBOT -> "CBT"，EUREX -> "EUX"，OTC -> "PINK"
     #[getter]
     fn value(&self) -> &'static str {
         match self {
@@ -339,30 +670,62 @@ impl RustExchange {
             RustExchange::LOCAL => "LOCAL",
         }
     }
+
+    /// 枚举名（如 "CBOT"），与交易所代码 value（如 "CBT"）不同；parse 两者都能识别
+    fn canonical_name(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    /// 交易所分类：chinese、global_stock、global_future、crypto、other
+    fn category(&self) -> &'static str {
+        self.exchange_category().as_str()
+    }
+
+    /// 是否为国内交易所（期货、证券、黄金、外汇交易中心等）
+    fn is_chinese(&self) -> bool {
+        self.exchange_category() == ExchangeCategory::Chinese
+    }
+
+    /// 是否为数字货币交易所
+    fn is_crypto(&self) -> bool {
+        self.exchange_category() == ExchangeCategory::Crypto
+    }
+
+    /// 全部交易所，按枚举定义顺序，之后为已注册的自定义交易所
+    #[staticmethod]
+    fn all() -> Vec<AnyExchange> {
+        let registered = read_lock(&EXCHANGE_REGISTRY, "交易所注册表").clone();
+        ALL_EXCHANGES.iter().copied().map(AnyExchange::Builtin).chain(registered.into_iter().map(AnyExchange::Custom)).collect()
+    }
 }
 
 impl RustExchange {
-    fn from_py_any(obj: &Bound<'_, PyAny>) -> PyResult<Self> {
-        if let Ok(re) = obj.extract::<RustExchange>() {
-            Ok(re)
-        } else if let Ok(s) = obj.extract::<String>() {
-            Self::parse_string(&s)
-        } else if let Ok(name_attr) = obj.getattr("name") {
-            let s = name_attr.extract::<String>()?;
-            Self::parse_string(&s)
-        } else if let Ok(value_attr) = obj.getattr("value") {
-            let s = value_attr.extract::<String>()?;
-            Self::parse_string(&s)
-        } else if let Ok(str_method) = obj.getattr("__str__") {
-            let result = str_method.call0()?;
-            let s = result.extract::<String>()?;
-            Self::parse_string(&s)
-        } else {
-            Err(PyValueError::new_err("无法转换为 RustExchange"))
+    /// 交易所分类，穷举全部枚举值（不使用通配分支），新增交易所时编译器会要求在此归类
+    fn exchange_category(&self) -> ExchangeCategory {
+        match self {
+            RustExchange::CFFEX | RustExchange::SHFE | RustExchange::CZCE | RustExchange::DCE |
+            RustExchange::GFEX | RustExchange::INE | RustExchange::SSE | RustExchange::SZSE |
+            RustExchange::BSE | RustExchange::SGE | RustExchange::WXE | RustExchange::CFETS => ExchangeCategory::Chinese,
+            RustExchange::SMART | RustExchange::NYSE | RustExchange::NASDAQ | RustExchange::ARCA |
+            RustExchange::EDGEA | RustExchange::ISLAND | RustExchange::BATS | RustExchange::IEX |
+            RustExchange::SEHK | RustExchange::HKSE | RustExchange::EUNX | RustExchange::KRX |
+            RustExchange::OTC | RustExchange::IBKRATS | RustExchange::TSE | RustExchange::AMEX => ExchangeCategory::GlobalStock,
+            RustExchange::NYMEX | RustExchange::COMEX | RustExchange::GLOBEX | RustExchange::CME |
+            RustExchange::ICE | RustExchange::HKFE | RustExchange::SGX | RustExchange::CBOT |
+            RustExchange::CBOE | RustExchange::CFE | RustExchange::DME | RustExchange::EUREX |
+            RustExchange::APEX | RustExchange::LME | RustExchange::BMD | RustExchange::TOCOM => ExchangeCategory::GlobalFuture,
+            RustExchange::BITMEX | RustExchange::OKX | RustExchange::HUOBI | RustExchange::HUOBIP |
+            RustExchange::HUOBIM | RustExchange::HUOBIF | RustExchange::HUOBISWAP | RustExchange::BITGETS |
+            RustExchange::BITFINEX | RustExchange::BITHUMB | RustExchange::BINANCE |
+            RustExchange::BINANCEF | RustExchange::BINANCES | RustExchange::COINBASE |
+            RustExchange::BYBIT | RustExchange::BYBITSPOT | RustExchange::KRAKEN | RustExchange::DERIBIT |
+            RustExchange::GATEIO | RustExchange::BITSTAMP | RustExchange::BINGXS | RustExchange::ORANGEX |
+            RustExchange::KUCOIN | RustExchange::DYDX | RustExchange::HYPE | RustExchange::HYPESPOT => ExchangeCategory::Crypto,
+            RustExchange::IDEALPRO | RustExchange::LOCAL => ExchangeCategory::Other,
         }
     }
 
-    fn parse_string(s: &str) -> PyResult<Self> {
+    fn parse_builtin(s: &str) -> PyResult<Self> {
         match s.to_uppercase().as_str() {
             // Chinese
             "CFFEX" => Ok(RustExchange::CFFEX),
@@ -444,18 +807,25 @@ impl RustExchange {
     }
 }
 
+/// 与 vnpy 一致的 vt_symbol：{symbol}_{exchange.value}/{gateway_name}
+fn make_vt_symbol(symbol: &str, exchange: &AnyExchange, gateway_name: &str) -> String {
+    format!("{}_{}/{}", symbol, exchange.value(), gateway_name)
+}
+
 // ================================================================================================
 // RustBarData - K线数据结构
 // ================================================================================================
 #[pyclass(module = "rust_bar_generator")]
 #[derive(Debug)]
 pub struct RustBarData {
-    #[pyo3(get, set)]
+    #[pyo3(get)]
     pub symbol: String,
-    #[pyo3(get, set)]
-    pub exchange: RustExchange,
-    #[pyo3(get, set)]
+    #[pyo3(get)]
+    pub exchange: AnyExchange,
+    /// Python 侧的时间；为空而 rust_datetime 有值时，读取 datetime 属性按需生成带固定偏移的 datetime
     pub datetime: Option<Py<PyAny>>,
+    /// 批量接口在 Rust 中构造的时间，避免逐个创建 Python datetime；datetime 有值时以 datetime 为准
+    pub rust_datetime: Option<DateTime<chrono_tz::Tz>>,
     #[pyo3(get, set)]
     pub interval: Option<RustInterval>,
     #[pyo3(get, set)]
@@ -470,30 +840,42 @@ pub struct RustBarData {
     pub low_price: f64,
     #[pyo3(get, set)]
     pub close_price: f64,
+    /// 主动买入成交量（按 tick rule 分类）
     #[pyo3(get, set)]
-    pub gateway_name: String,
+    pub buy_volume: f64,
+    /// 主动卖出成交量（按 tick rule 分类）
+    #[pyo3(get, set)]
+    pub sell_volume: f64,
+    /// 合成该K线的 Tick 数量，窗口K线为各分钟K线之和；由K线合成且没有该字段时为 0
+    #[pyo3(get, set)]
+    pub trade_count: u64,
+    /// 并入该窗口K线的基础K线数量，非窗口K线为 0
     #[pyo3(get, set)]
+    pub sub_bar_count: u64,
+    /// 完整窗口应包含的基础K线数量，由窗口周期、window 和基础K线周期推算，无法推算时为 0
+    #[pyo3(get, set)]
+    pub expected_sub_bars: u64,
+    #[pyo3(get)]
+    pub gateway_name: String,
+    /// 由 symbol、exchange、gateway_name 生成，修改这三个字段时自动更新
+    #[pyo3(get)]
     pub vt_symbol: String,
+    /// 是否为强制合成的K线（定时强制合成或 flush 推送的未走完K线）
+    #[pyo3(get, set)]
+    pub is_forced: bool,
+    /// 是否为未走完就被推送的K线（flush/finalize 推送或重采样返回的最后一根）
+    #[pyo3(get, set)]
+    pub is_partial: bool,
+    /// 合成该K线的 Tick 中是否有价格触及涨停价/跌停价，窗口K线取各分钟K线的“或”
+    #[pyo3(get, set)]
+    pub touched_limit_up: bool,
+    #[pyo3(get, set)]
+    pub touched_limit_down: bool,
 }
 
 impl Clone for RustBarData {
     fn clone(&self) -> Self {
-        Python::attach(|py| {
-            RustBarData {
-                symbol: self.symbol.clone(),
-                exchange: self.exchange,
-                datetime: self.datetime.as_ref().map(|dt| dt.clone_ref(py)),
-                interval: self.interval,
-                volume: self.volume,
-                open_interest: self.open_interest,
-                open_price: self.open_price,
-                high_price: self.high_price,
-                low_price: self.low_price,
-                close_price: self.close_price,
-                gateway_name: self.gateway_name.clone(),
-                vt_symbol: self.vt_symbol.clone(),
-            }
-        })
+        Python::attach(|py| self.clone_with_py(py))
     }
 }
 
@@ -501,8 +883,9 @@ impl RustBarData {
     fn clone_with_py(&self, py: Python) -> Self {
         RustBarData {
             symbol: self.symbol.clone(),
-            exchange: self.exchange,
+            exchange: self.exchange.clone(),
             datetime: self.datetime.as_ref().map(|dt| dt.clone_ref(py)),
+            rust_datetime: self.rust_datetime,
             interval: self.interval,
             volume: self.volume,
             open_interest: self.open_interest,
@@ -510,26 +893,37 @@ impl RustBarData {
             high_price: self.high_price,
             low_price: self.low_price,
             close_price: self.close_price,
+            buy_volume: self.buy_volume,
+            sell_volume: self.sell_volume,
+            trade_count: self.trade_count,
+            sub_bar_count: self.sub_bar_count,
+            expected_sub_bars: self.expected_sub_bars,
             gateway_name: self.gateway_name.clone(),
             vt_symbol: self.vt_symbol.clone(),
+            is_forced: self.is_forced,
+            is_partial: self.is_partial,
+            touched_limit_up: self.touched_limit_up,
+            touched_limit_down: self.touched_limit_down,
         }
     }
 
     fn get_datetime_chrono(&self, py: Python) -> PyResult<Option<DateTime<chrono_tz::Tz>>> {
-        if let Some(ref dt_obj) = self.datetime {
-            let dt_bound = dt_obj.bind(py);
-            let ts_method = dt_bound.call_method0("timestamp")?;
-            let ts_seconds = ts_method.extract::<f64>()?;
-            let ts_millis = (ts_seconds * 1000.0) as i64;
-            
-            Ok(DateTime::from_timestamp_millis(ts_millis)
-                .map(|dt| dt.with_timezone(&*TZ_INFO)))
-        } else {
-            Ok(None)
+        match self.datetime {
+            Some(ref dt) => py_datetime_to_chrono(dt.bind(py)).map(Some),
+            None => Ok(self.rust_datetime),
+        }
+    }
+
+    /// Python 侧的时间，只有 rust_datetime 时生成带固定偏移 tzinfo 的 datetime
+    fn py_datetime(&self, py: Python) -> PyResult<Option<Py<PyAny>>> {
+        match (&self.datetime, &self.rust_datetime) {
+            (Some(dt), _) => Ok(Some(dt.clone_ref(py))),
+            (None, Some(dt)) => to_py_datetime_fixed(py, dt, &mut None).map(Some),
+            (None, None) => Ok(None),
         }
     }
 
-    fn from_py_bar(_py: Python, py_bar: &Bound<'_, PyAny>) -> PyResult<Self> {
+    fn from_py_bar(py_bar: &Bound<'_, PyAny>) -> PyResult<Self> {
         if let Ok(rust_bar) = py_bar.extract::<RustBarData>() {
             return Ok(rust_bar);
         }
@@ -538,12 +932,14 @@ impl RustBarData {
         let gateway_name = py_bar.getattr("gateway_name")?.extract::<String>()?;
         
         let exchange_obj = py_bar.getattr("exchange")?;
-        let exchange = RustExchange::from_py_any(&exchange_obj)?;
+        let exchange = AnyExchange::from_py_any(&exchange_obj)?;
 
-        let datetime = if let Ok(dt_attr) = py_bar.getattr("datetime") {
-            Some(dt_attr.unbind())
-        } else {
-            None
+        // 部分数据源的 datetime 是时间戳或时间字符串（字符串按 UTC 解析），读入时转换为带 +08:00 偏移的上海时间，
+        // 不生成无时区的 datetime，避免读回时按主机本地时区解释
+        let (datetime, rust_datetime) = match py_bar.getattr("datetime") {
+            Ok(dt_attr) if dt_attr.is_none() || dt_attr.is_instance_of::<PyDateTime>() => (Some(dt_attr.unbind()), None),
+            Ok(dt_attr) => (None, Some(TZ_INFO.from_utc_datetime(&parse_any_timestamp(&dt_attr)?))),
+            Err(_) => (None, None),
         };
 
         let interval = if let Ok(interval_obj) = py_bar.getattr("interval") {
@@ -558,13 +954,24 @@ impl RustBarData {
         let high_price = py_bar.getattr("high_price")?.extract::<f64>().unwrap_or(0.0);
         let low_price = py_bar.getattr("low_price")?.extract::<f64>().unwrap_or(0.0);
         let close_price = py_bar.getattr("close_price")?.extract::<f64>().unwrap_or(0.0);
+        // vnpy 原生 BarData 没有主动买卖量字段
+        let buy_volume = py_bar.getattr("buy_volume").and_then(|v| v.extract::<f64>()).unwrap_or(0.0);
+        let sell_volume = py_bar.getattr("sell_volume").and_then(|v| v.extract::<f64>()).unwrap_or(0.0);
+        let trade_count = py_bar.getattr("trade_count").and_then(|v| v.extract::<u64>()).unwrap_or(0);
+        let sub_bar_count = py_bar.getattr("sub_bar_count").and_then(|v| v.extract::<u64>()).unwrap_or(0);
+        let expected_sub_bars = py_bar.getattr("expected_sub_bars").and_then(|v| v.extract::<u64>()).unwrap_or(0);
+        let is_forced = py_bar.getattr("is_forced").and_then(|v| v.extract::<bool>()).unwrap_or(false);
+        let is_partial = py_bar.getattr("is_partial").and_then(|v| v.extract::<bool>()).unwrap_or(false);
+        let touched_limit_up = py_bar.getattr("touched_limit_up").and_then(|v| v.extract::<bool>()).unwrap_or(false);
+        let touched_limit_down = py_bar.getattr("touched_limit_down").and_then(|v| v.extract::<bool>()).unwrap_or(false);
 
-        let vt_symbol = format!("{}_{}/{}", symbol, exchange.__str__(), gateway_name);
+        let vt_symbol = make_vt_symbol(&symbol, &exchange, &gateway_name);
 
         Ok(RustBarData {
             symbol,
             exchange,
             datetime,
+            rust_datetime,
             interval,
             volume,
             open_interest,
@@ -572,8 +979,17 @@ impl RustBarData {
             high_price,
             low_price,
             close_price,
+            buy_volume,
+            sell_volume,
+            trade_count,
+            sub_bar_count,
+            expected_sub_bars,
             gateway_name,
             vt_symbol,
+            is_forced,
+            is_partial,
+            touched_limit_up,
+            touched_limit_down,
         })
     }
 }
@@ -581,7 +997,8 @@ impl RustBarData {
 #[pymethods]
 impl RustBarData {
     #[new]
-    #[pyo3(signature = (symbol, exchange, gateway_name, datetime=None, interval=None, volume=0.0, open_interest=0.0, open_price=0.0, high_price=0.0, low_price=0.0, close_price=0.0))]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (symbol, exchange, gateway_name, datetime=None, interval=None, volume=0.0, open_interest=0.0, open_price=0.0, high_price=0.0, low_price=0.0, close_price=0.0, buy_volume=0.0, sell_volume=0.0, is_forced=false, is_partial=false, touched_limit_up=false, touched_limit_down=false, trade_count=0, sub_bar_count=0, expected_sub_bars=0))]
     fn new(
         _py: Python,
         symbol: String,
@@ -595,8 +1012,17 @@ impl RustBarData {
         high_price: f64,
         low_price: f64,
         close_price: f64,
+        buy_volume: f64,
+        sell_volume: f64,
+        is_forced: bool,
+        is_partial: bool,
+        touched_limit_up: bool,
+        touched_limit_down: bool,
+        trade_count: u64,
+        sub_bar_count: u64,
+        expected_sub_bars: u64,
     ) -> PyResult<Self> {
-        let rust_exchange = RustExchange::from_py_any(exchange)?;
+        let rust_exchange = AnyExchange::from_py_any(exchange)?;
         let rust_interval = if let Some(iv) = interval {
             Some(RustInterval::from_py_any(iv)?)
         } else {
@@ -605,12 +1031,13 @@ impl RustBarData {
 
         let py_datetime = datetime.map(|dt| dt.clone().unbind());
 
-        let vt_symbol = format!("{}_{}/{}", symbol, rust_exchange.__str__(), gateway_name);
+        let vt_symbol = make_vt_symbol(&symbol, &rust_exchange, &gateway_name);
         
         Ok(RustBarData {
             symbol,
             exchange: rust_exchange,
             datetime: py_datetime,
+            rust_datetime: None,
             interval: rust_interval,
             volume,
             open_interest,
@@ -618,15 +1045,54 @@ impl RustBarData {
             high_price,
             low_price,
             close_price,
+            buy_volume,
+            sell_volume,
+            trade_count,
+            sub_bar_count,
+            expected_sub_bars,
             gateway_name,
             vt_symbol,
+            is_forced,
+            is_partial,
+            touched_limit_up,
+            touched_limit_down,
         })
     }
 
+    #[getter(datetime)]
+    fn get_datetime(&self, py: Python) -> PyResult<Option<Py<PyAny>>> {
+        self.py_datetime(py)
+    }
+
+    #[setter]
+    fn set_datetime(&mut self, datetime: &Bound<'_, PyAny>) {
+        self.datetime = (!datetime.is_none()).then(|| datetime.clone().unbind());
+        self.rust_datetime = None;
+    }
+
+    #[setter]
+    fn set_symbol(&mut self, symbol: String) {
+        self.symbol = symbol;
+        self.vt_symbol = make_vt_symbol(&self.symbol, &self.exchange, &self.gateway_name);
+    }
+
+    /// 可传入 RustExchange、CustomExchange、vnpy Exchange 或交易所代码字符串
+    #[setter]
+    fn set_exchange(&mut self, exchange: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.exchange = AnyExchange::from_py_any(exchange)?;
+        self.vt_symbol = make_vt_symbol(&self.symbol, &self.exchange, &self.gateway_name);
+        Ok(())
+    }
+
+    #[setter]
+    fn set_gateway_name(&mut self, gateway_name: String) {
+        self.gateway_name = gateway_name;
+        self.vt_symbol = make_vt_symbol(&self.symbol, &self.exchange, &self.gateway_name);
+    }
+
     fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<(Py<PyAny>, Py<PyAny>)> {
         let cls = PyModule::import(py, "rust_bar_generator")?.getattr("RustBarData")?;
         
-        let exchange_str = self.exchange.__str__();
         let interval_str: Option<&str> = self.interval.map(|i| match i {
             RustInterval::TICK => "TICK",
             RustInterval::MINUTE => "MINUTE",
@@ -636,11 +1102,11 @@ impl RustBarData {
             RustInterval::MONTHLY => "MONTHLY",
         });
         
-        let dt_for_pickle = self.datetime.as_ref().map(|dt| dt.clone_ref(py));
+        let dt_for_pickle = self.py_datetime(py)?;
         
         let args = PyTuple::new(py, &[
             self.symbol.clone().into_pyobject(py)?.into_any().unbind(),
-            exchange_str.into_pyobject(py)?.into_any().unbind(),
+            (&self.exchange).into_pyobject(py)?.unbind(),
             self.gateway_name.clone().into_pyobject(py)?.into_any().unbind(),
             dt_for_pickle.into_pyobject(py)?.into_any().unbind(),
             interval_str.into_pyobject(py)?.into_any().unbind(),
@@ -650,16 +1116,104 @@ impl RustBarData {
             self.high_price.into_pyobject(py)?.into_any().unbind(),
             self.low_price.into_pyobject(py)?.into_any().unbind(),
             self.close_price.into_pyobject(py)?.into_any().unbind(),
+            self.buy_volume.into_pyobject(py)?.into_any().unbind(),
+            self.sell_volume.into_pyobject(py)?.into_any().unbind(),
+            self.is_forced.into_pyobject(py)?.to_owned().into_any().unbind(),
+            self.is_partial.into_pyobject(py)?.to_owned().into_any().unbind(),
+            self.touched_limit_up.into_pyobject(py)?.to_owned().into_any().unbind(),
+            self.touched_limit_down.into_pyobject(py)?.to_owned().into_any().unbind(),
+            self.trade_count.into_pyobject(py)?.into_any().unbind(),
+            self.sub_bar_count.into_pyobject(py)?.into_any().unbind(),
+            self.expected_sub_bars.into_pyobject(py)?.into_any().unbind(),
         ])?;
         
         Ok((cls.unbind(), args.unbind().into()))
     }
 
-    fn __repr__(&self) -> String {
-        format!(
-            "RustBarData(symbol='{}', exchange={:?}, datetime={:?}, interval={:?})",
-            self.symbol, self.exchange, self.datetime, self.interval
-        )
+    /// 主动买卖量差值
+    #[getter]
+    fn imbalance(&self) -> f64 {
+        self.buy_volume - self.sell_volume
+    }
+
+    /// 窗口K线的完整度：sub_bar_count / expected_sub_bars，expected_sub_bars 未知时返回 None
+    fn completeness(&self) -> Option<f64> {
+        (self.expected_sub_bars > 0).then(|| self.sub_bar_count as f64 / self.expected_sub_bars as f64)
+    }
+
+    /// 合并同一合约、同一时间的两根K线（如拼接两个数据源的历史数据），返回新的K线：
+    /// 开盘价取本K线（为 0 时取 other），最高/最低价取两者的最大/最小值（最低价为 0 视为未设置），成交量等计数相加，
+    /// other 视为较新的数据，收盘价和持仓量取 other；时间或合约不同时抛出 ValueError
+    fn merge(&self, py: Python, other: PyRef<'_, RustBarData>) -> PyResult<RustBarData> {
+        if self.symbol != other.symbol || self.exchange != other.exchange {
+            return Err(PyValueError::new_err(format!(
+                "只能合并同一合约的K线: {} 与 {}", self.vt_symbol, other.vt_symbol
+            )));
+        }
+        let (Some(dt), Some(other_dt)) = (self.py_datetime(py)?, other.py_datetime(py)?) else {
+            return Err(PyValueError::new_err("Bar缺少datetime"));
+        };
+        if !dt.bind(py).eq(other_dt.bind(py))? {
+            return Err(PyValueError::new_err(format!(
+                "只能合并同一时间的K线: {} 与 {}", dt.bind(py), other_dt.bind(py)
+            )));
+        }
+
+        let mut merged = self.clone_with_py(py);
+        if merged.open_price == 0.0 {
+            merged.open_price = other.open_price;
+        }
+        merged.high_price = merged.high_price.max(other.high_price);
+        if merged.low_price == 0.0 {
+            merged.low_price = other.low_price;
+        } else if other.low_price != 0.0 {
+            merged.low_price = merged.low_price.min(other.low_price);
+        }
+        merged.close_price = other.close_price;
+        merged.open_interest = other.open_interest;
+        merged.volume += other.volume;
+        merged.buy_volume += other.buy_volume;
+        merged.sell_volume += other.sell_volume;
+        merged.trade_count += other.trade_count;
+        merged.sub_bar_count += other.sub_bar_count;
+        merged.expected_sub_bars = merged.expected_sub_bars.max(other.expected_sub_bars);
+        merged.is_forced |= other.is_forced;
+        merged.is_partial |= other.is_partial;
+        merged.touched_limit_up |= other.touched_limit_up;
+        merged.touched_limit_down |= other.touched_limit_down;
+        Ok(merged)
+    }
+
+    /// 仅在开盘价未设置（为 0）时设置开盘价，返回是否设置成功；直接给 open_price 赋值会绕过这一保护
+    fn latch_open(&mut self, price: f64) -> bool {
+        if self.open_price != 0.0 {
+            return false;
+        }
+        self.open_price = price;
+        true
+    }
+
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        Ok(format!(
+            "RustBarData(symbol='{}', exchange={}, datetime={:?}, interval={:?})",
+            self.symbol, self.exchange.canonical_name(), self.py_datetime(py)?, self.interval
+        ))
+    }
+
+    /// 空格式为 "SYMBOL O/H/L/C V"，否则按 strftime 格式化时间后接同样的摘要，
+    /// 例如 f"{bar:%Y-%m-%d %H:%M}" 为 "2024-01-02 09:00 rb2405 3500/3510/3490/3505 120"
+    fn __format__(&self, py: Python, spec: &str) -> PyResult<String> {
+        let summary = format!(
+            "{} {}/{}/{}/{} {}",
+            self.symbol, self.open_price, self.high_price, self.low_price, self.close_price, self.volume
+        );
+        match self.py_datetime(py)? {
+            Some(dt) if !spec.is_empty() => {
+                let time = dt.bind(py).call_method1("strftime", (spec,))?.extract::<String>()?;
+                Ok(format!("{} {}", time, summary))
+            }
+            _ => Ok(summary),
+        }
     }
 }
 
@@ -669,12 +1223,14 @@ impl RustBarData {
 #[pyclass(module = "rust_bar_generator")]
 #[derive(Debug)]
 pub struct RustTickData {
-    #[pyo3(get, set)]
+    #[pyo3(get)]
     pub symbol: String,
-    #[pyo3(get, set)]
-    pub exchange: RustExchange,
-    #[pyo3(get, set)]
+    #[pyo3(get)]
+    pub exchange: AnyExchange,
+    /// Python 侧的时间；为空而 rust_datetime 有值时，读取 datetime 属性按需生成带固定偏移的 datetime
     pub datetime: Option<Py<PyAny>>,
+    /// 批量接口在 Rust 中构造的时间，避免逐个创建 Python datetime；datetime 有值时以 datetime 为准
+    pub rust_datetime: Option<DateTime<chrono_tz::Tz>>,
     #[pyo3(get, set)]
     pub name: String,
     #[pyo3(get, set)]
@@ -737,9 +1293,10 @@ pub struct RustTickData {
     pub ask_volume_4: f64,
     #[pyo3(get, set)]
     pub ask_volume_5: f64,
-    #[pyo3(get, set)]
+    #[pyo3(get)]
     pub gateway_name: String,
-    #[pyo3(get, set)]
+    /// 由 symbol、exchange、gateway_name 生成，修改这三个字段时自动更新
+    #[pyo3(get)]
     pub vt_symbol: String,
 }
 
@@ -750,11 +1307,76 @@ impl Clone for RustTickData {
 }
 
 impl RustTickData {
+    /// 最新价是否触及涨停价（涨停价为 0 表示未知）
+    fn touched_limit_up(&self) -> bool {
+        self.limit_up != 0.0 && self.last_price >= self.limit_up
+    }
+
+    /// 最新价是否触及跌停价（跌停价为 0 表示未知）
+    fn touched_limit_down(&self) -> bool {
+        self.limit_down != 0.0 && self.last_price <= self.limit_down
+    }
+
+    /// 五档盘口：买价、买量、卖价、卖量，各按档位排列
+    fn book_levels(&self) -> [[f64; 5]; 4] {
+        [
+            [self.bid_price_1, self.bid_price_2, self.bid_price_3, self.bid_price_4, self.bid_price_5],
+            [self.bid_volume_1, self.bid_volume_2, self.bid_volume_3, self.bid_volume_4, self.bid_volume_5],
+            [self.ask_price_1, self.ask_price_2, self.ask_price_3, self.ask_price_4, self.ask_price_5],
+            [self.ask_volume_1, self.ask_volume_2, self.ask_volume_3, self.ask_volume_4, self.ask_volume_5],
+        ]
+    }
+
+    /// 只有合约信息和时间、其余字段为 0 的 Tick
+    fn empty(symbol: String, exchange: AnyExchange, gateway_name: String, datetime: Option<Py<PyAny>>) -> Self {
+        let vt_symbol = make_vt_symbol(&symbol, &exchange, &gateway_name);
+        RustTickData {
+            symbol,
+            exchange,
+            datetime,
+            rust_datetime: None,
+            name: String::new(),
+            volume: 0.0,
+            open_interest: 0.0,
+            last_price: 0.0,
+            last_volume: 0.0,
+            limit_up: 0.0,
+            limit_down: 0.0,
+            open_price: 0.0,
+            high_price: 0.0,
+            low_price: 0.0,
+            pre_close: 0.0,
+            bid_price_1: 0.0,
+            bid_price_2: 0.0,
+            bid_price_3: 0.0,
+            bid_price_4: 0.0,
+            bid_price_5: 0.0,
+            ask_price_1: 0.0,
+            ask_price_2: 0.0,
+            ask_price_3: 0.0,
+            ask_price_4: 0.0,
+            ask_price_5: 0.0,
+            bid_volume_1: 0.0,
+            bid_volume_2: 0.0,
+            bid_volume_3: 0.0,
+            bid_volume_4: 0.0,
+            bid_volume_5: 0.0,
+            ask_volume_1: 0.0,
+            ask_volume_2: 0.0,
+            ask_volume_3: 0.0,
+            ask_volume_4: 0.0,
+            ask_volume_5: 0.0,
+            gateway_name,
+            vt_symbol,
+        }
+    }
+
     fn clone_with_py(&self, py: Python) -> Self {
         RustTickData {
             symbol: self.symbol.clone(),
-            exchange: self.exchange,
+            exchange: self.exchange.clone(),
             datetime: self.datetime.as_ref().map(|dt| dt.clone_ref(py)),
+            rust_datetime: self.rust_datetime,
             name: self.name.clone(),
             volume: self.volume,
             open_interest: self.open_interest,
@@ -792,16 +1414,18 @@ impl RustTickData {
     }
 
     fn get_datetime_chrono(&self, py: Python) -> PyResult<Option<DateTime<chrono_tz::Tz>>> {
-        if let Some(ref dt_obj) = self.datetime {
-            let dt_bound = dt_obj.bind(py);
-            let ts_method = dt_bound.call_method0("timestamp")?;
-            let ts_seconds = ts_method.extract::<f64>()?;
-            let ts_millis = (ts_seconds * 1000.0) as i64;
-            
-            Ok(DateTime::from_timestamp_millis(ts_millis)
-                .map(|dt| dt.with_timezone(&*TZ_INFO)))
-        } else {
-            Ok(None)
+        match self.datetime {
+            Some(ref dt) => py_datetime_to_chrono(dt.bind(py)).map(Some),
+            None => Ok(self.rust_datetime),
+        }
+    }
+
+    /// Python 侧的时间，只有 rust_datetime 时生成带固定偏移 tzinfo 的 datetime
+    fn py_datetime(&self, py: Python) -> PyResult<Option<Py<PyAny>>> {
+        match (&self.datetime, &self.rust_datetime) {
+            (Some(dt), _) => Ok(Some(dt.clone_ref(py))),
+            (None, Some(dt)) => to_py_datetime_fixed(py, dt, &mut None).map(Some),
+            (None, None) => Ok(None),
         }
     }
 
@@ -814,7 +1438,7 @@ impl RustTickData {
         let gateway_name = py_tick.getattr("gateway_name")?.extract::<String>()?;
         
         let exchange_obj = py_tick.getattr("exchange")?;
-        let exchange = RustExchange::from_py_any(&exchange_obj)?;
+        let exchange = AnyExchange::from_py_any(&exchange_obj)?;
 
         let datetime = if let Ok(dt_attr) = py_tick.getattr("datetime") {
             Some(dt_attr.unbind())
@@ -858,12 +1482,13 @@ impl RustTickData {
         let ask_volume_4 = py_tick.getattr("ask_volume_4")?.extract::<f64>().unwrap_or(0.0);
         let ask_volume_5 = py_tick.getattr("ask_volume_5")?.extract::<f64>().unwrap_or(0.0);
 
-        let vt_symbol = format!("{}_{}/{}", symbol, exchange.__str__(), gateway_name);
+        let vt_symbol = make_vt_symbol(&symbol, &exchange, &gateway_name);
 
         Ok(RustTickData {
             symbol,
             exchange,
             datetime,
+            rust_datetime: None,
             name,
             volume,
             open_interest,
@@ -913,49 +1538,10 @@ impl RustTickData {
         datetime: Option<&Bound<'_, PyAny>>,
         kwargs: Option<Bound<'_, PyDict>>,
     ) -> PyResult<Self> {
-        let rust_exchange = RustExchange::from_py_any(exchange)?;
-        let vt_symbol = format!("{}_{}/{}", symbol, rust_exchange.__str__(), gateway_name);
-        
+        let rust_exchange = AnyExchange::from_py_any(exchange)?;
         let py_datetime = datetime.map(|dt| dt.clone().unbind());
         
-        let mut tick = RustTickData {
-            symbol,
-            exchange: rust_exchange,
-            datetime: py_datetime,
-            name: String::new(),
-            volume: 0.0,
-            open_interest: 0.0,
-            last_price: 0.0,
-            last_volume: 0.0,
-            limit_up: 0.0,
-            limit_down: 0.0,
-            open_price: 0.0,
-            high_price: 0.0,
-            low_price: 0.0,
-            pre_close: 0.0,
-            bid_price_1: 0.0,
-            bid_price_2: 0.0,
-            bid_price_3: 0.0,
-            bid_price_4: 0.0,
-            bid_price_5: 0.0,
-            ask_price_1: 0.0,
-            ask_price_2: 0.0,
-            ask_price_3: 0.0,
-            ask_price_4: 0.0,
-            ask_price_5: 0.0,
-            bid_volume_1: 0.0,
-            bid_volume_2: 0.0,
-            bid_volume_3: 0.0,
-            bid_volume_4: 0.0,
-            bid_volume_5: 0.0,
-            ask_volume_1: 0.0,
-            ask_volume_2: 0.0,
-            ask_volume_3: 0.0,
-            ask_volume_4: 0.0,
-            ask_volume_5: 0.0,
-            gateway_name,
-            vt_symbol,
-        };
+        let mut tick = RustTickData::empty(symbol, rust_exchange, gateway_name, py_datetime);
 
         if let Some(kw) = kwargs {
             if let Ok(Some(val)) = kw.get_item("name") {
@@ -1056,16 +1642,46 @@ impl RustTickData {
         Ok(tick)
     }
 
+    #[getter(datetime)]
+    fn get_datetime(&self, py: Python) -> PyResult<Option<Py<PyAny>>> {
+        self.py_datetime(py)
+    }
+
+    #[setter]
+    fn set_datetime(&mut self, datetime: &Bound<'_, PyAny>) {
+        self.datetime = (!datetime.is_none()).then(|| datetime.clone().unbind());
+        self.rust_datetime = None;
+    }
+
+    #[setter]
+    fn set_symbol(&mut self, symbol: String) {
+        self.symbol = symbol;
+        self.vt_symbol = make_vt_symbol(&self.symbol, &self.exchange, &self.gateway_name);
+    }
+
+    /// 可传入 RustExchange、CustomExchange、vnpy Exchange 或交易所代码字符串
+    #[setter]
+    fn set_exchange(&mut self, exchange: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.exchange = AnyExchange::from_py_any(exchange)?;
+        self.vt_symbol = make_vt_symbol(&self.symbol, &self.exchange, &self.gateway_name);
+        Ok(())
+    }
+
+    #[setter]
+    fn set_gateway_name(&mut self, gateway_name: String) {
+        self.gateway_name = gateway_name;
+        self.vt_symbol = make_vt_symbol(&self.symbol, &self.exchange, &self.gateway_name);
+    }
+
     fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<(Py<PyAny>, Py<PyAny>, Py<PyAny>)> {
         let cls = PyModule::import(py, "rust_bar_generator")?.getattr("RustTickData")?;
         
-        let exchange_str = self.exchange.__str__();
         
-        let dt_for_pickle = self.datetime.as_ref().map(|dt| dt.clone_ref(py));
+        let dt_for_pickle = self.py_datetime(py)?;
         
         let args = PyTuple::new(py, &[
             self.symbol.clone().into_pyobject(py)?.into_any().unbind(),
-            exchange_str.into_pyobject(py)?.into_any().unbind(),
+            (&self.exchange).into_pyobject(py)?.unbind(),
             self.gateway_name.clone().into_pyobject(py)?.into_any().unbind(),
             dt_for_pickle.into_pyobject(py)?.into_any().unbind(),
         ])?;
@@ -1106,11 +1722,11 @@ impl RustTickData {
         Ok((cls.unbind(), args.unbind().into(), kwargs.unbind().into()))
     }
 
-    fn __repr__(&self) -> String {
-        format!(
-            "RustTickData(symbol='{}', exchange={:?}, datetime={:?}, last_price={})",
-            self.symbol, self.exchange, self.datetime, self.last_price
-        )
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        Ok(format!(
+            "RustTickData(symbol='{}', exchange={}, datetime={:?}, last_price={})",
+            self.symbol, self.exchange.canonical_name(), self.py_datetime(py)?, self.last_price
+        ))
     }
 }
 
@@ -1160,23 +1776,28 @@ fn parse_numeric_timestamp(timestamp: i64) -> PyResult<NaiveDateTime> {
         .ok_or_else(|| PyValueError::new_err("无效的时间戳"))
 }
 
-#[pyfunction]
-#[pyo3(signature = (timestamp, hours=8))]
-fn get_local_datetime(py: Python, timestamp: Bound<'_, PyAny>, hours: i64) -> PyResult<Py<PyAny>> {
-    let naive_dt = if let Ok(s) = timestamp.extract::<String>() {
+/// 时间戳（数字或数字字符串，按数值大小识别秒/毫秒/微秒/纳秒）或时间字符串转换为 UTC 时间
+fn parse_any_timestamp(timestamp: &Bound<'_, PyAny>) -> PyResult<NaiveDateTime> {
+    if let Ok(s) = timestamp.extract::<String>() {
         if s.chars().all(|c| c.is_ascii_digit()) {
             let ts: i64 = s.parse().map_err(|_| PyValueError::new_err("无效的时间戳字符串"))?;
-            parse_numeric_timestamp(ts)?
+            parse_numeric_timestamp(ts)
         } else {
-            parse_str_timestamp(&s)?
+            parse_str_timestamp(&s)
         }
     } else if let Ok(ts) = timestamp.extract::<i64>() {
-        parse_numeric_timestamp(ts)?
+        parse_numeric_timestamp(ts)
     } else if let Ok(ts) = timestamp.extract::<f64>() {
-        parse_numeric_timestamp((ts * 1000.0) as i64)?
+        parse_numeric_timestamp((ts * 1000.0) as i64)
     } else {
-        return Err(PyValueError::new_err("不支持的时间戳类型"));
-    };
+        Err(PyValueError::new_err("不支持的时间戳类型"))
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (timestamp, hours=8))]
+fn get_local_datetime(py: Python, timestamp: Bound<'_, PyAny>, hours: i64) -> PyResult<Py<PyAny>> {
+    let naive_dt = parse_any_timestamp(&timestamp)?;
 
     let dt = naive_dt + Duration::hours(hours);
     
@@ -1195,535 +1816,5607 @@ fn get_local_datetime(py: Python, timestamp: Bound<'_, PyAny>, hours: i64) -> Py
 }
 
 // ================================================================================================
-// BarGeneratorInner - 内部可变状态
-// ================================================================================================
-struct BarGeneratorInner {
-    bar: Option<RustBarData>,
-    interval_count: usize,
-    reset_count: usize,
-    window_bar: Option<RustBarData>,
-    last_tick: Option<RustTickData>,
-    last_bar: Option<RustBarData>,
-    finished: bool,
-    bar_push_status: HashMap<i64, bool>,
-}
-
-// ================================================================================================
-// BarGenerator - K线生成器核心类（使用 RefCell 实现内部可变性）
+// TimezoneConverter - 时间戳解析与时区转换工具
 // ================================================================================================
 #[pyclass(module = "rust_bar_generator")]
-pub struct BarGenerator {
-    // 使用 RefCell 包装可变状态
-    inner: RwLock<BarGeneratorInner>,
-    // 不可变配置
-    on_bar: Option<Py<PyAny>>,
-    on_window_bar: Option<Py<PyAny>>,
-    interval: RustInterval,
-    window: usize,
-    interval_slice: bool,
-    target_minutes: HashSet<u32>,
-    target_hours: HashSet<u32>,
-    target_days: HashSet<u32>,
-    target_weeks: HashSet<u32>,
-    target_months: HashSet<u32>,
-}
-
-/// 修剪时间到分钟精度
-fn trim_bar_time(py: Python, mut bar: RustBarData) -> PyResult<RustBarData> {
-    if let Some(ref dt_obj) = bar.datetime {
-        let dt_bound = dt_obj.bind(py);
-        let ts_method = dt_bound.call_method0("timestamp")?;
-        let ts_seconds = ts_method.extract::<f64>()?;
-        let ts_millis = (ts_seconds * 1000.0) as i64;
-        
-        if let Some(dt) = DateTime::from_timestamp_millis(ts_millis)
-            .map(|dt| dt.with_timezone(&*TZ_INFO)) 
-        {
-            let trimmed_py_dt = PyDateTime::new(
-                py,
-                dt.year(),
-                dt.month() as u8,
-                dt.day() as u8,
-                dt.hour() as u8,
-                dt.minute() as u8,
-                0,
-                0,
-                None
-            )?;
-            
-            bar.datetime = Some(trimmed_py_dt.into());
-        }
-    }
-    Ok(bar)
+pub struct TimezoneConverter {
+    tz: chrono_tz::Tz,
+    // 为 True 时返回带 tzinfo 的 datetime，否则返回该时区的本地时间（无时区）
+    aware: bool,
 }
 
 #[pymethods]
-impl BarGenerator {
+impl TimezoneConverter {
     #[new]
-    #[pyo3(signature = (on_bar=None, window=1, on_window_bar=None, interval=None, interval_slice=true))]
-    fn new(
-        _py: Python,
-        on_bar: Option<Py<PyAny>>,
-        window: usize,
-        on_window_bar: Option<Py<PyAny>>,
-        interval: Option<&Bound<'_, PyAny>>,
-        interval_slice: bool,
-    ) -> PyResult<Self> {
-        let rust_interval = if let Some(iv) = interval {
-            RustInterval::from_py_any(iv)?
-        } else {
-            RustInterval::MINUTE
-        };
-        
-        let target_minutes: HashSet<u32> = (0..60).step_by(window).collect();
-        let target_hours: HashSet<u32> = (0..24).step_by(window).collect();
-        let target_days: HashSet<u32> = (1..32).step_by(window).collect();
-        let target_weeks: HashSet<u32> = (1..54).step_by(window).collect();
-        let target_months: HashSet<u32> = (1..13).step_by(window).collect();
-
-        Ok(BarGenerator {
-            inner: RwLock::new(BarGeneratorInner {
-                bar: None,
-                interval_count: 0,
-                reset_count: 0,
-                window_bar: None,
-                last_tick: None,
-                last_bar: None,
-                finished: false,
-                bar_push_status: HashMap::new(),
-            }),
-            on_bar,
-            on_window_bar,
-            interval: rust_interval,
-            window,
-            interval_slice,
-            target_minutes,
-            target_hours,
-            target_days,
-            target_weeks,
-            target_months,
-        })
-    }
-
-    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<(Py<PyAny>, Py<PyAny>)> {
-        let cls = PyModule::import(py, "rust_bar_generator")?.getattr("BarGenerator")?;
-        
-        let interval_str = match self.interval {
-            RustInterval::TICK => "TICK",
-            RustInterval::MINUTE => "MINUTE",
-            RustInterval::HOUR => "HOUR",
-            RustInterval::DAILY => "DAILY",
-            RustInterval::WEEKLY => "WEEKLY",
-            RustInterval::MONTHLY => "MONTHLY",
-        };
-        
-        let args = (
-            self.on_bar.as_ref().map(|f| f.clone_ref(py)),
-            self.window,
-            self.on_window_bar.as_ref().map(|f| f.clone_ref(py)),
-            interval_str,
-            self.interval_slice,
-        );
-        
-        Ok((cls.into(), args.into_pyobject(py)?.into()))
-    }
-
-    /// update_tick 使用 &self 而不是 &mut self，避免借用冲突
-    fn update_tick(&self, py: Python, tick: Bound<'_, PyAny>) -> PyResult<()> {
-        let rust_tick = RustTickData::from_py_tick(py, &tick)?;
-        self.update_tick_internal(py, rust_tick)
+    #[pyo3(signature = (tz="Asia/Shanghai", aware=false))]
+    fn new(tz: &str, aware: bool) -> PyResult<Self> {
+        let tz = tz.parse::<chrono_tz::Tz>()
+            .map_err(|_| PyValueError::new_err(format!("无法识别的时区: {}", tz)))?;
+        Ok(TimezoneConverter { tz, aware })
     }
 
-    /// update_bar 使用 &self 而不是 &mut self，避免借用冲突
-    fn update_bar(&self, py: Python, bar: Bound<'_, PyAny>) -> PyResult<()> {
-        let rust_bar = RustBarData::from_py_bar(py, &bar)?;
-        self.update_bar_internal(py, rust_bar)
+    /// 时区名称
+    #[getter]
+    fn tz(&self) -> &'static str {
+        self.tz.name()
     }
 
-    fn generate(&self, py: Python) -> PyResult<()> {
-        // 先从 inner 中取出 bar，释放 RefCell 借用
-        let bar_to_callback = {
-            let mut inner = self.inner.write().unwrap();
-            inner.bar.take()
+    /// 将时间戳（数字或数字字符串）或时间字符串转换为 datetime。unit 为 s/ms/us/ns，
+    /// 不传时按数值大小自动识别；时间字符串按 UTC 解析，与 get_local_datetime 一致
+    #[pyo3(signature = (value, unit=None))]
+    fn to_datetime(&self, py: Python, value: &Bound<'_, PyAny>, unit: Option<&str>) -> PyResult<Py<PyAny>> {
+        let utc = match unit {
+            Some(unit) => {
+                let nanos_per_unit = timestamp_unit_nanos(unit)?;
+                let nanos = if let Ok(ts) = value.extract::<i64>() {
+                    ts.checked_mul(nanos_per_unit)
+                        .ok_or_else(|| PyValueError::new_err("时间戳超出范围"))?
+                } else if let Ok(ts) = value.extract::<f64>() {
+                    (ts * nanos_per_unit as f64) as i64
+                } else if let Ok(s) = value.extract::<String>() {
+                    let ts: f64 = s.trim().parse().map_err(|_| PyValueError::new_err("无效的时间戳字符串"))?;
+                    (ts * nanos_per_unit as f64) as i64
+                } else {
+                    return Err(PyValueError::new_err("不支持的时间戳类型"));
+                };
+                DateTime::from_timestamp_nanos(nanos).naive_utc()
+            }
+            None => parse_any_timestamp(value)?,
         };
+        self.to_py(py, &self.tz.from_utc_datetime(&utc))
+    }
 
-        if let Some(bar) = bar_to_callback {
-            let callback_opt = self.on_bar.as_ref().map(|c| c.clone_ref(py));
-            
-            if let Some(callback) = callback_opt {
-                let mut new_bar = bar;
-                
-                let now = chrono::Utc::now().with_timezone(&*TZ_INFO) - Duration::minutes(1);
-                let py_dt = PyDateTime::new(
-                    py,
-                    now.year(),
-                    now.month() as u8,
-                    now.day() as u8,
-                    now.hour() as u8,
-                    now.minute() as u8,
-                    now.second() as u8,
-                    now.nanosecond() / 1000,
-                    None
-                )?;
-                new_bar.datetime = Some(py_dt.into());
-                
-                let trimmed_bar = trim_bar_time(py, new_bar)?;
-                // 将 panic 改为返回 PyResult 错误
-                callback.call1(py, (trimmed_bar,)).map_err(|e| {
-                    PyValueError::new_err(format!("trimmed_bar回调处理错误：{:#?}", e))
-                })?;
-            }
-        }
-        Ok(())
+    /// 将 datetime 转换为时间戳，unit 为 s/ms/us/ns；无时区的 datetime 视为本时区的本地时间
+    #[pyo3(signature = (dt, unit="ms"))]
+    fn to_timestamp(&self, dt: &Bound<'_, PyAny>, unit: &str) -> PyResult<i64> {
+        let nanos_per_unit = timestamp_unit_nanos(unit)?;
+        let nanos = self.read_py(dt)?.timestamp_nanos_opt()
+            .ok_or_else(|| PyValueError::new_err("时间戳超出范围"))?;
+        Ok(nanos.div_euclid(nanos_per_unit))
     }
 
-    fn generate_bar_event(&self, py: Python, _event: Bound<'_, PyAny>) -> PyResult<()> {
-        // 先检查并获取必要的数据，然后释放借用
-        // 修改：将 bar_dt 加入返回元组，使其能在作用域外使用
-        let (should_generate, bar_timestamp, vt_symbol, bar_dt) = {
-            let inner = self.inner.read().unwrap();
-            
-            if inner.bar.is_none() {
-                return Ok(());
+    /// 将 datetime 向下取整到周期起点（分钟、小时、日、周一零点、月初），TICK 原样返回
+    fn floor(&self, py: Python, dt: &Bound<'_, PyAny>, interval: &Bound<'_, PyAny>) -> PyResult<Py<PyAny>> {
+        let interval = RustInterval::from_py_any(interval)?;
+        let local = self.read_py(dt)?.naive_local();
+        let date = local.date();
+        let floored = match interval {
+            RustInterval::TICK => local,
+            RustInterval::MINUTE => date.and_hms_opt(local.hour(), local.minute(), 0).unwrap_or(local),
+            RustInterval::HOUR => date.and_hms_opt(local.hour(), 0, 0).unwrap_or(local),
+            RustInterval::DAILY => date.and_time(NaiveTime::MIN),
+            RustInterval::WEEKLY => {
+                (date - Duration::days(date.weekday().num_days_from_monday() as i64)).and_time(NaiveTime::MIN)
             }
-            let bar = inner.bar.as_ref().unwrap();
-            let bar_dt = bar.get_datetime_chrono(py)?
-                .ok_or_else(|| PyValueError::new_err("Bar缺少datetime"))?;
-            let bar_timestamp = bar_dt.timestamp_millis();
-            if let Some(&status) = inner.bar_push_status.get(&bar_timestamp) {
-                if status {
-                    return Ok(());
-                }
-            }
-            let now_datetime = chrono::Utc::now().with_timezone(&*TZ_INFO);
-            let time_delta = now_datetime.signed_duration_since(bar_dt);
-            
-            let should_generate = time_delta > Duration::minutes(2);
-            let vt_symbol = bar.vt_symbol.clone();
-            
-            // 返回 bar_dt (DateTime<Tz> 实现了 Copy)
-            (should_generate, bar_timestamp, vt_symbol, bar_dt)
+            RustInterval::MONTHLY => date.with_day(1).unwrap_or(date).and_time(NaiveTime::MIN),
         };
-        
-        if should_generate {
-            println!(
-                "合约：{}，最新bar时间：{}，分钟bar缺失即将强制合成分钟bar",
-                vt_symbol, bar_dt
-            );
-            
-            // 更新状态
-            {
-                let mut inner = self.inner.write().unwrap();
-                inner.bar_push_status.insert(bar_timestamp, true);
-            }
-            
-            // 调用 generate（RefCell 借用已释放）
-            self.generate(py)?;
-        }
-        
-        Ok(())
+        let floored = self.tz.from_local_datetime(&floored)
+            .earliest()
+            .ok_or_else(|| PyValueError::new_err(format!("{} 在时区 {} 中不存在", floored, self.tz.name())))?;
+        self.to_py(py, &floored)
     }
+
     fn __repr__(&self) -> String {
-        format!("BarGenerator(interval={:?}, window={})", self.interval, self.window)
+        format!("TimezoneConverter(tz='{}', aware={})", self.tz.name(), self.aware)
     }
 }
 
-impl BarGenerator {
-    fn update_tick_internal(&self, py: Python, tick: RustTickData) -> PyResult<()> {
-        if tick.last_price == 0.0 {
-            return Ok(());
+impl TimezoneConverter {
+    /// 读取 Python datetime，无时区时按本时区的本地时间解释
+    fn read_py(&self, dt: &Bound<'_, PyAny>) -> PyResult<DateTime<chrono_tz::Tz>> {
+        if !dt.getattr("tzinfo")?.is_none() {
+            return py_datetime_to_chrono(dt).map(|d| d.with_timezone(&self.tz));
         }
+        let field = |name: &str| -> PyResult<u32> { dt.getattr(name)?.extract() };
+        let naive = NaiveDate::from_ymd_opt(dt.getattr("year")?.extract()?, field("month")?, field("day")?)
+            .and_then(|date| date.and_hms_micro_opt(field("hour").ok()?, field("minute").ok()?, field("second").ok()?, field("microsecond").ok()?))
+            .ok_or_else(|| PyValueError::new_err("无效的datetime"))?;
+        self.tz.from_local_datetime(&naive)
+            .earliest()
+            .ok_or_else(|| PyValueError::new_err(format!("{} 在时区 {} 中不存在", naive, self.tz.name())))
+    }
 
-        let tick_dt = tick.get_datetime_chrono(py)?
-            .ok_or_else(|| PyValueError::new_err("Tick缺少datetime"))?;
+    fn to_py(&self, py: Python, dt: &DateTime<chrono_tz::Tz>) -> PyResult<Py<PyAny>> {
+        let py_dt = PyDateTime::new(
+            py,
+            dt.year(),
+            dt.month() as u8,
+            dt.day() as u8,
+            dt.hour() as u8,
+            dt.minute() as u8,
+            dt.second() as u8,
+            dt.nanosecond() / 1000,
+            None
+        )?;
+        if !self.aware {
+            return Ok(py_dt.into_any().unbind());
+        }
+        let tzinfo = py.import("zoneinfo")?.getattr("ZoneInfo")?.call1((self.tz.name(),))?;
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("tzinfo", tzinfo)?;
+        Ok(py_dt.call_method("replace", (), Some(&kwargs))?.unbind())
+    }
+}
 
-        // 计算成交量变化和检查新分钟，使用临时借用
-        let (volume_change, new_minute, old_bar) = {
-            let mut inner = self.inner.write().unwrap();
-            
-            let volume_change = if let Some(ref last_tick) = inner.last_tick {
-                (tick.volume - last_tick.volume).max(0.0)
-            } else {
-                0.0
-            };
+/// 时间戳单位对应的纳秒数
+fn timestamp_unit_nanos(unit: &str) -> PyResult<i64> {
+    match unit {
+        "s" => Ok(1_000_000_000),
+        "ms" => Ok(1_000_000),
+        "us" => Ok(1_000),
+        "ns" => Ok(1),
+        _ => Err(PyValueError::new_err(format!("无效的时间戳单位: {}，可选 s、ms、us、ns", unit))),
+    }
+}
 
-            let new_minute = if let Some(ref bar) = inner.bar {
-                let bar_dt = bar.get_datetime_chrono(py)?
-                    .ok_or_else(|| PyValueError::new_err("Bar缺少datetime"))?;
-                bar_dt.minute() != tick_dt.minute()
-            } else {
-                true
-            };
+// ================================================================================================
+// vnpy 数据互转
+// ================================================================================================
 
-            let old_bar = if new_minute {
-                inner.bar.take()
-            } else {
-                None
-            };
+/// 批量将 vnpy BarData（或 RustBarData）转换为 RustBarData
+#[pyfunction]
+fn from_py_bars(bars: &Bound<'_, PyAny>) -> PyResult<Vec<RustBarData>> {
+    let mut rust_bars = Vec::with_capacity(bars.len().unwrap_or(0));
+    for (index, item) in bars.try_iter()?.enumerate() {
+        let bar = RustBarData::from_py_bar(&item?).map_err(|e| {
+            PyValueError::new_err(format!("第{}根bar转换失败: {}", index, e))
+        })?;
+        rust_bars.push(bar);
+    }
+    Ok(rust_bars)
+}
 
-            (volume_change, new_minute, old_bar)
-        };  // inner 借用在这里释放
+/// 通过缓冲区协议读取一维数组（如 NumPy ndarray）的全部元素
+fn buffer_to_vec<T: pyo3::buffer::Element + Copy>(py: Python, array: &Bound<'_, PyAny>, name: &str) -> PyResult<Vec<T>> {
+    let buffer = PyBuffer::<T>::get(array)
+        .map_err(|e| PyValueError::new_err(format!("{} 不是所需类型的数组: {}", name, e)))?;
+    if buffer.dimensions() != 1 {
+        return Err(PyValueError::new_err(format!("{} 必须是一维数组，实际维度 {}", name, buffer.dimensions())));
+    }
+    buffer.to_vec(py)
+}
 
-        // 处理旧 bar 的回调（在 RefCell 借用释放后）
-        if let Some(bar_data) = old_bar {
-            if let Some(ref callback) = self.on_bar {
-                let trimmed_bar = trim_bar_time(py, bar_data)?;
-                // 将 panic 改为返回 PyResult 错误
-                callback.call1(py, (trimmed_bar,)).map_err(|e| {
-                    PyValueError::new_err(format!("on_bar回调处理错误：{:#?}", e))
-                })?;
-            }
+/// 结构化数组的一列：持有缓冲区，按步长原地读取元素，不复制数据；
+/// 结构化数组的字段视图不连续且可能未对齐，因此不使用 as_slice
+struct ArrayColumn<T: pyo3::buffer::Element> {
+    buffer: PyBuffer<T>,
+}
+
+impl<T: pyo3::buffer::Element + Copy> ArrayColumn<T> {
+    /// 按字段名取结构化数组的一列，字段不存在时返回 None
+    fn field(arr: &Bound<'_, PyAny>, name: &str) -> PyResult<Option<Self>> {
+        let names = arr.getattr("dtype").and_then(|dtype| dtype.getattr("names")).ok().filter(|names| !names.is_none());
+        let present = match names {
+            Some(names) => names.contains(name)?,
+            None => arr.contains(name)?,
+        };
+        if !present {
+            return Ok(None);
+        }
+        let buffer = PyBuffer::<T>::get(&arr.get_item(name)?)
+            .map_err(|e| PyValueError::new_err(format!("{} 不是所需类型的数组: {}", name, e)))?;
+        if buffer.dimensions() != 1 {
+            return Err(PyValueError::new_err(format!("{} 必须是一维数组，实际维度 {}", name, buffer.dimensions())));
         }
+        Ok(Some(ArrayColumn { buffer }))
+    }
 
-        // 重新获取借用，创建或更新 bar
-        {
-            let mut inner = self.inner.write().unwrap();
-            
-            if new_minute {
-                let new_bar = RustBarData {
-                    symbol: tick.symbol.clone(),
-                    exchange: tick.exchange,
-                    datetime: tick.datetime.as_ref().map(|dt| dt.clone_ref(py)),
-                    interval: Some(RustInterval::MINUTE),
-                    volume: 0.0,
-                    open_interest: 0.0,
-                    open_price: tick.last_price,
-                    high_price: tick.last_price,
-                    low_price: tick.last_price,
-                    close_price: tick.last_price,
-                    gateway_name: tick.gateway_name.clone(),
-                    vt_symbol: tick.vt_symbol.clone(),
-                };
-                inner.bar = Some(new_bar);
-            } else {
-                if let Some(ref mut bar) = inner.bar {
-                    bar.high_price = bar.high_price.max(tick.last_price);
-                    bar.low_price = bar.low_price.min(tick.last_price);
-                    bar.close_price = tick.last_price;
-                    bar.datetime = tick.datetime.as_ref().map(|dt| dt.clone_ref(py));
-                }
-            }
+    fn len(&self) -> usize {
+        self.buffer.shape()[0]
+    }
 
-            if let Some(ref mut bar) = inner.bar {
-                bar.open_interest = tick.open_interest;
-            }
+    /// 读取第 index 个元素，调用方保证 index < len()
+    fn get(&self, index: usize) -> T {
+        assert!(index < self.len());
+        let offset = index as isize * self.buffer.strides()[0];
+        // SAFETY: index 不超过缓冲区长度，偏移由缓冲区自身的步长计算，buffer 存活期间内存有效
+        unsafe { (self.buffer.buf_ptr() as *const u8).offset(offset).cast::<T>().read_unaligned() }
+    }
+}
 
-            if inner.last_tick.is_some() {
-                if let Some(ref mut bar) = inner.bar {
-                    bar.volume += volume_change;
-                }
-            }
+/// 批量将 vnpy TickData（或 RustTickData）转换为 RustTickData
+fn from_py_ticks(py: Python, ticks: &Bound<'_, PyAny>) -> PyResult<Vec<RustTickData>> {
+    let mut rust_ticks = Vec::with_capacity(ticks.len().unwrap_or(0));
+    for (index, item) in ticks.try_iter()?.enumerate() {
+        let tick = RustTickData::from_py_tick(py, &item?).map_err(|e| {
+            PyValueError::new_err(format!("第{}个tick转换失败: {}", index, e))
+        })?;
+        rust_ticks.push(tick);
+    }
+    Ok(rust_ticks)
+}
 
-            inner.last_tick = Some(tick);
+/// 批量将 RustBarData 转换回 vnpy BarData，exchange_cls/interval_cls 传入 vnpy 的
+/// Exchange/Interval 枚举类时按 value 构造对应枚举，否则直接使用 RustExchange/RustInterval；
+/// exchange_name="canonical" 时改用枚举名（CBOT 而不是 CBT）构造 exchange_cls
+#[pyfunction]
+#[pyo3(signature = (bars, bar_cls, exchange_cls=None, interval_cls=None, exchange_name="value"))]
+fn to_py_bars(
+    py: Python,
+    bars: Vec<PyRef<'_, RustBarData>>,
+    bar_cls: &Bound<'_, PyAny>,
+    exchange_cls: Option<&Bound<'_, PyAny>>,
+    interval_cls: Option<&Bound<'_, PyAny>>,
+    exchange_name: &str,
+) -> PyResult<Vec<Py<PyAny>>> {
+    let canonical = match exchange_name {
+        "value" => false,
+        "canonical" => true,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "无效的exchange_name: {}，可选 value、canonical",
+                exchange_name
+            )));
         }
-        
-        Ok(())
+    };
+    let mut py_bars = Vec::with_capacity(bars.len());
+    for bar in bars {
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("symbol", &bar.symbol)?;
+        match exchange_cls {
+            Some(cls) if canonical => kwargs.set_item("exchange", cls.call1((bar.exchange.canonical_name(),))?)?,
+            Some(cls) => kwargs.set_item("exchange", cls.call1((bar.exchange.value(),))?)?,
+            None => kwargs.set_item("exchange", &bar.exchange)?,
+        }
+        kwargs.set_item("datetime", bar.py_datetime(py)?)?;
+        match (bar.interval, interval_cls) {
+            (Some(interval), Some(cls)) => kwargs.set_item("interval", cls.call1((interval.value(),))?)?,
+            (interval, _) => kwargs.set_item("interval", interval)?,
+        }
+        kwargs.set_item("volume", bar.volume)?;
+        kwargs.set_item("open_interest", bar.open_interest)?;
+        kwargs.set_item("open_price", bar.open_price)?;
+        kwargs.set_item("high_price", bar.high_price)?;
+        kwargs.set_item("low_price", bar.low_price)?;
+        kwargs.set_item("close_price", bar.close_price)?;
+        kwargs.set_item("gateway_name", &bar.gateway_name)?;
+        py_bars.push(bar_cls.call((), Some(&kwargs))?.unbind());
     }
+    Ok(py_bars)
+}
 
-    fn update_bar_internal(&self, py: Python, bar: RustBarData) -> PyResult<()> {
-        let bar_dt = bar.get_datetime_chrono(py)?
-            .ok_or_else(|| PyValueError::new_err("Bar缺少datetime"))?;
-
-        // 第一阶段：获取 last_bar 时间并处理 window_bar 初始化和更新
-        let (last_dt_opt, window_bar_to_callback) = {
-            let mut inner = self.inner.write().unwrap();
-            
-            let last_dt_opt = if let Some(ref last_bar) = inner.last_bar {
-                last_bar.get_datetime_chrono(py)?
-            } else {
-                None
-            };
+/// 将 Python datetime 转换为上海时区的 chrono 时间，保留到微秒（Python datetime 的精度）。
+/// 优先直接读取 datetime 结构体的字段在 Rust 中计算，无法处理时回退到 timestamp()：
+/// 浮点时间戳的误差远小于半微秒，四舍五入后即为原始微秒数
+fn py_datetime_to_chrono(dt: &Bound<'_, PyAny>) -> PyResult<DateTime<chrono_tz::Tz>> {
+    let utc = match dt.cast::<PyDateTime>() {
+        Ok(dt) => datetime_fields_to_utc(dt)?,
+        Err(_) => None,
+    };
+    let utc = match utc {
+        Some(utc) => utc,
+        None => {
+            let ts_seconds = dt.call_method0("timestamp")?.extract::<f64>()?;
+            DateTime::from_timestamp_micros((ts_seconds * 1e6).round() as i64)
+                .ok_or_else(|| PyValueError::new_err("无效的datetime"))?
+        }
+    };
+    Ok(utc.with_timezone(&*TZ_INFO))
+}
 
-            // 初始化或更新 window_bar
-            if inner.window_bar.is_none() {
-                let dt = match self.interval {
-                    RustInterval::MINUTE => bar_dt.with_second(0).unwrap().with_nanosecond(0).unwrap(),
-                    RustInterval::HOUR => bar_dt.with_minute(0).unwrap().with_second(0).unwrap().with_nanosecond(0).unwrap(),
-                    RustInterval::DAILY => (bar_dt + Duration::days(1)).date_naive().and_hms_opt(0, 0, 0).unwrap().and_local_timezone(*TZ_INFO).unwrap(),
-                    RustInterval::WEEKLY => (bar_dt + Duration::weeks(1)).date_naive().and_hms_opt(0, 0, 0).unwrap().and_local_timezone(*TZ_INFO).unwrap(),
-                    RustInterval::MONTHLY => {
-                        let (y, m) = if bar_dt.month() == 12 {
-                            (bar_dt.year() + 1, 1)
-                        } else {
-                            (bar_dt.year(), bar_dt.month() + 1)
-                        };
-                        match bar_dt.timezone().from_local_datetime(
-                            &NaiveDate::from_ymd_opt(y, m, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
-                        ) {
-                            chrono::LocalResult::Single(t) => t,
-                            _ => bar_dt,
-                        }
+/// 由 datetime 的各字段计算 UTC 时间，与 timestamp() 的结果一致：无时区时按进程本地时区解释，
+/// 不调用任何 Python 方法；有时区时只调用一次 utcoffset。本地时间在夏令时切换处有歧义或不存在、
+/// utcoffset 返回 None 时返回 None，由调用方回退到 timestamp()
+fn datetime_fields_to_utc(dt: &Bound<'_, PyDateTime>) -> PyResult<Option<DateTime<chrono::Utc>>> {
+    let naive = NaiveDate::from_ymd_opt(dt.get_year(), dt.get_month() as u32, dt.get_day() as u32)
+        .and_then(|date| date.and_hms_micro_opt(
+            dt.get_hour() as u32,
+            dt.get_minute() as u32,
+            dt.get_second() as u32,
+            dt.get_microsecond(),
+        ));
+    let Some(naive) = naive else {
+        return Ok(None);
+    };
+    let Some(tzinfo) = dt.get_tzinfo() else {
+        return Ok(chrono::Local.from_local_datetime(&naive).single().map(|dt| dt.to_utc()));
+    };
+    let offset = tzinfo.call_method1("utcoffset", (dt,))?;
+    let Ok(offset) = offset.cast::<PyDelta>() else {
+        return Ok(None);
+    };
+    let offset = Duration::days(offset.get_days() as i64)
+        + Duration::seconds(offset.get_seconds() as i64)
+        + Duration::microseconds(offset.get_microseconds() as i64);
+    Ok(Some((naive - offset).and_utc()))
+}
+
+/// 将带时区的时间转换为该时区本地时间的 Python datetime（无时区），与 py_datetime_to_chrono 互逆
+fn to_py_datetime(py: Python, dt: &DateTime<chrono_tz::Tz>) -> PyResult<Py<PyAny>> {
+    Ok(PyDateTime::new(
+        py,
+        dt.year(),
+        dt.month() as u8,
+        dt.day() as u8,
+        dt.hour() as u8,
+        dt.minute() as u8,
+        dt.second() as u8,
+        dt.nanosecond() / 1000,
+        None
+    )?.into_any().unbind())
+}
+
+/// 将带时区的时间转换为带固定偏移 tzinfo 的 Python datetime。生成器内部构造、之后还会由 py_datetime_to_chrono
+/// 读回的时间使用它，无时区的时间读回时会按主机本地时区解释，主机时区与 tz 不同时会错位
+fn to_py_datetime_fixed(py: Python, dt: &DateTime<chrono_tz::Tz>, tzinfo_cache: &mut Option<(i32, Py<PyTzInfo>)>) -> PyResult<Py<PyAny>> {
+    let offset = dt.offset().fix().local_minus_utc();
+    let tzinfo = match tzinfo_cache {
+        Some((cached, tzinfo)) if *cached == offset => tzinfo.bind(py).clone(),
+        _ => {
+            let tzinfo = PyTzInfo::fixed_offset(py, PyDelta::new(py, 0, offset, 0, true)?)?;
+            *tzinfo_cache = Some((offset, tzinfo.clone().unbind()));
+            tzinfo
+        }
+    };
+    Ok(PyDateTime::new(
+        py,
+        dt.year(),
+        dt.month() as u8,
+        dt.day() as u8,
+        dt.hour() as u8,
+        dt.minute() as u8,
+        dt.second() as u8,
+        dt.nanosecond() / 1000,
+        Some(&tzinfo)
+    )?.into_any().unbind())
+}
+
+/// 周期边界：不早于 dt 的第一个分钟、整点、零点、周一零点或月初零点
+fn ceil_to_interval(dt: &DateTime<chrono_tz::Tz>, interval: RustInterval) -> DateTime<chrono_tz::Tz> {
+    let local = dt.naive_local();
+    let date = local.date();
+    let midnight = date.and_time(NaiveTime::MIN);
+    let (start, next) = match interval {
+        RustInterval::TICK => return *dt,
+        RustInterval::MINUTE => {
+            let start = date.and_hms_opt(local.hour(), local.minute(), 0).unwrap_or(local);
+            (start, start + Duration::minutes(1))
+        }
+        RustInterval::HOUR => {
+            let start = date.and_hms_opt(local.hour(), 0, 0).unwrap_or(local);
+            (start, start + Duration::hours(1))
+        }
+        RustInterval::DAILY => (midnight, midnight + Duration::days(1)),
+        RustInterval::WEEKLY => {
+            let start = midnight - Duration::days(date.weekday().num_days_from_monday() as i64);
+            (start, start + Duration::weeks(1))
+        }
+        RustInterval::MONTHLY => {
+            let start = date.with_day(1).unwrap_or(date);
+            let (y, m) = if start.month() == 12 {
+                (start.year() + 1, 1)
+            } else {
+                (start.year(), start.month() + 1)
+            };
+            let next = NaiveDate::from_ymd_opt(y, m, 1).unwrap_or(start);
+            (start.and_time(NaiveTime::MIN), next.and_time(NaiveTime::MIN))
+        }
+    };
+    let boundary = if local == start { start } else { next };
+    dt.timezone().from_local_datetime(&boundary).earliest().unwrap_or(*dt)
+}
+
+/// 返回 update_bar 为窗口K线设置的时间：分钟、小时取起点，日、周为次日/下周零点，月为下月1日零点（12月跨年）。
+/// 无时区的 dt 视为 tz 的本地时间，返回值与输入的时区形式一致
+#[pyfunction]
+#[pyo3(signature = (dt, interval, tz="Asia/Shanghai"))]
+fn floor_datetime(py: Python, dt: &Bound<'_, PyAny>, interval: &Bound<'_, PyAny>, tz: &str) -> PyResult<Py<PyAny>> {
+    let converter = TimezoneConverter::new(tz, !dt.getattr("tzinfo")?.is_none())?;
+    let rule = WindowRule::new(RustInterval::from_py_any(interval)?, 1, true);
+    converter.to_py(py, &rule.window_label(&converter.read_py(dt)?))
+}
+
+/// 返回不早于 dt 的第一个周期边界（分钟、整点、零点、周一零点、月初零点），dt 恰好在边界上时原样返回
+#[pyfunction]
+#[pyo3(signature = (dt, interval, tz="Asia/Shanghai"))]
+fn ceil_datetime(py: Python, dt: &Bound<'_, PyAny>, interval: &Bound<'_, PyAny>, tz: &str) -> PyResult<Py<PyAny>> {
+    let converter = TimezoneConverter::new(tz, !dt.getattr("tzinfo")?.is_none())?;
+    let interval = RustInterval::from_py_any(interval)?;
+    converter.to_py(py, &ceil_to_interval(&converter.read_py(dt)?, interval))
+}
+
+/// 推荐的 interval_slice：window 能整除自然周期（60 分钟或 1440 分钟、24 小时、12 个月）时返回 True，
+/// 窗口对齐时钟切分；否则返回 False，按K线根数计数。周线按绝对周序号对齐，始终返回 True；
+/// 日线按实际交易日计数，始终返回 False
+#[pyfunction]
+fn recommended_interval_slice(interval: &Bound<'_, PyAny>, window: usize) -> PyResult<bool> {
+    if window == 0 {
+        return Err(PyValueError::new_err("window must be >= 1"));
+    }
+    Ok(WindowRule::new(RustInterval::from_py_any(interval)?, window, true).slice_fits())
+}
+
+// ================================================================================================
+// 离线重采样
+// ================================================================================================
+
+/// 窗口K线的时间标签
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BarLabel {
+    // 窗口起始时间
+    Start,
+    // 窗口内最后一根K线的时间
+    End,
+}
+
+impl BarLabel {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s.to_lowercase().as_str() {
+            "start" => Ok(BarLabel::Start),
+            "end" => Ok(BarLabel::End),
+            _ => Err(PyValueError::new_err(format!("无效的label: {}，可选 start、end", s))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            BarLabel::Start => "start",
+            BarLabel::End => "end",
+        }
+    }
+}
+
+/// 将历史K线重采样为 window 个 interval 周期的K线，窗口切分与 BarGenerator.update_bar 完全一致；
+/// 最后一个未走完的窗口仅在 include_partial=True 时返回，并标记 is_forced、is_partial
+#[pyfunction]
+#[pyo3(signature = (bars, window, interval, interval_slice=true, label="start", include_partial=false))]
+fn resample_bars(
+    py: Python,
+    bars: &Bound<'_, PyAny>,
+    window: usize,
+    interval: &Bound<'_, PyAny>,
+    interval_slice: bool,
+    label: &str,
+    include_partial: bool,
+) -> PyResult<Vec<RustBarData>> {
+    if window == 0 {
+        return Err(PyValueError::new_err("window must be >= 1"));
+    }
+    let rule = WindowRule::new(RustInterval::from_py_any(interval)?, window, interval_slice);
+    let label = BarLabel::parse(label)?;
+    let (mut resampled, partial) = resample(py, &rule, from_py_bars(bars)?, Some(label))?;
+    if include_partial && let Some(partial) = partial {
+        resampled.push(partial);
+    }
+    Ok(resampled)
+}
+
+/// 按 rule 合成窗口K线，返回已完成的窗口K线和最后未走完的窗口K线（标记 is_forced、is_partial）；
+/// label 为 None 时保留 update_bar 的时间标签
+fn resample(
+    py: Python,
+    rule: &WindowRule,
+    bars: Vec<RustBarData>,
+    label: Option<BarLabel>,
+) -> PyResult<(Vec<RustBarData>, Option<RustBarData>)> {
+    let mut resampled = Vec::new();
+    let mut window_bar: Option<RustBarData> = None;
+    let mut open_latched = false;
+    let mut interval_count = 0;
+    let mut last_dt: Option<DateTime<chrono_tz::Tz>> = None;
+    // 当前窗口第一根和最后一根K线的时间，用于设置时间标签
+    let mut window_span: Option<(DateTime<chrono_tz::Tz>, DateTime<chrono_tz::Tz>)> = None;
+
+    let relabel = |mut bar: RustBarData, span: Option<(DateTime<chrono_tz::Tz>, DateTime<chrono_tz::Tz>)>| -> PyResult<RustBarData> {
+        if let Some((first, last)) = span {
+            let dt = match label {
+                Some(BarLabel::Start) => rule.window_start(&first),
+                Some(BarLabel::End) => last,
+                None => return Ok(bar),
+            };
+            bar.datetime = Some(to_py_datetime(py, &dt)?);
+        }
+        Ok(bar)
+    };
+
+    for (index, bar) in bars.into_iter().enumerate() {
+        let bar_dt = bar.get_datetime_chrono(py)?
+            .ok_or_else(|| PyValueError::new_err(format!("第{}根bar缺少datetime", index)))?;
+        if rule.closes_period_before(last_dt.as_ref(), &bar_dt)
+            && let Some(finished_bar) = window_bar.take()
+        {
+            resampled.push(relabel(finished_bar, window_span.take())?);
+        }
+        rule.update_window_bar(py, &mut window_bar, &mut open_latched, &bar, &bar_dt)?;
+        window_span = Some(match window_span {
+            Some((first, _)) => (first, bar_dt),
+            None => (bar_dt, bar_dt),
+        });
+        let (finished, count) = rule.window_decision(last_dt.as_ref(), &bar_dt, interval_count, bar.interval);
+        interval_count = count;
+        if finished && let Some(finished_bar) = window_bar.take() {
+            interval_count = 0;
+            resampled.push(relabel(finished_bar, window_span.take())?);
+        }
+        last_dt = Some(bar_dt);
+    }
+
+    let partial = match window_bar {
+        Some(mut partial) => {
+            partial.is_forced = true;
+            partial.is_partial = true;
+            Some(relabel(partial, window_span)?)
+        }
+        None => None,
+    };
+    Ok((resampled, partial))
+}
+
+/// ticks_to_bars 的简化版本：返回全部K线，最后未走完的一根（标记 is_forced、is_partial）也包含在内，
+/// interval 默认 1 分钟
+#[pyfunction]
+#[pyo3(signature = (ticks, interval=None, window=1))]
+fn bars_from_ticks(
+    py: Python,
+    ticks: &Bound<'_, PyAny>,
+    interval: Option<&Bound<'_, PyAny>>,
+    window: usize,
+) -> PyResult<Vec<RustBarData>> {
+    let (mut bars, partial) = ticks_to_bars(py, ticks, interval, window, "cumulative", "", None, "")?;
+    bars.extend(partial);
+    Ok(bars)
+}
+
+/// 用与 BarGenerator.update_tick 相同的逻辑将 Tick 合成为K线，返回 (已完成的K线列表, 最后未走完的K线)。
+/// ticks 为 Tick 列表，或 update_ticks_array 支持的结构化数组（此时使用 symbol/exchange/gateway_name）；
+/// interval/window 不是 1 分钟时，分钟K线（含未走完的一根）再按 update_bar 的规则合成窗口K线
+#[pyfunction]
+#[pyo3(signature = (ticks, interval=None, window=1, volume_mode="cumulative", symbol="", exchange=None, gateway_name=""))]
+#[allow(clippy::too_many_arguments)]
+fn ticks_to_bars(
+    py: Python,
+    ticks: &Bound<'_, PyAny>,
+    interval: Option<&Bound<'_, PyAny>>,
+    window: usize,
+    volume_mode: &str,
+    symbol: &str,
+    exchange: Option<&Bound<'_, PyAny>>,
+    gateway_name: &str,
+) -> PyResult<(Vec<RustBarData>, Option<RustBarData>)> {
+    let interval = match interval {
+        Some(interval) => RustInterval::from_py_any(interval)?,
+        None => RustInterval::MINUTE,
+    };
+    if window == 0 {
+        return Err(PyValueError::new_err("window must be >= 1"));
+    }
+    if interval == RustInterval::TICK {
+        return Err(PyValueError::new_err("ticks_to_bars 不支持 TICK 周期"));
+    }
+
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("volume_mode", volume_mode)?;
+    let generator = py.get_type::<BarGenerator>().call((), Some(&kwargs))?.cast_into::<BarGenerator>()?;
+    let generator = generator.borrow();
+    if ticks.hasattr("dtype")? {
+        generator.update_ticks_array(py, ticks, symbol, exchange, gateway_name)?;
+    } else {
+        for tick in from_py_ticks(py, ticks)? {
+            generator.update_tick_internal(py, tick)?;
+        }
+    }
+
+    let mut bars = generator.drain_bars();
+    let partial = generator.write_inner().bar.take();
+    let partial = match partial {
+        Some(mut partial) => {
+            partial.is_forced = true;
+            partial.is_partial = true;
+            Some(trim_bar_time(py, partial, &generator.config.tz)?)
+        }
+        None => None,
+    };
+    if interval == RustInterval::MINUTE && window == 1 {
+        return Ok((bars, partial));
+    }
+
+    bars.extend(partial);
+    let rule = WindowRule::new(interval, window, true);
+    resample(py, &rule, bars, None)
+}
+
+// ================================================================================================
+// 交易时段 - 国内期货夜盘与交易日归属
+// ================================================================================================
+
+/// 夜盘开盘时间，晚于该时间的行情归属下一交易日
+const NIGHT_SESSION_START_HOUR: u32 = 20;
+
+/// 交易所时段配置
+#[derive(Debug, Clone, Copy)]
+struct ExchangeSession {
+    /// 夜盘收盘时间（取该交易所品种中最晚的收盘时间），None 表示没有夜盘
+    night_end: Option<NaiveTime>,
+}
+
+/// 国内期货交易所的时段表，非国内期货交易所返回 None
+fn cn_futures_session(exchange: &AnyExchange) -> Option<ExchangeSession> {
+    let night_end = match exchange.builtin()? {
+        // 黄金、白银、原油等品种夜盘到次日 02:30
+        RustExchange::SHFE | RustExchange::INE | RustExchange::SGE => NaiveTime::from_hms_opt(2, 30, 0),
+        RustExchange::DCE | RustExchange::CZCE => NaiveTime::from_hms_opt(23, 0, 0),
+        RustExchange::CFFEX | RustExchange::GFEX => None,
+        _ => return None,
+    };
+    Some(ExchangeSession { night_end })
+}
+
+/// 是否为周末（不考虑节假日）
+fn is_weekend(date: NaiveDate) -> bool {
+    matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+}
+
+/// 每周的工作日数，按 is_weekend 对任意一周计数
+fn business_days_per_week() -> u64 {
+    let monday = NaiveDate::from_isoywd_opt(2024, 1, Weekday::Mon).unwrap();
+    monday.iter_days().take(7).filter(|date| !is_weekend(*date)).count() as u64
+}
+
+/// 下一个工作日（跳过周末，不考虑节假日）
+fn next_business_day(date: NaiveDate) -> NaiveDate {
+    let mut next = date + Duration::days(1);
+    while is_weekend(next) {
+        next += Duration::days(1);
+    }
+    next
+}
+
+/// 落在周末的日期顺延到下一个工作日
+fn roll_to_business_day(date: NaiveDate) -> NaiveDate {
+    if is_weekend(date) {
+        next_business_day(date)
+    } else {
+        date
+    }
+}
+
+/// 计算行情所属交易日：20:00 至该交易所夜盘收盘之间的行情归属下一工作日，
+/// 跨零点夜盘在凌晨的延续部分归属当天（周六凌晨顺延到周一）；
+/// 夜盘收盘之后、日盘之前的时段以及没有夜盘的交易所返回自然日。
+/// 收盘时刻所在的一分钟仍算作夜盘，收盘 Tick 常晚于整点几百毫秒
+fn trading_day_of(dt: NaiveDateTime, exchange: &AnyExchange) -> NaiveDate {
+    let date = dt.date();
+    let Some(night_end) = cn_futures_session(exchange).and_then(|session| session.night_end) else {
+        return date;
+    };
+    let time = dt.time();
+    let night_start = NaiveTime::from_hms_opt(NIGHT_SESSION_START_HOUR, 0, 0).unwrap();
+    let before_close = |time: NaiveTime| time < night_end + Duration::minutes(1);
+    if night_end < night_start {
+        // 夜盘跨过零点
+        if time >= night_start {
+            next_business_day(date)
+        } else if before_close(time) {
+            roll_to_business_day(date)
+        } else {
+            date
+        }
+    } else if time >= night_start && before_close(time) {
+        next_business_day(date)
+    } else {
+        date
+    }
+}
+
+/// 常见交易场所的日线边界预设，同时确定时区和交易日的起始时间
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SessionPreset {
+    // CME 集团（含 COMEX、NYMEX）：America/Chicago，17:00 开始下一交易日，周日晚归属周一
+    Cme,
+    // 国内期货：Asia/Shanghai，20:00 之后的夜盘归属下一交易日，周五夜盘归属周一
+    CnFutures,
+    // 加密货币：UTC 零点切日，周末照常
+    CryptoUtc,
+}
+
+impl SessionPreset {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s.to_lowercase().as_str() {
+            "cme" => Ok(SessionPreset::Cme),
+            "cn_futures" => Ok(SessionPreset::CnFutures),
+            "crypto_utc" => Ok(SessionPreset::CryptoUtc),
+            _ => Err(PyValueError::new_err(format!("无效的session_preset: {}，可选 cme、cn_futures、crypto_utc", s))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            SessionPreset::Cme => "cme",
+            SessionPreset::CnFutures => "cn_futures",
+            SessionPreset::CryptoUtc => "crypto_utc",
+        }
+    }
+
+    fn tz(&self) -> chrono_tz::Tz {
+        match self {
+            SessionPreset::Cme => chrono_tz::America::Chicago,
+            SessionPreset::CnFutures => Shanghai,
+            SessionPreset::CryptoUtc => chrono_tz::UTC,
+        }
+    }
+
+    /// 交易日起始的整点，该时间之后的行情归属下一工作日；None 表示按自然日切分
+    fn day_start_hour(&self) -> Option<u32> {
+        match self {
+            SessionPreset::Cme => Some(17),
+            SessionPreset::CnFutures => Some(NIGHT_SESSION_START_HOUR),
+            SessionPreset::CryptoUtc => None,
+        }
+    }
+
+    /// 交易日的开盘时间，anchor="session" 时分钟、小时窗口从该时间开始计数
+    fn session_open(&self) -> NaiveTime {
+        match self {
+            SessionPreset::Cme => NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+            SessionPreset::CnFutures => NaiveTime::from_hms_opt(21, 0, 0).unwrap(),
+            SessionPreset::CryptoUtc => NaiveTime::MIN,
+        }
+    }
+}
+
+/// 分钟、小时窗口的计数起点
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WindowAnchor {
+    // 从零点开始（兼容旧行为）
+    Midnight,
+    // 从 session_preset 的开盘时间开始
+    Session,
+    // 从指定的时间开始
+    Time(NaiveTime),
+}
+
+impl WindowAnchor {
+    /// 可以是 "midnight"、"session"、"HH:MM"/"HH:MM:SS" 字符串或 datetime.time
+    fn parse(value: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(s) = value.extract::<String>() {
+            return match s.to_lowercase().as_str() {
+                "midnight" => Ok(WindowAnchor::Midnight),
+                "session" => Ok(WindowAnchor::Session),
+                _ => NaiveTime::parse_from_str(&s, "%H:%M:%S")
+                    .or_else(|_| NaiveTime::parse_from_str(&s, "%H:%M"))
+                    .map(WindowAnchor::Time)
+                    .map_err(|_| PyValueError::new_err(format!("无效的anchor: {}，可选 midnight、session 或 HH:MM", s))),
+            };
+        }
+        let hour = value.getattr("hour")?.extract::<u32>()?;
+        let minute = value.getattr("minute")?.extract::<u32>()?;
+        let second = value.getattr("second")?.extract::<u32>()?;
+        NaiveTime::from_hms_opt(hour, minute, second)
+            .map(WindowAnchor::Time)
+            .ok_or_else(|| PyValueError::new_err("无效的anchor时间"))
+    }
+
+    fn to_object(self, py: Python) -> PyResult<Py<PyAny>> {
+        let s = match self {
+            WindowAnchor::Midnight => "midnight".to_string(),
+            WindowAnchor::Session => "session".to_string(),
+            WindowAnchor::Time(time) => time.format("%H:%M:%S").to_string(),
+        };
+        Ok(s.into_pyobject(py)?.into_any().unbind())
+    }
+
+    /// 计数起点距零点的时长，anchor="session" 时必须设置 session_preset
+    fn resolve(self, session_preset: Option<SessionPreset>) -> PyResult<Duration> {
+        let time = match self {
+            WindowAnchor::Midnight => NaiveTime::MIN,
+            WindowAnchor::Session => session_preset
+                .ok_or_else(|| PyValueError::new_err("anchor=\"session\" 需要同时设置 session_preset"))?
+                .session_open(),
+            WindowAnchor::Time(time) => time,
+        };
+        Ok(time - NaiveTime::MIN)
+    }
+}
+
+// ================================================================================================
+// BarGeneratorInner - 内部可变状态
+// ================================================================================================
+struct BarGeneratorInner {
+    bar: Option<RustBarData>,
+    interval_count: usize,
+    reset_count: usize,
+    window_bar: Option<RustBarData>,
+    // 窗口开盘价是否已由第一根有成交的K线确定，确定后不再改变
+    window_open_latched: bool,
+    last_tick: Option<RustTickData>,
+    last_bar: Option<RustBarData>,
+    bar_push_status: HashMap<i64, bool>,
+    // 砖型图状态：上一块砖的收盘价、方向（1 上涨，-1 下跌，0 未定）、未分配的成交量
+    renko_close: Option<f64>,
+    renko_direction: i8,
+    renko_volume: f64,
+    // 未设置 on_bar 时缓存的已完成K线，通过 get_bars/drain_bars 取出
+    bar_buffer: VecDeque<RustBarData>,
+    // 未设置 on_window_bar 时缓存的主窗口K线，通过 get_window_bars 取出
+    window_bar_buffer: VecDeque<RustBarData>,
+    // 平均K线（Heikin-Ashi）状态：上一根平均K线的开盘价和收盘价，以及最近一根原始窗口K线
+    ha_prev: Option<(f64, f64)>,
+    raw_window_bar: Option<RustBarData>,
+    // 失衡K线状态：上一个非零价格变动的方向（tick rule），以及当前生效的阈值
+    imbalance_sign: f64,
+    imbalance_threshold: f64,
+    // Tick 直接合成小时窗口K线时，当前窗口起始时间的时间戳（秒）
+    window_bucket: Option<i64>,
+    // 通过 add_window 注册的其余窗口
+    extra_windows: Vec<ExtraWindow>,
+    // 最近一个时间K线 Tick 是否开始了新的分钟K线
+    new_bar_opened: bool,
+    // raise_callback_errors=False 时最近一次被忽略的回调异常
+    last_callback_error: Option<Py<PyAny>>,
+    stats: GeneratorStats,
+    // on_duplicate_bar="replace" 时上一根K线并入前的窗口状态，上一根K线完成了窗口时为 None
+    window_snapshot: Option<WindowSnapshot<RustBarData>>,
+    // clock="data" 时的当前时间：已推送行情的最新时间，或 set_backtest_time 设置的时间
+    data_time: Option<DateTime<chrono_tz::Tz>>,
+    // out_of_order 不为 accept 时已收到的最新 Tick 时间
+    max_tick_time: Option<DateTime<chrono_tz::Tz>>,
+    // 上一个因涨跌幅过大被拒绝的 Tick 价格
+    spike_price: Option<f64>,
+    // attach_stats 挂载的滚动统计
+    rolling_stats: Option<RollingStats>,
+    // 上一次报告的缺口结束时间，Tick 和由它合成的分钟K线报告同一缺口时只回调一次
+    last_gap_end: Option<DateTime<chrono_tz::Tz>>,
+    // 上一次调用 on_bar_update 的时刻，用于限频
+    last_bar_update: Option<std::time::Instant>,
+    // 设置后作为第二个参数传给推送K线的回调，随 get_state/pickle 保存
+    callback_context: Option<Py<PyAny>>,
+}
+
+impl BarGeneratorInner {
+    fn new(imbalance_threshold: f64) -> Self {
+        BarGeneratorInner {
+            bar: None,
+            interval_count: 0,
+            reset_count: 0,
+            window_bar: None,
+            window_open_latched: false,
+            last_tick: None,
+            last_bar: None,
+            bar_push_status: HashMap::new(),
+            renko_close: None,
+            renko_direction: 0,
+            renko_volume: 0.0,
+            bar_buffer: VecDeque::new(),
+            window_bar_buffer: VecDeque::new(),
+            ha_prev: None,
+            raw_window_bar: None,
+            imbalance_sign: 0.0,
+            imbalance_threshold,
+            window_bucket: None,
+            extra_windows: Vec::new(),
+            new_bar_opened: false,
+            last_callback_error: None,
+            stats: GeneratorStats::default(),
+            window_snapshot: None,
+            data_time: None,
+            max_tick_time: None,
+            spike_price: None,
+            rolling_stats: None,
+            last_gap_end: None,
+            last_bar_update: None,
+            callback_context: None,
+        }
+    }
+
+    /// 保存各窗口当前的聚合状态（主窗口在前，其余按注册顺序），last_dt 为上一根K线的时间
+    fn snapshot_windows(&self, py: Python, last_dt: Option<DateTime<chrono_tz::Tz>>) -> WindowSnapshot<RustBarData> {
+        let mut windows = vec![(
+            self.window_bar.as_ref().map(|bar| bar.clone_with_py(py)),
+            self.window_open_latched,
+            self.interval_count,
+        )];
+        windows.extend(self.extra_windows.iter().map(|extra| {
+            (extra.window_bar.as_ref().map(|bar| bar.clone_with_py(py)), extra.window_open_latched, extra.interval_count)
+        }));
+        WindowSnapshot { last_dt, windows }
+    }
+
+    /// 恢复 snapshot_windows 保存的窗口状态，返回保存时上一根K线的时间
+    fn restore_windows(&mut self, snapshot: WindowSnapshot<RustBarData>) -> Option<DateTime<chrono_tz::Tz>> {
+        let mut windows = snapshot.windows.into_iter();
+        if let Some((window_bar, open_latched, interval_count)) = windows.next() {
+            self.window_bar = window_bar;
+            self.window_open_latched = open_latched;
+            self.interval_count = interval_count;
+        }
+        for (extra, (window_bar, open_latched, interval_count)) in self.extra_windows.iter_mut().zip(windows) {
+            extra.window_bar = window_bar;
+            extra.window_open_latched = open_latched;
+            extra.interval_count = interval_count;
+        }
+        snapshot.last_dt
+    }
+
+    /// 清空窗口聚合状态，包括 add_window 注册的窗口
+    fn clear_window(&mut self) {
+        self.window_snapshot = None;
+        self.window_bar = None;
+        self.window_open_latched = false;
+        self.interval_count = 0;
+        self.reset_count = 0;
+        self.window_bucket = None;
+        for extra in self.extra_windows.iter_mut() {
+            extra.window_bar = None;
+            extra.window_open_latched = false;
+            extra.interval_count = 0;
+        }
+    }
+}
+
+/// attach_stats 挂载的滚动统计：保存最近 period 根已完成窗口K线的收盘价和真实波幅
+struct RollingStats {
+    period: usize,
+    closes: VecDeque<f64>,
+    true_ranges: VecDeque<f64>,
+    // 上一根窗口K线的收盘价，用于计算真实波幅
+    prev_close: Option<f64>,
+}
+
+impl RollingStats {
+    fn new(period: usize) -> Self {
+        RollingStats {
+            period,
+            closes: VecDeque::with_capacity(period),
+            true_ranges: VecDeque::with_capacity(period),
+            prev_close: None,
+        }
+    }
+
+    /// 并入一根已完成的窗口K线，超出 period 的旧数据被挤出
+    fn push(&mut self, bar: &RustBarData) {
+        let mut true_range = bar.high_price - bar.low_price;
+        if let Some(prev_close) = self.prev_close {
+            true_range = true_range
+                .max((bar.high_price - prev_close).abs())
+                .max((bar.low_price - prev_close).abs());
+        }
+        self.prev_close = Some(bar.close_price);
+        if self.closes.len() == self.period {
+            self.closes.pop_front();
+            self.true_ranges.pop_front();
+        }
+        self.closes.push_back(bar.close_price);
+        self.true_ranges.push_back(true_range);
+    }
+
+    /// 数据不足 period 根时返回 None
+    fn ready(&self) -> bool {
+        self.closes.len() == self.period
+    }
+
+    fn atr(&self) -> Option<f64> {
+        self.ready().then(|| self.true_ranges.iter().sum::<f64>() / self.period as f64)
+    }
+
+    fn sma_close(&self) -> Option<f64> {
+        self.ready().then(|| self.closes.iter().sum::<f64>() / self.period as f64)
+    }
+
+    /// 总体标准差（除以 period）
+    fn std_close(&self) -> Option<f64> {
+        let mean = self.sma_close()?;
+        let variance = self.closes.iter().map(|close| (close - mean).powi(2)).sum::<f64>() / self.period as f64;
+        Some(variance.sqrt())
+    }
+
+    /// 导出到 get_state 的状态字典
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("period", self.period)?;
+        dict.set_item("closes", Vec::from(self.closes.clone()))?;
+        dict.set_item("true_ranges", Vec::from(self.true_ranges.clone()))?;
+        dict.set_item("prev_close", self.prev_close)?;
+        Ok(dict)
+    }
+
+    fn from_dict(dict: &Bound<'_, PyDict>) -> PyResult<Self> {
+        let period = state_item(dict, "period")?.extract::<usize>()?;
+        let closes = state_item(dict, "closes")?.extract::<Vec<f64>>()?;
+        let true_ranges = state_item(dict, "true_ranges")?.extract::<Vec<f64>>()?;
+        if period == 0 || closes.len() > period || closes.len() != true_ranges.len() {
+            return Err(PyValueError::new_err("rolling_stats 状态无效"));
+        }
+        Ok(RollingStats {
+            period,
+            closes: closes.into(),
+            true_ranges: true_ranges.into(),
+            prev_close: state_item(dict, "prev_close")?.extract()?,
+        })
+    }
+}
+
+/// 运行统计计数，由 stats() 导出
+#[derive(Debug, Default, Clone, Copy)]
+struct GeneratorStats {
+    ticks_processed: u64,
+    // 被忽略的 Tick 总数（含最新价为 0 的 Tick），下面按原因分别计数
+    ticks_rejected: u64,
+    rejected_nan: u64,
+    rejected_negative: u64,
+    rejected_out_of_order: u64,
+    rejected_duplicate: u64,
+    rejected_stale: u64,
+    rejected_limit: u64,
+    rejected_spike: u64,
+    bars_updated: u64,
+    bars_emitted: u64,
+    // 主窗口和 add_window 注册的窗口推送的K线
+    window_bars_emitted: u64,
+    // 检测到的累计成交量重置次数
+    volume_resets: u64,
+    // 检测到的行情缺口次数和缺失的K线总数（见 on_gap）
+    gaps_detected: u64,
+    missing_bars: u64,
+    // 因 symbol/exchange 不匹配而忽略的 Tick 和K线
+    filtered_count: u64,
+}
+
+impl GeneratorStats {
+    fn to_dict<'py>(self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("ticks_processed", self.ticks_processed)?;
+        dict.set_item("ticks_rejected", self.ticks_rejected)?;
+        dict.set_item("rejected_nan", self.rejected_nan)?;
+        dict.set_item("rejected_negative", self.rejected_negative)?;
+        dict.set_item("rejected_out_of_order", self.rejected_out_of_order)?;
+        dict.set_item("rejected_duplicate", self.rejected_duplicate)?;
+        dict.set_item("rejected_stale", self.rejected_stale)?;
+        dict.set_item("rejected_limit", self.rejected_limit)?;
+        dict.set_item("rejected_spike", self.rejected_spike)?;
+        dict.set_item("bars_updated", self.bars_updated)?;
+        dict.set_item("bars_emitted", self.bars_emitted)?;
+        dict.set_item("window_bars_emitted", self.window_bars_emitted)?;
+        dict.set_item("volume_resets", self.volume_resets)?;
+        dict.set_item("gaps_detected", self.gaps_detected)?;
+        dict.set_item("missing_bars", self.missing_bars)?;
+        dict.set_item("filtered_count", self.filtered_count)?;
+        Ok(dict)
+    }
+
+    /// 从 to_dict 的结果恢复，缺少的计数为 0
+    fn from_dict(dict: &Bound<'_, PyDict>) -> PyResult<Self> {
+        let count = |key: &str| -> PyResult<u64> {
+            match dict.get_item(key)? {
+                Some(value) => value.extract(),
+                None => Ok(0),
+            }
+        };
+        Ok(GeneratorStats {
+            ticks_processed: count("ticks_processed")?,
+            ticks_rejected: count("ticks_rejected")?,
+            rejected_nan: count("rejected_nan")?,
+            rejected_negative: count("rejected_negative")?,
+            rejected_out_of_order: count("rejected_out_of_order")?,
+            rejected_duplicate: count("rejected_duplicate")?,
+            rejected_stale: count("rejected_stale")?,
+            rejected_limit: count("rejected_limit")?,
+            rejected_spike: count("rejected_spike")?,
+            bars_updated: count("bars_updated")?,
+            bars_emitted: count("bars_emitted")?,
+            window_bars_emitted: count("window_bars_emitted")?,
+            volume_resets: count("volume_resets")?,
+            gaps_detected: count("gaps_detected")?,
+            missing_bars: count("missing_bars")?,
+            filtered_count: count("filtered_count")?,
+        })
+    }
+}
+
+/// Tick 被忽略的原因
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TickRejection {
+    // 最新价为 0（无成交的快照）
+    ZeroPrice,
+    // 价格或成交量为 NaN / inf
+    NotFinite,
+    // 价格或成交量为负
+    Negative,
+    // 时间早于已收到的最新 Tick（out_of_order="drop"）
+    OutOfOrder,
+    // 与上一个 Tick 的时间、最新价、累计成交量都相同（dedup_ticks=True）
+    Duplicate,
+    // 距当前时钟时间超过 max_tick_age_seconds
+    Stale,
+    // 最新价超出涨跌停价
+    Limit,
+    // 相对上一个 Tick 的涨跌幅超过 max_price_change_pct
+    Spike,
+}
+
+impl TickRejection {
+    /// 检查 Tick 的价格和成交量，合法时返回 None
+    fn check(tick: &RustTickData) -> Option<Self> {
+        let values = [tick.last_price, tick.volume, tick.open_interest];
+        if values.iter().any(|v| !v.is_finite()) {
+            Some(TickRejection::NotFinite)
+        } else if values.iter().any(|&v| v < 0.0) {
+            Some(TickRejection::Negative)
+        } else if tick.last_price == 0.0 {
+            Some(TickRejection::ZeroPrice)
+        } else if Self::outside_limits(tick) {
+            Some(TickRejection::Limit)
+        } else {
+            None
+        }
+    }
+
+    /// 最新价高于涨停价或低于跌停价（为 0 的涨跌停价视为未知），恰好等于涨跌停价的不算
+    fn outside_limits(tick: &RustTickData) -> bool {
+        let tolerance = tick.last_price.abs() * 1e-9;
+        (tick.limit_up != 0.0 && tick.last_price > tick.limit_up + tolerance)
+            || (tick.limit_down != 0.0 && tick.last_price < tick.limit_down - tolerance)
+    }
+
+    /// 传给 on_tick_rejected 的原因
+    fn reason(&self) -> &'static str {
+        match self {
+            TickRejection::ZeroPrice => "zero_price",
+            TickRejection::NotFinite => "nan",
+            TickRejection::Negative => "negative",
+            TickRejection::OutOfOrder => "out_of_order",
+            TickRejection::Duplicate => "duplicate",
+            TickRejection::Stale => "stale",
+            TickRejection::Limit => "limit",
+            TickRejection::Spike => "spike",
+        }
+    }
+}
+
+impl GeneratorStats {
+    fn record_rejection(&mut self, rejection: TickRejection) {
+        self.ticks_rejected += 1;
+        match rejection {
+            TickRejection::ZeroPrice => {}
+            TickRejection::NotFinite => self.rejected_nan += 1,
+            TickRejection::Negative => self.rejected_negative += 1,
+            TickRejection::OutOfOrder => self.rejected_out_of_order += 1,
+            TickRejection::Duplicate => self.rejected_duplicate += 1,
+            TickRejection::Stale => self.rejected_stale += 1,
+            TickRejection::Limit => self.rejected_limit += 1,
+            TickRejection::Spike => self.rejected_spike += 1,
+        }
+    }
+}
+
+/// 各窗口的聚合状态快照（窗口K线、开盘价是否已确定、计数），用于撤回重复K线的贡献
+struct WindowSnapshot<B> {
+    last_dt: Option<DateTime<chrono_tz::Tz>>,
+    windows: Vec<(Option<B>, bool, usize)>,
+}
+
+/// 通过 add_window 注册的额外窗口：独立的切分规则、回调和聚合状态
+struct ExtraWindow {
+    rule: WindowRule,
+    callback: Py<PyAny>,
+    window_bar: Option<RustBarData>,
+    window_open_latched: bool,
+    interval_count: usize,
+}
+
+/// 不含 Python 对象的K线，用于释放 GIL 后批量合成（update_bars_numpy）
+#[derive(Debug, Clone, Copy)]
+struct PlainBar {
+    dt: DateTime<chrono_tz::Tz>,
+    open_price: f64,
+    high_price: f64,
+    low_price: f64,
+    close_price: f64,
+    volume: f64,
+    open_interest: f64,
+    touched_limit_up: bool,
+    touched_limit_down: bool,
+    is_partial: bool,
+    trade_count: u64,
+    sub_bar_count: u64,
+    expected_sub_bars: u64,
+}
+
+impl PlainBar {
+    fn from_bar(py: Python, bar: &RustBarData) -> PyResult<Option<Self>> {
+        Ok(bar.get_datetime_chrono(py)?.map(|dt| PlainBar {
+            dt,
+            open_price: bar.open_price,
+            high_price: bar.high_price,
+            low_price: bar.low_price,
+            close_price: bar.close_price,
+            volume: bar.volume,
+            open_interest: bar.open_interest,
+            touched_limit_up: bar.touched_limit_up,
+            touched_limit_down: bar.touched_limit_down,
+            is_partial: bar.is_partial,
+            trade_count: bar.trade_count,
+            sub_bar_count: bar.sub_bar_count,
+            expected_sub_bars: bar.expected_sub_bars,
+        }))
+    }
+
+    fn to_bar(self, py: Python, template: &BarTemplate, interval: RustInterval) -> PyResult<RustBarData> {
+        let dt = self.dt;
+        let py_dt = PyDateTime::new(
+            py,
+            dt.year(),
+            dt.month() as u8,
+            dt.day() as u8,
+            dt.hour() as u8,
+            dt.minute() as u8,
+            dt.second() as u8,
+            dt.nanosecond() / 1000,
+            None
+        )?;
+        Ok(RustBarData {
+            symbol: template.symbol.clone(),
+            exchange: template.exchange.clone(),
+            datetime: Some(py_dt.into_any().unbind()),
+            rust_datetime: None,
+            interval: Some(interval),
+            volume: self.volume,
+            open_interest: self.open_interest,
+            open_price: self.open_price,
+            high_price: self.high_price,
+            low_price: self.low_price,
+            close_price: self.close_price,
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            trade_count: self.trade_count,
+            sub_bar_count: self.sub_bar_count,
+            expected_sub_bars: self.expected_sub_bars,
+            gateway_name: template.gateway_name.clone(),
+            vt_symbol: make_vt_symbol(&template.symbol, &template.exchange, &template.gateway_name),
+            is_forced: false,
+            is_partial: self.is_partial,
+            touched_limit_up: self.touched_limit_up,
+            touched_limit_down: self.touched_limit_down,
+        })
+    }
+}
+
+/// PlainBar 转回 RustBarData 时使用的合约信息
+struct BarTemplate {
+    symbol: String,
+    exchange: AnyExchange,
+    gateway_name: String,
+}
+
+/// update_bars_numpy 中单个窗口的聚合状态，extra_index 为 None 表示主窗口，否则为 add_window 注册的第几个窗口
+struct PlainWindow {
+    rule: WindowRule,
+    extra_index: Option<usize>,
+    window_bar: Option<PlainBar>,
+    open_latched: bool,
+    interval_count: usize,
+}
+
+/// update_plain_bars 合成过程中产生的事件，重新获取 GIL 后按顺序回调
+enum PlainEvent {
+    /// on_gap：上一根K线时间、本根K线时间、缺失数量
+    Gap(DateTime<chrono_tz::Tz>, DateTime<chrono_tz::Tz>, i64),
+    /// 完成的窗口K线：窗口时长、extra_index、窗口K线
+    Window(u64, Option<usize>, PlainBar),
+}
+
+/// update_plain_bars 释放 GIL 期间逐根更新的状态，结束后写回 BarGeneratorInner
+struct PlainRun {
+    last_dt: Option<DateTime<chrono_tz::Tz>>,
+    last_bar: Option<PlainBar>,
+    windows: Vec<PlainWindow>,
+    snapshot: Option<WindowSnapshot<PlainBar>>,
+    last_gap_end: Option<DateTime<chrono_tz::Tz>>,
+    events: Vec<PlainEvent>,
+    bars_updated: u64,
+    gaps_detected: u64,
+    missing_bars: u64,
+}
+
+// ================================================================================================
+// WindowRule - 窗口切分规则（周期、窗口大小、是否对齐时钟）
+// ================================================================================================
+#[derive(Debug, Clone)]
+struct WindowRule {
+    interval: RustInterval,
+    window: usize,
+    interval_slice: bool,
+    target_minutes: HashSet<u32>,
+    target_hours: HashSet<u32>,
+    target_months: HashSet<u32>,
+    // 切分前先减去的偏移，整体平移窗口网格（如整点后 30 分钟开盘的交易所）
+    offset: Duration,
+    // 日、周、月窗口按交易日切分时，交易日起始的整点（见 SessionPreset）
+    day_start_hour: Option<u32>,
+    // 分钟、小时窗口计数起点距零点的时长（见 WindowAnchor），与 offset 叠加
+    anchor: Duration,
+    // 窗口K线的时间标签，None 时沿用各周期原有的标签（见 window_label）
+    label: Option<BarLabel>,
+    // 构造时指定的完整窗口基础K线数量，覆盖 expected_sub_bars 的推算结果
+    expected_sub_bars: Option<u64>,
+}
+
+impl WindowRule {
+    fn new(interval: RustInterval, window: usize, interval_slice: bool) -> Self {
+        WindowRule {
+            interval,
+            window,
+            interval_slice,
+            target_minutes: (0..60).step_by(window).collect(),
+            target_hours: (0..24).step_by(window).collect(),
+            target_months: (1..13).step_by(window).collect(),
+            offset: Duration::zero(),
+            day_start_hour: None,
+            anchor: Duration::zero(),
+            label: None,
+            expected_sub_bars: None,
+        }
+    }
+
+    fn with_offset(mut self, offset: Duration) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    fn with_day_start_hour(mut self, day_start_hour: Option<u32>) -> Self {
+        self.day_start_hour = day_start_hour;
+        self
+    }
+
+    fn with_anchor(mut self, anchor: Duration) -> Self {
+        self.anchor = anchor;
+        self
+    }
+
+    fn with_label(mut self, label: Option<BarLabel>) -> Self {
+        self.label = label;
+        self
+    }
+
+    fn with_expected_sub_bars(mut self, expected_sub_bars: Option<u64>) -> Self {
+        self.expected_sub_bars = expected_sub_bars;
+        self
+    }
+
+    /// 完整窗口包含的基础K线数量：分钟、小时窗口按分钟或小时K线推算，日、周窗口按日K线推算（每周的交易日数见 business_days_per_week），
+    /// 月窗口按月K线推算；由分钟K线合成日线等依赖交易时段的组合无法推算，返回 0
+    fn expected_sub_bars(&self, base_interval: Option<RustInterval>) -> u64 {
+        if let Some(expected) = self.expected_sub_bars {
+            return expected;
+        }
+        let window = self.window as u64;
+        match (self.interval, base_interval.unwrap_or(RustInterval::MINUTE)) {
+            (RustInterval::MINUTE, RustInterval::MINUTE) => window,
+            (RustInterval::HOUR, RustInterval::MINUTE) => window * 60,
+            (RustInterval::HOUR, RustInterval::HOUR) => window,
+            (RustInterval::DAILY, RustInterval::DAILY) => window,
+            (RustInterval::WEEKLY, RustInterval::DAILY) => window * business_days_per_week(),
+            (RustInterval::MONTHLY, RustInterval::MONTHLY) => window,
+            _ => 0,
+        }
+    }
+
+    /// 切分前整体平移的时长：offset，分钟、小时窗口再加上 anchor
+    fn grid_offset(&self) -> Duration {
+        match self.interval {
+            RustInterval::MINUTE | RustInterval::HOUR => self.offset + self.anchor,
+            _ => self.offset,
+        }
+    }
+
+    /// 日、周、月窗口使用的日期：设置了 day_start_hour 时为所属交易日，否则为自然日
+    fn session_date(&self, dt: &DateTime<chrono_tz::Tz>) -> NaiveDate {
+        let date = dt.date_naive();
+        match self.day_start_hour {
+            Some(hour) if dt.hour() >= hour => next_business_day(date),
+            Some(_) => roll_to_business_day(date),
+            None => date,
+        }
+    }
+
+    /// 日期零点对应的时间，不存在时（夏令时切换）返回 fallback
+    fn date_start(date: NaiveDate, fallback: DateTime<chrono_tz::Tz>) -> DateTime<chrono_tz::Tz> {
+        date.and_time(NaiveTime::MIN)
+            .and_local_timezone(fallback.timezone())
+            .earliest()
+            .unwrap_or(fallback)
+    }
+
+    /// 按 interval_slice=True 切分时窗口是否都等长（window 能整除自然周期）
+    fn slice_fits(&self) -> bool {
+        self.use_target_check() && (self.interval != RustInterval::MONTHLY || 12 % self.window == 0)
+    }
+
+    /// interval_slice=True 但 window 不能整除分钟、小时、月的自然周期时输出警告：
+    /// 分钟、小时窗口会退回按K线根数计数，月窗口在年末会出现较短的窗口
+    fn warn_uneven_slice(&self, py: Python) {
+        if self.interval_slice
+            && self.window > 1
+            && matches!(self.interval, RustInterval::MINUTE | RustInterval::HOUR | RustInterval::MONTHLY)
+            && !self.slice_fits()
+        {
+            log_message(py, LOG_WARNING, &format!(
+                "window={} 不能整除 {:?} 的自然周期，interval_slice=True 时窗口不能对齐时钟，建议改用 interval_slice=False",
+                self.window, self.interval
+            ), None);
+        }
+    }
+
+    /// 窗口的大致时长（分钟），用于同一根K线触发多个窗口时按从小到大的顺序回调
+    fn span_minutes(&self) -> u64 {
+        let unit = match self.interval {
+            RustInterval::TICK => 0,
+            RustInterval::MINUTE => 1,
+            RustInterval::HOUR => 60,
+            RustInterval::DAILY => 1440,
+            RustInterval::WEEKLY => 10080,
+            RustInterval::MONTHLY => 43200,
+        };
+        unit * self.window as u64
+    }
+
+    /// 窗口K线的时间：分钟、小时窗口取起始时间，日、周、月窗口沿用次日/下周/下月零点；设置了 offset 时整体平移
+    fn window_label(&self, bar_dt: &DateTime<chrono_tz::Tz>) -> DateTime<chrono_tz::Tz> {
+        let offset = self.grid_offset();
+        self.aligned_label(&(*bar_dt - offset)) + offset
+    }
+
+    /// 分钟、小时按时长减去多出的部分截断，不经过本地时间换算，夏令时回拨的重复时段也不会失败
+    fn aligned_label(&self, bar_dt: &DateTime<chrono_tz::Tz>) -> DateTime<chrono_tz::Tz> {
+        let bar_dt = *bar_dt;
+        let sub_minute = Duration::seconds(bar_dt.second() as i64) + Duration::nanoseconds(bar_dt.nanosecond() as i64);
+        match self.interval {
+            RustInterval::MINUTE => bar_dt - sub_minute,
+            RustInterval::HOUR => bar_dt - sub_minute - Duration::minutes(bar_dt.minute() as i64),
+            RustInterval::DAILY => Self::date_start(self.session_date(&bar_dt) + Duration::days(1), bar_dt),
+            RustInterval::WEEKLY => Self::date_start(self.session_date(&bar_dt) + Duration::weeks(1), bar_dt),
+            RustInterval::MONTHLY => {
+                let date = self.session_date(&bar_dt);
+                // 按季度、半年等日历周期切分时取周期结束后的第一个月
+                let (y, m) = if self.calendar_months() {
+                    let next = (self.month_period(date) + 1) * self.window as i32;
+                    (next.div_euclid(12), next.rem_euclid(12) as u32 + 1)
+                } else if date.month() == 12 {
+                    (date.year() + 1, 1)
+                } else {
+                    (date.year(), date.month() + 1)
+                };
+                match NaiveDate::from_ymd_opt(y, m, 1)
+                    .map(|first| bar_dt.timezone().from_local_datetime(&first.and_time(NaiveTime::MIN)))
+                {
+                    Some(chrono::LocalResult::Single(t)) => t,
+                    _ => bar_dt,
+                }
+            }
+            _ => bar_dt,
+        }
+    }
+
+    /// 窗口的真实起始时间：分钟、小时同 window_label，日为当日零点，周为周一零点，月为月初零点
+    fn window_start(&self, bar_dt: &DateTime<chrono_tz::Tz>) -> DateTime<chrono_tz::Tz> {
+        let shifted = *bar_dt - self.offset;
+        let date = self.session_date(&shifted);
+        let start = match self.interval {
+            RustInterval::DAILY => date,
+            RustInterval::WEEKLY => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            RustInterval::MONTHLY if self.calendar_months() => {
+                let first = self.month_period(date) * self.window as i32;
+                NaiveDate::from_ymd_opt(first.div_euclid(12), first.rem_euclid(12) as u32 + 1, 1).unwrap_or(date)
+            }
+            RustInterval::MONTHLY => date.with_day(1).unwrap_or(date),
+            _ => return self.window_label(bar_dt),
+        };
+        bar_dt.timezone().from_local_datetime(&start.and_time(NaiveTime::MIN))
+            .earliest()
+            .map_or(*bar_dt, |start| start + self.offset)
+    }
+
+    /// 窗口的最后一个周期：分钟、小时同 window_label，日为当日零点，周为周日零点，月为月末最后一天零点
+    fn window_end(&self, bar_dt: &DateTime<chrono_tz::Tz>) -> DateTime<chrono_tz::Tz> {
+        let shifted = *bar_dt - self.offset;
+        let date = self.session_date(&shifted);
+        let end = match self.interval {
+            RustInterval::DAILY => date,
+            RustInterval::WEEKLY => date + Duration::days(6 - date.weekday().num_days_from_monday() as i64),
+            RustInterval::MONTHLY => {
+                let (y, m) = if date.month() == 12 { (date.year() + 1, 1) } else { (date.year(), date.month() + 1) };
+                NaiveDate::from_ymd_opt(y, m, 1).and_then(|first| first.pred_opt()).unwrap_or(date)
+            }
+            _ => return self.window_label(bar_dt),
+        };
+        bar_dt.timezone().from_local_datetime(&end.and_time(NaiveTime::MIN))
+            .earliest()
+            .map_or(*bar_dt, |end| end + self.offset)
+    }
+
+    /// 新窗口K线的时间：label="start" 取 window_start，"end" 取 window_end（并入后续K线时随之更新），
+    /// 未设置时取 window_label
+    fn opening_label(&self, bar_dt: &DateTime<chrono_tz::Tz>) -> DateTime<chrono_tz::Tz> {
+        match self.label {
+            Some(BarLabel::Start) => self.window_start(bar_dt),
+            Some(BarLabel::End) => self.window_end(bar_dt),
+            None => self.window_label(bar_dt),
+        }
+    }
+
+    /// 用不含 Python 对象的K线初始化或更新窗口K线，规则与 update_window_bar 一致
+    fn update_plain_window_bar(&self, window_bar: &mut Option<PlainBar>, open_latched: &mut bool, bar: &PlainBar) {
+        match window_bar.as_mut() {
+            None => {
+                *window_bar = Some(PlainBar {
+                    dt: self.opening_label(&bar.dt),
+                    volume: 0.0,
+                    trade_count: 0,
+                    sub_bar_count: 0,
+                    // update_bars_numpy/update_bars_df 的K线均为分钟K线
+                    expected_sub_bars: self.expected_sub_bars(Some(RustInterval::MINUTE)),
+                    is_partial: self.starts_mid_period(&bar.dt),
+                    ..*bar
+                });
+                *open_latched = bar.volume > 0.0;
+            }
+            Some(window_bar) => {
+                window_bar.high_price = window_bar.high_price.max(bar.high_price);
+                window_bar.low_price = window_bar.low_price.min(bar.low_price);
+                if !*open_latched && bar.volume > 0.0 {
+                    window_bar.open_price = bar.open_price;
+                    *open_latched = true;
+                }
+                if self.label == Some(BarLabel::End) {
+                    window_bar.dt = self.window_end(&bar.dt);
+                }
+            }
+        }
+        if let Some(window_bar) = window_bar.as_mut() {
+            window_bar.close_price = bar.close_price;
+            window_bar.volume += bar.volume;
+            window_bar.trade_count += bar.trade_count;
+            window_bar.sub_bar_count += 1;
+            window_bar.open_interest = bar.open_interest;
+            window_bar.touched_limit_up |= bar.touched_limit_up;
+            window_bar.touched_limit_down |= bar.touched_limit_down;
+        }
+    }
+
+    /// 用新的K线初始化或更新窗口K线，开盘价取第一根有成交的K线的开盘价
+    fn update_window_bar(
+        &self,
+        py: Python,
+        window_bar: &mut Option<RustBarData>,
+        open_latched: &mut bool,
+        bar: &RustBarData,
+        bar_dt: &DateTime<chrono_tz::Tz>,
+    ) -> PyResult<()> {
+        let bar_dt = *bar_dt;
+        if window_bar.is_none() {
+            let dt = self.opening_label(&bar_dt);
+
+            let py_dt = PyDateTime::new(
+                py,
+                dt.year(),
+                dt.month() as u8,
+                dt.day() as u8,
+                dt.hour() as u8,
+                dt.minute() as u8,
+                dt.second() as u8,
+                dt.nanosecond() / 1000,
+                None
+            )?;
+
+            let new_window_bar = RustBarData {
+                symbol: bar.symbol.clone(),
+                exchange: bar.exchange.clone(),
+                datetime: Some(py_dt.into()),
+                rust_datetime: None,
+                interval: Some(self.interval),
+                volume: 0.0,
+                open_interest: bar.open_interest,
+                open_price: bar.open_price,
+                high_price: bar.high_price,
+                low_price: bar.low_price,
+                close_price: bar.close_price,
+                buy_volume: 0.0,
+                sell_volume: 0.0,
+                trade_count: 0,
+                sub_bar_count: 0,
+                expected_sub_bars: self.expected_sub_bars(bar.interval),
+                gateway_name: bar.gateway_name.clone(),
+                vt_symbol: bar.vt_symbol.clone(),
+                is_forced: false,
+                is_partial: self.starts_mid_period(&bar_dt),
+                touched_limit_up: false,
+                touched_limit_down: false,
+            };
+            *window_bar = Some(new_window_bar);
+            *open_latched = bar.volume > 0.0;
+        } else if let Some(window_bar) = window_bar.as_mut() {
+            window_bar.high_price = window_bar.high_price.max(bar.high_price);
+            window_bar.low_price = window_bar.low_price.min(bar.low_price);
+            // 窗口开头都是无成交的填充K线时，开盘价改由第一根有成交的K线确定
+            if !*open_latched && bar.volume > 0.0 {
+                window_bar.open_price = bar.open_price;
+                *open_latched = true;
+            }
+            if self.label == Some(BarLabel::End) {
+                window_bar.datetime = Some(to_py_datetime(py, &self.window_end(&bar_dt))?);
+            }
+        }
+
+        // 更新 close_price, volume, open_interest
+        if let Some(window_bar) = window_bar.as_mut() {
+            window_bar.close_price = bar.close_price;
+            window_bar.volume += bar.volume;
+            window_bar.buy_volume += bar.buy_volume;
+            window_bar.sell_volume += bar.sell_volume;
+            window_bar.trade_count += bar.trade_count;
+            window_bar.sub_bar_count += 1;
+            window_bar.open_interest = bar.open_interest;
+            window_bar.touched_limit_up |= bar.touched_limit_up;
+            window_bar.touched_limit_down |= bar.touched_limit_down;
+        }
+        Ok(())
+    }
+
+    /// 判断是否使用目标时间点检查模式（窗口对齐时钟整点），否则使用计数器
+    fn use_target_check(&self) -> bool {
+        match self.interval {
+            RustInterval::MINUTE => {
+                if self.interval_slice {
+                    if self.window < 60 {
+                        60 % self.window == 0
+                    } else {
+                        1440 % self.window == 0
+                    }
+                } else {
+                    false
+                }
+            }
+            RustInterval::HOUR => self.interval_slice && 24 % self.window == 0,
+            // 日线按实际出现的交易日计数，周末和节假日没有K线，不参与分组
+            RustInterval::DAILY => false,
+            // 周线按绝对周序号对齐，任意窗口都能跨年连续切分
+            RustInterval::WEEKLY => self.interval_slice,
+            _ => self.interval_slice,
+        }
+    }
+
+    /// 窗口完成判断：根据上一根K线时间、当前K线时间和当前计数，返回 (窗口是否完成, 更新后的计数)，不修改任何状态
+    fn window_decision(
+        &self,
+        last_dt: Option<&DateTime<chrono_tz::Tz>>,
+        now_dt: &DateTime<chrono_tz::Tz>,
+        interval_count: usize,
+        bar_interval: Option<RustInterval>,
+    ) -> (bool, usize) {
+        // 日历周期窗口：月K线落在周期最后一个月时完成，更小周期的K线由 closes_period_before 在进入下一周期时完成
+        if self.calendar_months() {
+            let date = self.period_date(now_dt);
+            let finished = bar_interval == Some(RustInterval::MONTHLY)
+                && (date.month0() as usize + 1).is_multiple_of(self.window);
+            return (finished, 0);
+        }
+        let now_value = self.get_interval_value_from_dt(now_dt);
+        // 第一根K线视为越过了边界，否则第一个窗口会比后续窗口多一根K线（window=1 时头两根K线合成一根）
+        if let Some(last_dt) = last_dt
+            && self.same_period(last_dt, now_dt)
+        {
+            return (false, interval_count);
+        }
+
+        if self.use_target_check() {
+            (self.check_target_value(now_value), interval_count)
+        } else {
+            // 对于 DAILY/WEEKLY/MONTHLY 或不能整除的情况，使用计数器方式
+            // 每次日期值变化时递增计数器，当计数达到 window 时触发
+            let interval_count = interval_count + 1;
+            (interval_count.is_multiple_of(self.window), interval_count)
+        }
+    }
+
+    /// 两根K线是否在同一个切分周期内。分钟、小时比较截断后的时刻而不只是分钟数、小时数，
+    /// 相隔整小时、整天的K线以及夏令时结束时重复出现的本地时间都属于不同周期
+    fn same_period(&self, last_dt: &DateTime<chrono_tz::Tz>, now_dt: &DateTime<chrono_tz::Tz>) -> bool {
+        let floor = |dt: &DateTime<chrono_tz::Tz>| {
+            let dt = *dt - self.grid_offset();
+            let minute = dt - Duration::seconds(dt.second() as i64) - Duration::nanoseconds(dt.nanosecond() as i64);
+            match self.interval {
+                RustInterval::HOUR => minute - Duration::minutes(dt.minute() as i64),
+                _ => minute,
+            }
+        };
+        match self.interval {
+            RustInterval::MINUTE | RustInterval::HOUR => floor(last_dt) == floor(now_dt),
+            RustInterval::MONTHLY => {
+                let (last, now) = (self.period_date(last_dt), self.period_date(now_dt));
+                (last.year(), last.month()) == (now.year(), now.month())
+            }
+            _ => self.get_interval_value_from_dt(last_dt) == self.get_interval_value_from_dt(now_dt),
+        }
+    }
+
+    /// 月窗口能整除 12 且对齐时钟时按日历周期（单月、季度、半年、年）切分，与开始推送的月份无关；
+    /// 日线等更小周期的K线在进入下一周期时先完成窗口，下个月的第一根K线不会并入上个月
+    fn calendar_months(&self) -> bool {
+        self.interval == RustInterval::MONTHLY && self.interval_slice && 12 % self.window == 0
+    }
+
+    /// 切分月窗口使用的日期（已减去偏移的交易日）
+    fn period_date(&self, dt: &DateTime<chrono_tz::Tz>) -> NaiveDate {
+        self.session_date(&(*dt - self.grid_offset()))
+    }
+
+    /// 日期所在日历周期的绝对序号
+    fn month_period(&self, date: NaiveDate) -> i32 {
+        (date.year() * 12 + date.month0() as i32).div_euclid(self.window as i32)
+    }
+
+    /// 日历周期窗口中，上一根K线与本K线属于不同周期时，进行中的窗口应在并入本K线之前完成
+    fn closes_period_before(&self, last_dt: Option<&DateTime<chrono_tz::Tz>>, now_dt: &DateTime<chrono_tz::Tz>) -> bool {
+        self.calendar_months()
+            && last_dt.is_some_and(|last_dt| {
+                self.month_period(self.period_date(last_dt)) != self.month_period(self.period_date(now_dt))
+            })
+    }
+
+    /// 日历周期窗口的第一根K线不在周期的第一个月时，该窗口不完整
+    fn starts_mid_period(&self, dt: &DateTime<chrono_tz::Tz>) -> bool {
+        self.calendar_months() && !(self.period_date(dt).month0() as usize).is_multiple_of(self.window)
+    }
+
+    /// 日期所在周的绝对序号（公元 1 年 1 月 1 日所在周为 0），ISO 第 53 周与次年第 1 周也是相邻的两周
+    fn absolute_week(date: NaiveDate) -> u32 {
+        let monday = date.num_days_from_ce() - date.weekday().num_days_from_monday() as i32;
+        ((monday - 1) / 7) as u32
+    }
+
+    #[inline(always)]
+    fn get_interval_value_from_dt(&self, dt: &DateTime<chrono_tz::Tz>) -> u32 {
+        let dt = &(*dt - self.grid_offset());
+        match self.interval {
+            RustInterval::MINUTE => {
+                if self.interval_slice && self.window >= 60 {
+                    // 对于大于等于60分钟的窗口，返回从计数起点（默认0点）开始的总分钟数
+                    dt.hour() * 60 + dt.minute()
+                } else {
+                    dt.minute()
+                }
+            }
+            RustInterval::HOUR => dt.hour(),
+            RustInterval::DAILY => self.session_date(dt).num_days_from_ce() as u32,
+            RustInterval::WEEKLY => Self::absolute_week(self.session_date(dt)),
+            RustInterval::MONTHLY => self.session_date(dt).month(),
+            _ => 0,
+        }
+    }
+
+    fn check_target_value(&self, value: u32) -> bool {
+        match self.interval {
+            RustInterval::MINUTE => {
+                if self.interval_slice && self.window >= 60 {
+                    // 对于大于等于60分钟的窗口，检查总分钟数是否是window的倍数
+                    (value as usize).is_multiple_of(self.window)
+                } else {
+                    self.target_minutes.contains(&value)
+                }
+            }
+            RustInterval::HOUR => self.target_hours.contains(&value),
+            RustInterval::WEEKLY => (value as usize).is_multiple_of(self.window),
+            RustInterval::MONTHLY => self.target_months.contains(&value),
+            _ => false,
+        }
+    }
+}
+
+/// 由相邻两根K线的时间差推断K线周期，时间差不是单个周期（如跨午休）时返回 None
+fn interval_from_delta(delta: Duration) -> Option<RustInterval> {
+    match delta.num_seconds() {
+        60 => Some(RustInterval::MINUTE),
+        3600 => Some(RustInterval::HOUR),
+        86400 => Some(RustInterval::DAILY),
+        604800 => Some(RustInterval::WEEKLY),
+        seconds if (28 * 86400..=31 * 86400).contains(&seconds) => Some(RustInterval::MONTHLY),
+        _ => None,
+    }
+}
+
+// ================================================================================================
+// VolumeMode - Tick 成交量的含义
+// ================================================================================================
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VolumeMode {
+    // volume 为当日累计成交量，K线成交量取相邻 Tick 的差值
+    Cumulative,
+    // last_volume 为逐笔成交量，直接累加进K线（常见于数字货币逐笔成交推送）
+    Delta,
+    // 按 Tick 的交易所选择：数字货币交易所用 Delta，其余用 Cumulative
+    Auto,
+}
+
+impl VolumeMode {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s.to_lowercase().as_str() {
+            "cumulative" => Ok(VolumeMode::Cumulative),
+            "delta" => Ok(VolumeMode::Delta),
+            "auto" => Ok(VolumeMode::Auto),
+            _ => Err(PyValueError::new_err(format!("无效的volume_mode: {}，可选 cumulative、delta、auto", s))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            VolumeMode::Cumulative => "cumulative",
+            VolumeMode::Delta => "delta",
+            VolumeMode::Auto => "auto",
+        }
+    }
+
+    /// 该 Tick 的成交量是否由累计成交量差分得到
+    fn is_cumulative(&self, tick: &RustTickData) -> bool {
+        match self {
+            VolumeMode::Cumulative => true,
+            VolumeMode::Delta => false,
+            VolumeMode::Auto => !tick.exchange.is_crypto(),
+        }
+    }
+
+    /// Tick 带来的成交量，last_volume 为上一个 Tick 的累计成交量；累计模式下没有上一个 Tick 时无法差分，
+    /// 取该 Tick 自身的最新成交量 last_volume，开盘第一笔成交不会丢失（数据源不提供时为 0）
+    #[inline(always)]
+    fn volume_change(&self, last_volume: Option<f64>, tick: &RustTickData) -> f64 {
+        match self {
+            VolumeMode::Auto if tick.exchange.is_crypto() => VolumeMode::Delta.volume_change(last_volume, tick),
+            VolumeMode::Auto => VolumeMode::Cumulative.volume_change(last_volume, tick),
+            VolumeMode::Delta => tick.last_volume.max(0.0),
+            VolumeMode::Cumulative => match last_volume {
+                Some(last_volume) => (tick.volume - last_volume).max(0.0),
+                None => tick.last_volume.max(0.0),
+            },
+        }
+    }
+}
+
+// ================================================================================================
+// PriceSource - 合成K线所用的 Tick 价格
+// ================================================================================================
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PriceSource {
+    // 最新成交价
+    Last,
+    // 买一卖一中间价，只有一侧报价时取该侧价格（只推送盘口的行情源）
+    Mid,
+    // 买一价
+    Bid,
+    // 卖一价
+    Ask,
+}
+
+impl PriceSource {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s.to_lowercase().as_str() {
+            "last" => Ok(PriceSource::Last),
+            "mid" => Ok(PriceSource::Mid),
+            "bid" => Ok(PriceSource::Bid),
+            "ask" => Ok(PriceSource::Ask),
+            _ => Err(PyValueError::new_err(format!("无效的price_source: {}，可选 last、mid、bid、ask", s))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            PriceSource::Last => "last",
+            PriceSource::Mid => "mid",
+            PriceSource::Bid => "bid",
+            PriceSource::Ask => "ask",
+        }
+    }
+
+    /// 用选定的价格替换 Tick 的 last_price；所需报价都为 0 时 last_price 为 0，按无成交的快照忽略
+    fn apply(&self, tick: &mut RustTickData) {
+        let (bid, ask) = (tick.bid_price_1, tick.ask_price_1);
+        tick.last_price = match self {
+            PriceSource::Last => return,
+            PriceSource::Mid if bid != 0.0 && ask != 0.0 => (bid + ask) / 2.0,
+            PriceSource::Mid if bid != 0.0 => bid,
+            PriceSource::Mid => ask,
+            PriceSource::Bid => bid,
+            PriceSource::Ask => ask,
+        };
+    }
+}
+
+// ================================================================================================
+// DuplicateBarPolicy - update_bar 收到与上一根时间相同的K线时的处理方式
+// ================================================================================================
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DuplicateBarPolicy {
+    // 忽略重复的K线（重叠的历史数据请求最常见）
+    Skip,
+    // 撤回上一根K线对窗口的贡献，改用新的K线
+    Replace,
+    // 与普通K线一样并入窗口
+    Add,
+}
+
+impl DuplicateBarPolicy {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(DuplicateBarPolicy::Skip),
+            "replace" => Ok(DuplicateBarPolicy::Replace),
+            "add" => Ok(DuplicateBarPolicy::Add),
+            _ => Err(PyValueError::new_err(format!("无效的on_duplicate_bar: {}，可选 skip、replace、add", s))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            DuplicateBarPolicy::Skip => "skip",
+            DuplicateBarPolicy::Replace => "replace",
+            DuplicateBarPolicy::Add => "add",
+        }
+    }
+}
+
+// ================================================================================================
+// BoundaryVolume - 开始新分钟K线的 Tick 带来的成交量记在哪根K线上
+// ================================================================================================
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BoundaryVolume {
+    // 记在新K线上（兼容旧行为）
+    NewBar,
+    // 先并入即将完成的旧K线再推送，新K线从 0 开始
+    OldBar,
+}
+
+impl BoundaryVolume {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s.to_lowercase().as_str() {
+            "new_bar" => Ok(BoundaryVolume::NewBar),
+            "old_bar" => Ok(BoundaryVolume::OldBar),
+            _ => Err(PyValueError::new_err(format!("无效的boundary_volume: {}，可选 new_bar、old_bar", s))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            BoundaryVolume::NewBar => "new_bar",
+            BoundaryVolume::OldBar => "old_bar",
+        }
+    }
+}
+
+// ================================================================================================
+// OutOfOrderPolicy - 收到时间早于已收到的最新 Tick 时的处理方式
+// ================================================================================================
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutOfOrderPolicy {
+    // 照常合成（兼容旧行为）
+    Accept,
+    // 忽略该 Tick
+    Drop,
+    // 抛出 ValueError
+    Raise,
+}
+
+impl OutOfOrderPolicy {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s.to_lowercase().as_str() {
+            "accept" => Ok(OutOfOrderPolicy::Accept),
+            "drop" => Ok(OutOfOrderPolicy::Drop),
+            "raise" => Ok(OutOfOrderPolicy::Raise),
+            _ => Err(PyValueError::new_err(format!("无效的out_of_order: {}，可选 accept、drop、raise", s))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            OutOfOrderPolicy::Accept => "accept",
+            OutOfOrderPolicy::Drop => "drop",
+            OutOfOrderPolicy::Raise => "raise",
+        }
+    }
+}
+
+// ================================================================================================
+// Clock - generate_bar_event 判断K线是否缺失时使用的时钟
+// ================================================================================================
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Clock {
+    // 系统时间，实盘使用
+    Wall,
+    // 已推送行情的最新时间（或 set_backtest_time 设置的时间），回测使用
+    Data,
+}
+
+impl Clock {
+    fn parse(s: &str) -> PyResult<Self> {
+        match s.to_lowercase().as_str() {
+            "wall" => Ok(Clock::Wall),
+            "data" => Ok(Clock::Data),
+            _ => Err(PyValueError::new_err(format!("无效的clock: {}，可选 wall、data", s))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Clock::Wall => "wall",
+            Clock::Data => "data",
+        }
+    }
+}
+
+// ================================================================================================
+// TickBarMode - 由 Tick 合成 K 线的模式
+// ================================================================================================
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TickBarMode {
+    /// 按分钟切分的时间K线
+    Time,
+    /// 砖型图（Renko），参数为砖块大小
+    Renko(f64),
+    /// 固定价格区间K线，参数为最高价与最低价之差的目标区间
+    Range(f64),
+    /// 成交量失衡K线，参数为主动买卖量差值的阈值
+    Imbalance(f64),
+}
+
+// ================================================================================================
+// 日志 - 诊断信息通过 Python logging（logger 名为 rust_bar_generator）输出，
+// set_log_callback 设置回调后改为调用回调，两者都失败时才写到 stderr
+// ================================================================================================
+const LOG_WARNING: u8 = 30;
+const LOG_ERROR: u8 = 40;
+
+static LOGGER: PyOnceLock<Py<PyAny>> = PyOnceLock::new();
+/// 中毒时直接取回锁内的值而不经 read_lock，锁中毒告警本身要输出日志，避免递归
+static LOG_CALLBACK: RwLock<Option<Py<PyAny>>> = RwLock::new(None);
+
+/// 设置日志回调 callback(level, message)，level 为 logging 的级别数值；传入 None 恢复使用 logging
+#[pyfunction]
+#[pyo3(signature = (callback=None))]
+fn set_log_callback(callback: Option<Py<PyAny>>) {
+    *LOG_CALLBACK.write().unwrap_or_else(PoisonError::into_inner) = callback;
+}
+
+/// 输出一条日志，error 为需要附带的异常
+fn log_message(py: Python, level: u8, message: &str, error: Option<&PyErr>) {
+    let callback = LOG_CALLBACK.read().unwrap_or_else(PoisonError::into_inner).as_ref().map(|cb| cb.clone_ref(py));
+    let result = match callback {
+        Some(callback) => {
+            let message = match error {
+                Some(e) => format!("{}：{}", message, e),
+                None => message.to_string(),
+            };
+            callback.call1(py, (level, message)).map(|_| ())
+        }
+        None => LOGGER
+            .get_or_try_init(py, || -> PyResult<Py<PyAny>> {
+                Ok(PyModule::import(py, "logging")?.call_method1("getLogger", ("rust_bar_generator",))?.unbind())
+            })
+            .and_then(|logger| {
+                let kwargs = PyDict::new(py);
+                if let Some(e) = error {
+                    kwargs.set_item("exc_info", (e.get_type(py), e.value(py), e.traceback(py)))?;
+                }
+                logger.call_method(py, "log", (level, message), Some(&kwargs)).map(|_| ())
+            }),
+    };
+    if result.is_err() {
+        eprintln!("{}", message);
+        if let Some(e) = error {
+            e.display(py);
+        }
+    }
+}
+
+// ================================================================================================
+// 锁中毒恢复 - 持锁期间发生 panic 会使 RwLock 中毒，之后的 unwrap 会让每次调用都 panic，
+// 这里清除中毒标记并记录告警后继续使用锁内的状态
+// ================================================================================================
+/// 获取读锁，锁已中毒时恢复，name 用于告警信息
+fn read_lock<'a, T>(lock: &'a RwLock<T>, name: &str) -> RwLockReadGuard<'a, T> {
+    match lock.read() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            // 先释放锁再输出日志，避免日志回调重入时死锁
+            drop(poisoned);
+            recover_poison(name, || lock.clear_poison());
+            lock.read().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+}
+
+/// 获取写锁，锁已中毒时恢复，name 用于告警信息
+fn write_lock<'a, T>(lock: &'a RwLock<T>, name: &str) -> RwLockWriteGuard<'a, T> {
+    match lock.write() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            drop(poisoned);
+            recover_poison(name, || lock.clear_poison());
+            lock.write().unwrap_or_else(PoisonError::into_inner)
+        }
+    }
+}
+
+/// 清除中毒标记并记录告警；锁内状态可能停留在 panic 时的中间值
+fn recover_poison(name: &str, clear: impl FnOnce()) {
+    clear();
+    let message = format!("{} 的锁因持锁线程 panic 而中毒，已恢复，状态可能不完整", name);
+    Python::attach(|py| log_message(py, LOG_WARNING, &message, None));
+}
+
+// ================================================================================================
+// 更新闸门 - 自由线程版 Python 下多个线程真正并行地更新同一个生成器。内部状态有 RwLock 保护，
+// 但回调在释放锁之后调用，两个线程的回调会交错、先合成的K线可能后推送；
+// 闸门让同一生成器的更新和推送逐个执行，同一线程在回调中再次调用生成器时可以重入。
+// 回调中调用另一个生成器时闸门会嵌套持有，A 的回调等 B、B 的回调等 A 会互相等待，
+// 因此所有闸门的持有者和等待关系登记在同一张表里，等待前沿等待链检查，会形成环时报错而不是死锁
+// ================================================================================================
+/// 全部闸门的持有者（线程, 重入深度）和正在等待的线程所等的闸门
+#[derive(Default)]
+struct GateTable {
+    owners: HashMap<u64, (ThreadId, usize)>,
+    waits: HashMap<ThreadId, u64>,
+}
+
+impl GateTable {
+    /// current 等待 gate 是否会形成等待环：沿“闸门持有者正在等待的闸门”走下去回到 current
+    fn would_deadlock(&self, gate: u64, current: ThreadId) -> bool {
+        let mut gate = gate;
+        // 等待链上每个线程只等一个闸门，步数不超过等待线程数
+        for _ in 0..=self.waits.len() {
+            let Some(&(owner, _)) = self.owners.get(&gate) else {
+                return false;
+            };
+            if owner == current {
+                return true;
+            }
+            match self.waits.get(&owner) {
+                Some(&next) => gate = next,
+                None => return false,
+            }
+        }
+        false
+    }
+}
+
+static GATE_TABLE: Lazy<Mutex<GateTable>> = Lazy::new(Mutex::default);
+static GATE_RELEASED: Condvar = Condvar::new();
+static NEXT_GATE_ID: AtomicU64 = AtomicU64::new(0);
+
+struct UpdateGate {
+    id: u64,
+}
+
+impl Default for UpdateGate {
+    fn default() -> Self {
+        UpdateGate { id: NEXT_GATE_ID.fetch_add(1, Ordering::Relaxed) }
+    }
+}
+
+fn gate_table() -> MutexGuard<'static, GateTable> {
+    GATE_TABLE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl UpdateGate {
+    /// 进入闸门，其他线程持有时释放 GIL（自由线程版为脱离解释器）等待，避免阻塞持有者的回调；
+    /// 等待会与其他线程互相等待时返回错误
+    fn enter(&self, py: Python) -> PyResult<UpdateGateGuard<'_>> {
+        let current = std::thread::current().id();
+        let held_by_other = |table: &GateTable| table.owners.get(&self.id).is_some_and(|&(owner, _)| owner != current);
+        let acquire = |table: &mut GateTable| {
+            table.owners.entry(self.id).or_insert((current, 0)).1 += 1;
+        };
+        let mut table = gate_table();
+        if held_by_other(&table) {
+            if table.would_deadlock(self.id, current) {
+                return Err(PyRuntimeError::new_err("回调中等待的生成器正被另一个线程更新，而该线程在等待本线程持有的生成器，继续等待会死锁"));
+            }
+            table.waits.insert(current, self.id);
+            drop(table);
+            py.detach(|| {
+                let table = gate_table();
+                let mut table = GATE_RELEASED.wait_while(table, |table| held_by_other(table))
+                    .unwrap_or_else(PoisonError::into_inner);
+                table.waits.remove(&current);
+                acquire(&mut table);
+            });
+        } else {
+            acquire(&mut table);
+        }
+        Ok(UpdateGateGuard { gate: self })
+    }
+}
+
+/// 离开作用域时退出闸门，最外层退出后唤醒等待的线程
+struct UpdateGateGuard<'a> {
+    gate: &'a UpdateGate,
+}
+
+impl Drop for UpdateGateGuard<'_> {
+    fn drop(&mut self) {
+        let mut table = gate_table();
+        if let Some(owner) = table.owners.get_mut(&self.gate.id) {
+            owner.1 -= 1;
+            if owner.1 == 0 {
+                table.owners.remove(&self.gate.id);
+                GATE_RELEASED.notify_all();
+            }
+        }
+    }
+}
+
+// ================================================================================================
+// 自动强制合成 - start_auto_generate 启动的后台线程按固定间隔执行 generate_bar_event 的检查。
+// 线程只持有生成器的弱引用，生成器被回收后自行退出；解释器退出前由 atexit 统一停止，
+// 避免线程在解释器销毁后获取 GIL
+// ================================================================================================
+/// 后台线程的停止信号和退出状态
+#[derive(Default)]
+struct StopSignal {
+    // （已请求停止, 线程已退出）
+    state: Mutex<(bool, bool)>,
+    changed: Condvar,
+}
+
+impl StopSignal {
+    fn stop(&self) {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).0 = true;
+        self.changed.notify_all();
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).0
+    }
+
+    /// 等待 timeout 或停止信号，返回是否已请求停止
+    fn wait(&self, timeout: std::time::Duration) -> bool {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let (state, _) = self.changed.wait_timeout_while(state, timeout, |state| !state.0)
+            .unwrap_or_else(PoisonError::into_inner);
+        state.0
+    }
+
+    fn mark_finished(&self) {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).1 = true;
+        self.changed.notify_all();
+    }
+
+    /// 等待线程退出
+    fn wait_finished(&self) {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let _state = self.changed.wait_while(state, |state| !state.1).unwrap_or_else(PoisonError::into_inner);
+    }
+}
+
+/// 运行中的自动强制合成线程
+struct AutoGenerate {
+    signal: Arc<StopSignal>,
+    handle: std::thread::JoinHandle<()>,
+}
+
+/// 所有启动过的后台线程的停止信号，解释器退出时逐个停止
+static AUTO_GENERATE_SIGNALS: Mutex<Vec<std::sync::Weak<StopSignal>>> = Mutex::new(Vec::new());
+
+/// 停止所有自动强制合成线程并等待退出，模块导入时注册到 atexit
+#[pyfunction]
+fn _stop_auto_generate_threads(py: Python) {
+    let signals: Vec<Arc<StopSignal>> = std::mem::take(&mut *AUTO_GENERATE_SIGNALS.lock().unwrap_or_else(PoisonError::into_inner))
+        .iter()
+        .filter_map(std::sync::Weak::upgrade)
+        .collect();
+    for signal in &signals {
+        signal.stop();
+    }
+    // 线程可能正在等待 GIL，释放后才能看到停止信号并退出
+    py.detach(|| signals.iter().for_each(|signal| signal.wait_finished()));
+}
+
+/// 后台线程主体：每隔 interval 获取 GIL 检查一次，生成器已被回收或收到停止信号时退出
+fn auto_generate_loop(generator: Py<PyWeakrefReference>, signal: Arc<StopSignal>, interval: std::time::Duration) {
+    while !signal.wait(interval) {
+        let alive = Python::attach(|py| {
+            // 等待 GIL 期间可能已经收到停止信号
+            if signal.is_stopped() {
+                return false;
+            }
+            let Some(generator) = generator.bind(py).upgrade() else {
+                return false;
+            };
+            let Ok(generator) = generator.cast::<BarGenerator>() else {
+                return false;
+            };
+            if let Err(e) = generator.borrow().generate_bar_event(py, py.None().into_bound(py), None) {
+                log_message(py, LOG_ERROR, "自动强制合成失败", Some(&e));
+            }
+            true
+        });
+        if !alive {
+            break;
+        }
+    }
+    signal.mark_finished();
+}
+
+// ================================================================================================
+// 生成器状态持久化 - get_state/set_state 使用的纯 Python 字典，datetime 保存为微秒时间戳
+// ================================================================================================
+/// get_state 的格式版本，字段不兼容时递增；版本 1 的 datetime 为毫秒时间戳，仍可读取
+const STATE_VERSION: u32 = 2;
+
+/// 读取状态字典中的必需字段
+fn state_item<'py>(state: &Bound<'py, PyDict>, key: &str) -> PyResult<Bound<'py, PyAny>> {
+    state.get_item(key)?
+        .ok_or_else(|| PyValueError::new_err(format!("状态缺少字段: {}", key)))
+}
+
+/// 将 datetime 转换为微秒时间戳，与 Python datetime 的精度一致，恢复后不丢失微秒
+fn datetime_to_state(py: Python, dt: Option<&Py<PyAny>>) -> PyResult<Option<i64>> {
+    match dt {
+        Some(dt) => Ok(Some(py_datetime_to_chrono(dt.bind(py))?.timestamp_micros())),
+        None => Ok(None),
+    }
+}
+
+/// 将状态中的时间戳还原为时间，version 为状态的格式版本
+fn datetime_from_state(ts: i64, version: u32) -> Option<DateTime<chrono::Utc>> {
+    if version == 1 {
+        DateTime::from_timestamp_millis(ts)
+    } else {
+        DateTime::from_timestamp_micros(ts)
+    }
+}
+
+/// K线转换为状态字典，键与 RustBarData 的构造参数一致
+fn bar_to_state<'py>(py: Python<'py>, bar: &RustBarData) -> PyResult<Bound<'py, PyDict>> {
+    let state = PyDict::new(py);
+    state.set_item("symbol", &bar.symbol)?;
+    state.set_item("exchange", &bar.exchange)?;
+    state.set_item("gateway_name", &bar.gateway_name)?;
+    state.set_item("datetime", datetime_to_state(py, bar.py_datetime(py)?.as_ref())?)?;
+    state.set_item("interval", bar.interval.map(|interval| interval.value()))?;
+    state.set_item("volume", bar.volume)?;
+    state.set_item("open_interest", bar.open_interest)?;
+    state.set_item("open_price", bar.open_price)?;
+    state.set_item("high_price", bar.high_price)?;
+    state.set_item("low_price", bar.low_price)?;
+    state.set_item("close_price", bar.close_price)?;
+    state.set_item("buy_volume", bar.buy_volume)?;
+    state.set_item("sell_volume", bar.sell_volume)?;
+    state.set_item("trade_count", bar.trade_count)?;
+    state.set_item("sub_bar_count", bar.sub_bar_count)?;
+    state.set_item("expected_sub_bars", bar.expected_sub_bars)?;
+    state.set_item("is_forced", bar.is_forced)?;
+    state.set_item("is_partial", bar.is_partial)?;
+    state.set_item("touched_limit_up", bar.touched_limit_up)?;
+    state.set_item("touched_limit_down", bar.touched_limit_down)?;
+    Ok(state)
+}
+
+/// Tick 转换为状态字典，键与 RustTickData 的构造参数一致
+fn tick_to_state<'py>(py: Python<'py>, tick: &RustTickData) -> PyResult<Bound<'py, PyDict>> {
+    let (_, args, kwargs) = tick.__reduce__(py)?;
+    let state = kwargs.bind(py).cast::<PyDict>()?.copy()?;
+    let args = args.bind(py);
+    state.set_item("symbol", args.get_item(0)?)?;
+    state.set_item("exchange", args.get_item(1)?)?;
+    state.set_item("gateway_name", args.get_item(2)?)?;
+    state.set_item("datetime", datetime_to_state(py, tick.py_datetime(py)?.as_ref())?)?;
+    Ok(state)
+}
+
+/// 追加到K线缓冲区，超过 max_len 时丢弃最早的K线
+fn push_bounded(buffer: &mut VecDeque<RustBarData>, bar: RustBarData, max_len: Option<usize>) {
+    if let Some(max_len) = max_len {
+        while buffer.len() >= max_len {
+            buffer.pop_front();
+        }
+    }
+    buffer.push_back(bar);
+}
+
+/// 复制或取出K线缓冲区的内容
+fn take_buffer(py: Python, buffer: &mut VecDeque<RustBarData>, clear: bool) -> Vec<RustBarData> {
+    if clear {
+        std::mem::take(buffer).into()
+    } else {
+        buffer.iter().map(|bar| bar.clone_with_py(py)).collect()
+    }
+}
+
+/// 用状态字典调用 RustBarData/RustTickData 的构造函数，None 表示没有该对象
+fn object_from_state<T>(py: Python, cls: Bound<'_, PyType>, state: &Bound<'_, PyAny>, version: u32) -> PyResult<Option<T>>
+where
+    T: for<'a, 'py> FromPyObject<'a, 'py>,
+{
+    if state.is_none() {
+        return Ok(None);
+    }
+    let kwargs = state.cast::<PyDict>()?.copy()?;
+    let datetime = match kwargs.get_item("datetime")? {
+        Some(ts) if !ts.is_none() => {
+            let dt = datetime_from_state(ts.extract::<i64>()?, version)
+                .ok_or_else(|| PyValueError::new_err("无效的datetime"))?
+                .with_timezone(&*TZ_INFO);
+            Some(to_py_datetime(py, &dt)?)
+        }
+        _ => None,
+    };
+    kwargs.set_item("datetime", datetime)?;
+    let obj = cls.call((), Some(&kwargs))?;
+    obj.extract::<T>().map(Some).map_err(Into::into)
+}
+
+// ================================================================================================
+// BarGenerator - K线生成器核心类（合成状态放在 RwLock 中，通过 &self 修改）
+// 未传入 on_bar 时不会丢弃已完成的K线，而是缓存起来，由 drain_bars() 一次性取出
+// ================================================================================================
+#[pyclass(module = "rust_bar_generator", weakref)]
+pub struct BarGenerator {
+    // 可变的合成状态，经 read_inner/write_inner 加锁，锁中毒时恢复
+    inner: RwLock<BarGeneratorInner>,
+    // 不可变配置
+    config: BarGeneratorConfig,
+    // 未等待的协程回调抛出的异常（回调名、异常），在事件循环线程中写入，下次推送K线时按回调异常处理
+    async_errors: Arc<Mutex<Vec<(String, PyErr)>>>,
+    // 多线程同时更新时保证合成与回调按顺序逐个执行
+    update_gate: UpdateGate,
+    // start_auto_generate 启动的后台线程
+    auto_generate: Mutex<Option<AutoGenerate>>,
+}
+
+/// BarGenerator 的构造配置，创建后不再修改；fork 与 pickle 都由这里复制和序列化
+struct BarGeneratorConfig {
+    on_bar: Option<Py<PyAny>>,
+    on_window_bar: Option<Py<PyAny>>,
+    rule: WindowRule,
+    tick_bar_mode: TickBarMode,
+    range_session_reset: bool,
+    heikin_ashi: bool,
+    imbalance_ewma_alpha: Option<f64>,
+    window_from_tick: bool,
+    volume_mode: VolumeMode,
+    strict_bar_order: bool,
+    on_duplicate_bar: DuplicateBarPolicy,
+    infer_interval: bool,
+    raise_callback_errors: bool,
+    push_status_retention: usize,
+    force_generate_after: Duration,
+    force_generate: bool,
+    // 切分窗口和输出K线时间所用的时区
+    tz: chrono_tz::Tz,
+    clock: Clock,
+    out_of_order: OutOfOrderPolicy,
+    on_tick_rejected: Option<Py<PyAny>>,
+    // update_bar 补齐的最大缺失分钟数，0 表示不补齐
+    fill_window_gaps: u32,
+    dedup_ticks: bool,
+    max_tick_age: Option<Duration>,
+    max_price_change_pct: Option<f64>,
+    session_preset: Option<SessionPreset>,
+    volume_reset_tolerance: Option<f64>,
+    boundary_volume: BoundaryVolume,
+    anchor: WindowAnchor,
+    // 不推送不完整的日历周期窗口K线（从周期中间开始推送时的第一个窗口）
+    drop_partial_windows: bool,
+    // 小时窗口边界所在的分钟，30 时按 9:30、10:30 切分
+    hour_anchor_minute: u32,
+    on_gap: Option<Py<PyAny>>,
+    // 超过该分钟数的缺口视为休市，不报告；None 表示全部报告
+    max_gap_minutes: Option<u32>,
+    // 回调为协程函数时执行协程的 asyncio 事件循环
+    event_loop: Option<Py<PyAny>>,
+    on_bar_update: Option<Py<PyAny>>,
+    // on_bar_update 两次调用之间的最短间隔，为 0 时每个 Tick 都调用
+    bar_update_interval: std::time::Duration,
+    // 未设置回调时K线缓冲区的最大长度，None 表示不限
+    max_buffered_bars: Option<usize>,
+    // 只处理该合约代码、交易所的行情，None 表示不过滤
+    symbol_filter: Option<String>,
+    exchange_filter: Option<AnyExchange>,
+    // 为 True 时 update_* 等待协程回调执行完成
+    await_callbacks: bool,
+    // 分钟切换时以（刚完成的分钟K线, 触发切换的 Tick）调用
+    on_bar_with_tick: Option<Py<PyAny>>,
+    // 合成K线所用的价格，非 last 时在检查 Tick 之前替换 last_price
+    price_source: PriceSource,
+    // 分钟K线完成时以（K线, 买价, 买量, 卖价, 卖量）调用，盘口取该分钟最后一个 Tick
+    on_bar_book: Option<Py<PyAny>>,
+}
+
+impl BarGeneratorConfig {
+    fn clone_ref(&self, py: Python) -> Self {
+        BarGeneratorConfig {
+            on_bar: self.on_bar.as_ref().map(|obj| obj.clone_ref(py)),
+            on_window_bar: self.on_window_bar.as_ref().map(|obj| obj.clone_ref(py)),
+            rule: self.rule.clone(),
+            tick_bar_mode: self.tick_bar_mode,
+            range_session_reset: self.range_session_reset,
+            heikin_ashi: self.heikin_ashi,
+            imbalance_ewma_alpha: self.imbalance_ewma_alpha,
+            window_from_tick: self.window_from_tick,
+            volume_mode: self.volume_mode,
+            strict_bar_order: self.strict_bar_order,
+            on_duplicate_bar: self.on_duplicate_bar,
+            infer_interval: self.infer_interval,
+            raise_callback_errors: self.raise_callback_errors,
+            push_status_retention: self.push_status_retention,
+            force_generate_after: self.force_generate_after,
+            force_generate: self.force_generate,
+            tz: self.tz,
+            clock: self.clock,
+            out_of_order: self.out_of_order,
+            on_tick_rejected: self.on_tick_rejected.as_ref().map(|obj| obj.clone_ref(py)),
+            fill_window_gaps: self.fill_window_gaps,
+            dedup_ticks: self.dedup_ticks,
+            max_tick_age: self.max_tick_age,
+            max_price_change_pct: self.max_price_change_pct,
+            session_preset: self.session_preset,
+            volume_reset_tolerance: self.volume_reset_tolerance,
+            boundary_volume: self.boundary_volume,
+            anchor: self.anchor,
+            drop_partial_windows: self.drop_partial_windows,
+            hour_anchor_minute: self.hour_anchor_minute,
+            on_gap: self.on_gap.as_ref().map(|obj| obj.clone_ref(py)),
+            max_gap_minutes: self.max_gap_minutes,
+            event_loop: self.event_loop.as_ref().map(|obj| obj.clone_ref(py)),
+            on_bar_update: self.on_bar_update.as_ref().map(|obj| obj.clone_ref(py)),
+            bar_update_interval: self.bar_update_interval,
+            max_buffered_bars: self.max_buffered_bars,
+            symbol_filter: self.symbol_filter.clone(),
+            exchange_filter: self.exchange_filter.clone(),
+            await_callbacks: self.await_callbacks,
+            on_bar_with_tick: self.on_bar_with_tick.as_ref().map(|obj| obj.clone_ref(py)),
+            price_source: self.price_source,
+            on_bar_book: self.on_bar_book.as_ref().map(|obj| obj.clone_ref(py)),
+        }
+    }
+
+    /// 转换为与构造参数同名的关键字参数，BarGenerator(**kwargs) 可以重建相同配置；
+    /// 事件循环无法 pickle，不包含在内，重建后需重新传入
+    fn to_kwargs<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let kwargs = PyDict::new(py);
+        let (brick_size, range_size, imbalance_threshold) = match self.tick_bar_mode {
+            TickBarMode::Time => (None, None, None),
+            TickBarMode::Renko(size) => (Some(size), None, None),
+            TickBarMode::Range(size) => (None, Some(size), None),
+            TickBarMode::Imbalance(threshold) => (None, None, Some(threshold)),
+        };
+        kwargs.set_item("on_bar", &self.on_bar)?;
+        kwargs.set_item("window", self.rule.window)?;
+        kwargs.set_item("on_window_bar", &self.on_window_bar)?;
+        kwargs.set_item("interval", self.rule.interval.value())?;
+        kwargs.set_item("interval_slice", self.rule.interval_slice)?;
+        kwargs.set_item("brick_size", brick_size)?;
+        kwargs.set_item("range_size", range_size)?;
+        kwargs.set_item("range_session_reset", self.range_session_reset)?;
+        kwargs.set_item("heikin_ashi", self.heikin_ashi)?;
+        kwargs.set_item("imbalance_threshold", imbalance_threshold)?;
+        kwargs.set_item("imbalance_ewma_alpha", self.imbalance_ewma_alpha)?;
+        kwargs.set_item("window_from_tick", self.window_from_tick)?;
+        kwargs.set_item("volume_mode", self.volume_mode.as_str())?;
+        kwargs.set_item("strict_bar_order", self.strict_bar_order)?;
+        kwargs.set_item("on_duplicate_bar", self.on_duplicate_bar.as_str())?;
+        kwargs.set_item("infer_interval", self.infer_interval)?;
+        kwargs.set_item("raise_callback_errors", self.raise_callback_errors)?;
+        kwargs.set_item("push_status_retention", self.push_status_retention)?;
+        kwargs.set_item("force_generate_after_seconds", self.force_generate_after.num_milliseconds() as f64 / 1000.0)?;
+        kwargs.set_item("force_generate", self.force_generate)?;
+        kwargs.set_item("tz", self.tz.name())?;
+        kwargs.set_item("clock", self.clock.as_str())?;
+        kwargs.set_item("out_of_order", self.out_of_order.as_str())?;
+        kwargs.set_item("on_tick_rejected", &self.on_tick_rejected)?;
+        kwargs.set_item("offset_seconds", self.rule.offset.num_microseconds().unwrap_or(0) as f64 / 1e6)?;
+        kwargs.set_item("fill_window_gaps", self.fill_window_gaps)?;
+        kwargs.set_item("dedup_ticks", self.dedup_ticks)?;
+        kwargs.set_item("max_tick_age_seconds", self.max_tick_age.map(|age| age.num_microseconds().unwrap_or(0) as f64 / 1e6))?;
+        kwargs.set_item("max_price_change_pct", self.max_price_change_pct)?;
+        kwargs.set_item("session_preset", self.session_preset.map(|preset| preset.as_str()))?;
+        kwargs.set_item("volume_reset_tolerance", self.volume_reset_tolerance)?;
+        kwargs.set_item("boundary_volume", self.boundary_volume.as_str())?;
+        kwargs.set_item("anchor", self.anchor.to_object(py)?)?;
+        kwargs.set_item("drop_partial_windows", self.drop_partial_windows)?;
+        kwargs.set_item("label", self.rule.label.map(|label| label.as_str()))?;
+        kwargs.set_item("hour_anchor_minute", self.hour_anchor_minute)?;
+        kwargs.set_item("on_gap", &self.on_gap)?;
+        kwargs.set_item("max_gap_minutes", self.max_gap_minutes)?;
+        kwargs.set_item("on_bar_update", &self.on_bar_update)?;
+        kwargs.set_item("bar_update_interval_ms", self.bar_update_interval.as_millis() as u64)?;
+        kwargs.set_item("max_buffered_bars", self.max_buffered_bars)?;
+        kwargs.set_item("symbol", &self.symbol_filter)?;
+        // 内置交易所保存为代码字符串，自定义交易所保存对象本身，反序列化时随之注册
+        match &self.exchange_filter {
+            Some(AnyExchange::Builtin(exchange)) => kwargs.set_item("exchange", exchange.value())?,
+            exchange => kwargs.set_item("exchange", exchange)?,
+        }
+        kwargs.set_item("await_callbacks", self.await_callbacks)?;
+        kwargs.set_item("expected_sub_bars", self.rule.expected_sub_bars)?;
+        kwargs.set_item("on_bar_with_tick", &self.on_bar_with_tick)?;
+        kwargs.set_item("price_source", self.price_source.as_str())?;
+        kwargs.set_item("on_bar_book", &self.on_bar_book)?;
+        Ok(kwargs)
+    }
+}
+
+impl Drop for BarGenerator {
+    /// 生成器被回收时通知自动强制合成线程退出；不等待线程结束，回收时持有 GIL，等待会与线程获取 GIL 互相阻塞
+    fn drop(&mut self) {
+        if let Some(auto_generate) = self.auto_generate.get_mut().unwrap_or_else(PoisonError::into_inner).take() {
+            auto_generate.signal.stop();
+        }
+    }
+}
+
+/// 两个 datetime 的各字段和 tzinfo 是否完全相同，直接读取 datetime 结构体，不调用 Python 方法
+fn same_datetime(py: Python, a: &Py<PyAny>, b: &Py<PyAny>) -> bool {
+    if a.is(b) {
+        return true;
+    }
+    let (Ok(a), Ok(b)) = (a.bind(py).cast::<PyDateTime>(), b.bind(py).cast::<PyDateTime>()) else {
+        return false;
+    };
+    a.get_year() == b.get_year()
+        && a.get_month() == b.get_month()
+        && a.get_day() == b.get_day()
+        && a.get_hour() == b.get_hour()
+        && a.get_minute() == b.get_minute()
+        && a.get_second() == b.get_second()
+        && a.get_microsecond() == b.get_microsecond()
+        && match (a.get_tzinfo(), b.get_tzinfo()) {
+            (None, None) => true,
+            (Some(a), Some(b)) => a.is(&b),
+            _ => false,
+        }
+}
+
+/// 秒数转换为 Duration，保留到微秒
+fn seconds_to_duration(seconds: f64) -> Duration {
+    Duration::microseconds((seconds * 1e6).round() as i64)
+}
+
+/// 修剪时间到分钟精度，结果为 tz 时区的本地时间
+fn trim_bar_time(py: Python, mut bar: RustBarData, tz: &chrono_tz::Tz) -> PyResult<RustBarData> {
+    if let Some(dt) = bar.get_datetime_chrono(py)? {
+        let dt = dt.with_timezone(tz);
+        let trimmed_py_dt = PyDateTime::new(
+            py,
+            dt.year(),
+            dt.month() as u8,
+            dt.day() as u8,
+            dt.hour() as u8,
+            dt.minute() as u8,
+            0,
+            0,
+            None
+        )?;
+        bar.datetime = Some(trimmed_py_dt.into());
+        bar.rust_datetime = None;
+    }
+    Ok(bar)
+}
+
+#[pymethods]
+impl BarGenerator {
+    #[new]
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (on_bar=None, window=1, on_window_bar=None, interval=None, interval_slice=true, brick_size=None, range_size=None, range_session_reset=false, heikin_ashi=false, imbalance_threshold=None, imbalance_ewma_alpha=None, window_from_tick=false, volume_mode="cumulative", strict_bar_order=false, on_duplicate_bar="skip", infer_interval=false, raise_callback_errors=false, push_status_retention=1440, force_generate_after_seconds=120.0, force_generate=true, tz=None, clock="wall", out_of_order="accept", on_tick_rejected=None, offset_seconds=0.0, fill_window_gaps=0, dedup_ticks=false, max_tick_age_seconds=None, max_price_change_pct=None, session_preset=None, volume_reset_tolerance=None, boundary_volume="new_bar", anchor=None, drop_partial_windows=false, label=None, hour_anchor_minute=0, on_gap=None, max_gap_minutes=None, event_loop=None, on_bar_update=None, bar_update_interval_ms=0, max_buffered_bars=None, symbol=None, exchange=None, await_callbacks=false, expected_sub_bars=None, callback_context=None, on_bar_with_tick=None, price_source="last", on_bar_book=None))]
+    fn new(
+        py: Python,
+        on_bar: Option<Py<PyAny>>,
+        window: usize,
+        on_window_bar: Option<Py<PyAny>>,
+        interval: Option<&Bound<'_, PyAny>>,
+        interval_slice: bool,
+        brick_size: Option<f64>,
+        range_size: Option<f64>,
+        range_session_reset: bool,
+        heikin_ashi: bool,
+        imbalance_threshold: Option<f64>,
+        imbalance_ewma_alpha: Option<f64>,
+        window_from_tick: bool,
+        volume_mode: &str,
+        strict_bar_order: bool,
+        on_duplicate_bar: &str,
+        infer_interval: bool,
+        raise_callback_errors: bool,
+        push_status_retention: usize,
+        force_generate_after_seconds: f64,
+        force_generate: bool,
+        tz: Option<&str>,
+        clock: &str,
+        out_of_order: &str,
+        on_tick_rejected: Option<Py<PyAny>>,
+        offset_seconds: f64,
+        fill_window_gaps: u32,
+        dedup_ticks: bool,
+        max_tick_age_seconds: Option<f64>,
+        max_price_change_pct: Option<f64>,
+        session_preset: Option<&str>,
+        volume_reset_tolerance: Option<f64>,
+        boundary_volume: &str,
+        anchor: Option<&Bound<'_, PyAny>>,
+        drop_partial_windows: bool,
+        label: Option<&str>,
+        hour_anchor_minute: u32,
+        on_gap: Option<Py<PyAny>>,
+        max_gap_minutes: Option<u32>,
+        event_loop: Option<Py<PyAny>>,
+        on_bar_update: Option<Py<PyAny>>,
+        bar_update_interval_ms: u64,
+        max_buffered_bars: Option<usize>,
+        symbol: Option<String>,
+        exchange: Option<&Bound<'_, PyAny>>,
+        await_callbacks: bool,
+        expected_sub_bars: Option<u64>,
+        callback_context: Option<Py<PyAny>>,
+        on_bar_with_tick: Option<Py<PyAny>>,
+        price_source: &str,
+        on_bar_book: Option<Py<PyAny>>,
+    ) -> PyResult<Self> {
+        if window == 0 {
+            return Err(PyValueError::new_err("window must be >= 1"));
+        }
+        if let Some(tolerance) = volume_reset_tolerance
+            && !(tolerance.is_finite() && tolerance >= 0.0)
+        {
+            return Err(PyValueError::new_err(format!("volume_reset_tolerance 必须为非负数: {}", tolerance)));
+        }
+        if let Some(pct) = max_price_change_pct
+            && !(pct.is_finite() && pct > 0.0)
+        {
+            return Err(PyValueError::new_err(format!("max_price_change_pct 必须为正数: {}", pct)));
+        }
+        if let Some(age) = max_tick_age_seconds
+            && !(age.is_finite() && age >= 0.0)
+        {
+            return Err(PyValueError::new_err(format!("max_tick_age_seconds 必须为非负数: {}", age)));
+        }
+        if !offset_seconds.is_finite() {
+            return Err(PyValueError::new_err(format!("offset_seconds 必须为有限数: {}", offset_seconds)));
+        }
+        let clock = Clock::parse(clock)?;
+        let out_of_order = OutOfOrderPolicy::parse(out_of_order)?;
+        let session_preset = session_preset.map(SessionPreset::parse).transpose()?;
+        let tz = match tz {
+            Some(tz) => tz.parse::<chrono_tz::Tz>()
+                .map_err(|_| PyValueError::new_err(format!("无效的时区: {}", tz)))?,
+            None => session_preset.map_or(Shanghai, |preset| preset.tz()),
+        };
+        if let Some(preset) = session_preset
+            && preset.tz() != tz
+        {
+            return Err(PyValueError::new_err(format!(
+                "session_preset={} 使用时区 {}，与 tz={} 不一致", preset.as_str(), preset.tz().name(), tz.name()
+            )));
+        }
+        let volume_mode = VolumeMode::parse(volume_mode)?;
+        let price_source = PriceSource::parse(price_source)?;
+        let boundary_volume = BoundaryVolume::parse(boundary_volume)?;
+        let anchor = anchor.map(WindowAnchor::parse).transpose()?.unwrap_or(WindowAnchor::Midnight);
+        let mut anchor_offset = anchor.resolve(session_preset)?;
+        let on_duplicate_bar = DuplicateBarPolicy::parse(on_duplicate_bar)?;
+        let rust_interval = if let Some(iv) = interval {
+            RustInterval::from_py_any(iv)?
+        } else {
+            RustInterval::MINUTE
+        };
+
+        let mode_params = [
+            ("brick_size", brick_size, TickBarMode::Renko as fn(f64) -> TickBarMode),
+            ("range_size", range_size, TickBarMode::Range),
+            ("imbalance_threshold", imbalance_threshold, TickBarMode::Imbalance),
+        ];
+        let mut tick_bar_mode = TickBarMode::Time;
+        for (name, param, mode) in mode_params {
+            let Some(size) = param else { continue };
+            if !(size.is_finite() && size > 0.0) {
+                return Err(PyValueError::new_err(format!("{} 必须为正数: {}", name, size)));
+            }
+            if tick_bar_mode != TickBarMode::Time {
+                return Err(PyValueError::new_err("brick_size、range_size、imbalance_threshold 只能设置其中一个"));
+            }
+            tick_bar_mode = mode(size);
+        }
+        if window_from_tick
+            && (rust_interval != RustInterval::HOUR || tick_bar_mode != TickBarMode::Time || window == 0 || 24 % window != 0)
+        {
+            return Err(PyValueError::new_err("window_from_tick 仅支持按时间切分的 HOUR 周期，且 window 需能整除 24"));
+        }
+        if max_buffered_bars == Some(0) {
+            return Err(PyValueError::new_err("max_buffered_bars 必须 >= 1"));
+        }
+        if hour_anchor_minute != 0 {
+            if rust_interval != RustInterval::HOUR || hour_anchor_minute >= 60 {
+                return Err(PyValueError::new_err(format!(
+                    "hour_anchor_minute 仅支持 HOUR 周期，且须位于 [0, 60): {}", hour_anchor_minute
+                )));
+            }
+            if anchor != WindowAnchor::Midnight {
+                return Err(PyValueError::new_err("hour_anchor_minute 与 anchor 不能同时设置"));
+            }
+            anchor_offset = Duration::minutes(hour_anchor_minute as i64);
+        }
+        if !(force_generate_after_seconds.is_finite() && force_generate_after_seconds >= 0.0) {
+            return Err(PyValueError::new_err(format!(
+                "force_generate_after_seconds 必须为非负数: {}", force_generate_after_seconds
+            )));
+        }
+        if let Some(alpha) = imbalance_ewma_alpha
+            && !(alpha > 0.0 && alpha <= 1.0)
+        {
+            return Err(PyValueError::new_err(format!("imbalance_ewma_alpha 必须位于 (0, 1]: {}", alpha)));
+        }
+
+        let rule = WindowRule::new(rust_interval, window, interval_slice)
+            .with_offset(seconds_to_duration(offset_seconds))
+            .with_day_start_hour(session_preset.and_then(|preset| preset.day_start_hour()))
+            .with_anchor(anchor_offset)
+            .with_label(label.map(BarLabel::parse).transpose()?)
+            .with_expected_sub_bars(expected_sub_bars);
+        rule.warn_uneven_slice(py);
+        let mut inner = BarGeneratorInner::new(imbalance_threshold.unwrap_or(0.0));
+        inner.callback_context = callback_context;
+
+        Ok(BarGenerator::with_config(inner, BarGeneratorConfig {
+            on_bar,
+            on_window_bar,
+            rule,
+            tick_bar_mode,
+            range_session_reset,
+            heikin_ashi,
+            imbalance_ewma_alpha,
+            window_from_tick,
+            volume_mode,
+            strict_bar_order,
+            on_duplicate_bar,
+            infer_interval,
+            raise_callback_errors,
+            push_status_retention,
+            force_generate_after: Duration::milliseconds((force_generate_after_seconds * 1000.0) as i64),
+            force_generate,
+            tz,
+            clock,
+            out_of_order,
+            on_tick_rejected,
+            fill_window_gaps,
+            dedup_ticks,
+            max_tick_age: max_tick_age_seconds.map(seconds_to_duration),
+            max_price_change_pct,
+            session_preset,
+            volume_reset_tolerance,
+            boundary_volume,
+            anchor,
+            drop_partial_windows,
+            hour_anchor_minute,
+            on_gap,
+            max_gap_minutes,
+            event_loop,
+            on_bar_update,
+            bar_update_interval: std::time::Duration::from_millis(bar_update_interval_ms),
+            max_buffered_bars,
+            symbol_filter: symbol,
+            exchange_filter: exchange.map(AnyExchange::from_py_any).transpose()?,
+            await_callbacks,
+            on_bar_with_tick,
+            price_source,
+            on_bar_book,
+        }))
+    }
+
+    /// pickle 时保存 get_config 的配置（反序列化时经 from_config 重建），以及 add_window 注册的窗口、
+    /// get_state 导出的合成状态和未取出的缓存K线，反序列化后可从中断处继续合成
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<(Py<PyAny>, Py<PyAny>, Py<PyAny>)> {
+        let from_config = py.get_type::<BarGenerator>().getattr("from_config")?;
+        let args = PyTuple::new(py, [self.get_config(py)?])?;
+
+        let state = PyDict::new(py);
+        {
+            let inner = self.read_inner();
+            let extra_windows = PyList::empty(py);
+            for extra in inner.extra_windows.iter() {
+                extra_windows.append((
+                    extra.rule.window,
+                    extra.rule.interval.value(),
+                    extra.callback.clone_ref(py),
+                    extra.rule.interval_slice,
+                ))?;
+            }
+            state.set_item("extra_windows", extra_windows)?;
+            let bar_buffer: Vec<RustBarData> = inner.bar_buffer.iter().map(|bar| bar.clone_with_py(py)).collect();
+            state.set_item("bar_buffer", bar_buffer)?;
+            let window_bar_buffer: Vec<RustBarData> = inner.window_bar_buffer.iter().map(|bar| bar.clone_with_py(py)).collect();
+            state.set_item("window_bar_buffer", window_bar_buffer)?;
+        }
+        state.set_item("generator_state", self.get_state(py)?)?;
+
+        Ok((from_config.unbind(), args.into_any().unbind(), state.into_any().unbind()))
+    }
+
+    /// 恢复 __reduce__ 保存的窗口注册、合成状态和缓存K线
+    fn __setstate__(&self, py: Python, state: &Bound<'_, PyDict>) -> PyResult<()> {
+        for extra in state_item(state, "extra_windows")?.cast_into::<PyList>()?.iter() {
+            let (window, interval, callback, interval_slice): (usize, String, Py<PyAny>, bool) = extra.extract()?;
+            let rule = WindowRule::new(RustInterval::parse_string(&interval)?, window, interval_slice);
+            self.push_extra_window(rule, callback);
+        }
+        self.set_state(py, &state_item(state, "generator_state")?.cast_into::<PyDict>()?)?;
+        let bar_buffer: Vec<RustBarData> = state_item(state, "bar_buffer")?.extract()?;
+        // 旧版本的 pickle 没有窗口K线缓存
+        let window_bar_buffer: Vec<RustBarData> = match state.get_item("window_bar_buffer")? {
+            Some(buffer) => buffer.extract()?,
+            None => Vec::new(),
+        };
+        let mut inner = self.write_inner();
+        inner.bar_buffer = bar_buffer.into();
+        inner.window_bar_buffer = window_bar_buffer.into();
+        Ok(())
+    }
+
+    /// update_tick 使用 &self 而不是 &mut self，避免借用冲突；
+    /// 返回该 Tick 是否开始了一根新的分钟K线（砖型图等非时间K线始终为 False）
+    fn update_tick(&self, py: Python, tick: Bound<'_, PyAny>) -> PyResult<bool> {
+        let rust_tick = RustTickData::from_py_tick(py, &tick)?;
+        self.update_tick_opened(py, rust_tick)
+    }
+
+    /// 批量推送 NumPy 数组形式的K线（ts_ms 为 int64 毫秒时间戳，其余为 float64，长度一致）。
+    /// 合成过程释放 GIL，完成的窗口K线在重新获取 GIL 后按顺序回调；含 NaN 的行与 update_bars_df 一样跳过；
+    /// return_bars=True 时不调用 on_window_bar，改为返回主窗口完成的K线列表
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (ts_ms, open, high, low, close, volume, open_interest=None, symbol="", exchange=None, gateway_name="", return_bars=false))]
+    fn update_bars_numpy(
+        &self,
+        py: Python,
+        ts_ms: &Bound<'_, PyAny>,
+        open: &Bound<'_, PyAny>,
+        high: &Bound<'_, PyAny>,
+        low: &Bound<'_, PyAny>,
+        close: &Bound<'_, PyAny>,
+        volume: &Bound<'_, PyAny>,
+        open_interest: Option<&Bound<'_, PyAny>>,
+        symbol: &str,
+        exchange: Option<&Bound<'_, PyAny>>,
+        gateway_name: &str,
+        return_bars: bool,
+    ) -> PyResult<Option<Vec<RustBarData>>> {
+        let ts_ms = buffer_to_vec::<i64>(py, ts_ms, "ts_ms")?;
+        let open = buffer_to_vec::<f64>(py, open, "open")?;
+        let high = buffer_to_vec::<f64>(py, high, "high")?;
+        let low = buffer_to_vec::<f64>(py, low, "low")?;
+        let close = buffer_to_vec::<f64>(py, close, "close")?;
+        let volume = buffer_to_vec::<f64>(py, volume, "volume")?;
+        let open_interest = match open_interest {
+            Some(array) => buffer_to_vec::<f64>(py, array, "open_interest")?,
+            None => vec![0.0; ts_ms.len()],
+        };
+        let len = ts_ms.len();
+        for (name, column_len) in [("open", open.len()), ("high", high.len()), ("low", low.len()),
+            ("close", close.len()), ("volume", volume.len()), ("open_interest", open_interest.len())] {
+            if column_len != len {
+                return Err(PyValueError::new_err(format!(
+                    "数组长度不一致: ts_ms 长度 {}，{} 长度 {}", len, name, column_len
+                )));
+            }
+        }
+        let template = BarTemplate {
+            symbol: symbol.to_string(),
+            exchange: match exchange {
+                Some(exchange) => AnyExchange::from_py_any(exchange)?,
+                None => AnyExchange::Builtin(RustExchange::LOCAL),
+            },
+            gateway_name: gateway_name.to_string(),
+        };
+
+        let rows: Vec<usize> = (0..len)
+            .filter(|&i| [open[i], high[i], low[i], close[i], volume[i], open_interest[i]].iter().all(|v| !v.is_nan()))
+            .collect();
+
+        let tz = self.config.tz;
+        self.update_plain_bars(py, rows.len(), |n| {
+            let index = rows[n];
+            let dt = DateTime::from_timestamp_millis(ts_ms[index])
+                .map(|dt| dt.with_timezone(&tz))
+                .ok_or_else(|| format!("第{}根bar的时间戳无效: {}", index, ts_ms[index]))?;
+            Ok(PlainBar {
+                dt,
+                open_price: open[index],
+                high_price: high[index],
+                low_price: low[index],
+                close_price: close[index],
+                volume: volume[index],
+                open_interest: open_interest[index],
+                touched_limit_up: false,
+                touched_limit_down: false,
+                is_partial: false,
+                trade_count: 0,
+                sub_bar_count: 0,
+                expected_sub_bars: 0,
+            })
+        }, &template, return_bars)
+    }
+
+    /// 批量推送 pandas DataFrame 形式的K线。时间取 datetime_column 列，未指定时取名为 datetime 的列，没有则取索引；
+    /// open/high/low/close/volume（及可选的 open_interest）列名不区分大小写，可通过 columns 指定映射，如 {"close": "last"}，
+    /// 多余的列忽略。无时区的时间按 tz 解释，默认 Asia/Shanghai。含 NaN 的行跳过，返回跳过的行数
+    #[allow(clippy::too_many_arguments)]
+    #[pyo3(signature = (df, symbol, exchange, gateway_name="DB", tz=None, columns=None, datetime_column=None))]
+    fn update_bars_df(
+        &self,
+        py: Python,
+        df: &Bound<'_, PyAny>,
+        symbol: &str,
+        exchange: &Bound<'_, PyAny>,
+        gateway_name: &str,
+        tz: Option<&str>,
+        columns: Option<&Bound<'_, PyDict>>,
+        datetime_column: Option<&str>,
+    ) -> PyResult<usize> {
+        let mut found: Vec<(String, Bound<'_, PyAny>)> = Vec::new();
+        for column in df.getattr("columns")?.try_iter()? {
+            let column = column?;
+            found.push((column.str()?.to_string(), column));
+        }
+        let find_column = |name: &str| -> PyResult<Option<Bound<'_, PyAny>>> {
+            let target = match columns.map(|mapping| mapping.get_item(name)).transpose()?.flatten() {
+                Some(mapped) => mapped.str()?.to_string(),
+                None => name.to_string(),
+            };
+            Ok(found.iter()
+                .find(|(column, _)| column.eq_ignore_ascii_case(&target))
+                .map(|(_, column)| column.clone()))
+        };
+
+        let mut required = Vec::new();
+        let mut missing = Vec::new();
+        for name in ["open", "high", "low", "close", "volume"] {
+            match find_column(name)? {
+                Some(column) => required.push(column),
+                None => missing.push(name),
+            }
+        }
+        if !missing.is_empty() {
+            let found_names: Vec<&str> = found.iter().map(|(name, _)| name.as_str()).collect();
+            return Err(PyValueError::new_err(format!(
+                "DataFrame缺少必要的列: {:?}，已有列: {:?}", missing, found_names
+            )));
+        }
+
+        let to_float = |column: &Bound<'_, PyAny>, name: &str| -> PyResult<Vec<f64>> {
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("dtype", "float64")?;
+            let array = df.get_item(column)?.call_method("to_numpy", (), Some(&kwargs))?;
+            buffer_to_vec::<f64>(py, &array, name)
+        };
+        let open = to_float(&required[0], "open")?;
+        let high = to_float(&required[1], "high")?;
+        let low = to_float(&required[2], "low")?;
+        let close = to_float(&required[3], "close")?;
+        let volume = to_float(&required[4], "volume")?;
+        let open_interest = match find_column("open_interest")? {
+            Some(column) => to_float(&column, "open_interest")?,
+            None => vec![0.0; open.len()],
+        };
+
+        // 时间列统一转换为 DatetimeIndex，读取 int64 纳秒值：带时区时为 UTC，无时区时为本地时间
+        let datetime_source = match datetime_column {
+            Some(name) => df.get_item(name)?,
+            None => match find_column("datetime")? {
+                Some(column) => df.get_item(column)?,
+                None => df.getattr("index")?,
+            },
+        };
+        let mut index = PyModule::import(py, "pandas")?.getattr("DatetimeIndex")?.call1((datetime_source,))?;
+        if index.getattr("tz")?.is_none()
+            && let Some(tz) = tz
+        {
+            index = index.call_method1("tz_localize", (tz,))?;
+        }
+        let aware = !index.getattr("tz")?.is_none();
+        let ts_ns = buffer_to_vec::<i64>(py, &index.getattr("asi8")?, "datetime")?;
+        if ts_ns.len() != open.len() {
+            return Err(PyValueError::new_err(format!(
+                "时间列长度 {} 与数据行数 {} 不一致", ts_ns.len(), open.len()
+            )));
+        }
+
+        // 跳过含 NaN 的行以及空时间（NaT）
+        let rows: Vec<usize> = (0..open.len())
+            .filter(|&i| {
+                ts_ns[i] != i64::MIN
+                    && [open[i], high[i], low[i], close[i], volume[i], open_interest[i]].iter().all(|v| !v.is_nan())
+            })
+            .collect();
+        let skipped = open.len() - rows.len();
+
+        let template = BarTemplate {
+            symbol: symbol.to_string(),
+            exchange: AnyExchange::from_py_any(exchange)?,
+            gateway_name: gateway_name.to_string(),
+        };
+        let tz = self.config.tz;
+        self.update_plain_bars(py, rows.len(), |n| {
+            let i = rows[n];
+            let utc = DateTime::from_timestamp_nanos(ts_ns[i]);
+            let dt = if aware {
+                utc.with_timezone(&tz)
+            } else {
+                tz.from_local_datetime(&utc.naive_utc())
+                    .earliest()
+                    .ok_or_else(|| format!("第{}行时间无效: {}", i, utc.naive_utc()))?
+            };
+            Ok(PlainBar {
+                dt,
+                open_price: open[i],
+                high_price: high[i],
+                low_price: low[i],
+                close_price: close[i],
+                volume: volume[i],
+                open_interest: open_interest[i],
+                touched_limit_up: false,
+                touched_limit_down: false,
+                is_partial: false,
+                trade_count: 0,
+                sub_bar_count: 0,
+                expected_sub_bars: 0,
+            })
+        }, &template, false)?;
+        Ok(skipped)
+    }
+
+    /// 从 NumPy 结构化数组（或按字段名取列的对象）批量推送 Tick：datetime_ns 为 int64 纳秒时间戳（UTC），
+    /// last_price 必需，volume、last_volume、open_interest 可选，均为 float64。原地读取各列的缓冲区（不复制），
+    /// Tick 及其时间都在 Rust 中构造，不创建 RustTickData 和 datetime Python 对象；返回期间推送给 on_bar 的已完成K线数量
+    #[pyo3(signature = (arr, symbol="", exchange=None, gateway_name=""))]
+    fn update_ticks_array(
+        &self,
+        py: Python,
+        arr: &Bound<'_, PyAny>,
+        symbol: &str,
+        exchange: Option<&Bound<'_, PyAny>>,
+        gateway_name: &str,
+    ) -> PyResult<usize> {
+        let ts_ns = ArrayColumn::<i64>::field(arr, "datetime_ns")?
+            .ok_or_else(|| PyValueError::new_err("数组缺少 datetime_ns 字段"))?;
+        let last_price = ArrayColumn::<f64>::field(arr, "last_price")?
+            .ok_or_else(|| PyValueError::new_err("数组缺少 last_price 字段"))?;
+        let len = ts_ns.len();
+        let volume = ArrayColumn::<f64>::field(arr, "volume")?;
+        let last_volume = ArrayColumn::<f64>::field(arr, "last_volume")?;
+        let open_interest = ArrayColumn::<f64>::field(arr, "open_interest")?;
+        let columns = [("last_price", Some(&last_price)), ("volume", volume.as_ref()),
+            ("last_volume", last_volume.as_ref()), ("open_interest", open_interest.as_ref())];
+        for (name, column) in columns {
+            if let Some(column) = column
+                && column.len() != len
+            {
+                return Err(PyValueError::new_err(format!(
+                    "字段长度不一致: datetime_ns 长度 {}，{} 长度 {}", len, name, column.len()
+                )));
+            }
+        }
+        // 可选字段缺失时取 0
+        let optional = |column: &Option<ArrayColumn<f64>>, i: usize| column.as_ref().map_or(0.0, |column| column.get(i));
+        let exchange = match exchange {
+            Some(exchange) => AnyExchange::from_py_any(exchange)?,
+            None => AnyExchange::Builtin(RustExchange::LOCAL),
+        };
+
+        // Tick 时间只保存在 Rust 侧，合成过程不创建 Python datetime，推送K线时才转换为分钟时间
+        let mut emitted = 0;
+        for i in 0..len {
+            let mut tick = RustTickData::empty(symbol.to_string(), exchange.clone(), gateway_name.to_string(), None);
+            // 与 Python datetime 一致截断到微秒，去重和状态保存的结果与逐个推送相同
+            let ts = ts_ns.get(i);
+            tick.rust_datetime = Some(DateTime::from_timestamp_nanos(ts - ts.rem_euclid(1000)).with_timezone(&self.config.tz));
+            tick.last_price = last_price.get(i);
+            tick.volume = optional(&volume, i);
+            tick.last_volume = optional(&last_volume, i);
+            tick.open_interest = optional(&open_interest, i);
+            emitted += self.update_tick_internal(py, tick)?;
+        }
+        Ok(emitted)
+    }
+
+    /// 批量推送 Tick（如历史回放），先全部转换为 RustTickData，再逐个合成，回调顺序与逐个调用 update_tick 一致；
+    /// 返回期间推送给 on_bar 的已完成K线数量，便于跟踪回放进度
+    fn update_ticks(&self, py: Python, ticks: &Bound<'_, PyAny>) -> PyResult<usize> {
+        let mut emitted = 0;
+        for tick in from_py_ticks(py, ticks)? {
+            emitted += self.update_tick_internal(py, tick)?;
+        }
+        Ok(emitted)
+    }
+
+    /// update_bar 使用 &self 而不是 &mut self，避免借用冲突；返回这根K线完成的主窗口K线（on_window_bar 照常调用），
+    /// 未完成窗口时返回 None
+    fn update_bar(&self, py: Python, bar: Bound<'_, PyAny>) -> PyResult<Option<RustBarData>> {
+        let rust_bar = RustBarData::from_py_bar(&bar)?;
+        self.update_bar_internal(py, rust_bar)
+    }
+
+    /// 批量推送K线（如历史回放），先全部转换为 RustBarData，再逐根合成，回调顺序与逐根调用 update_bar 一致
+    fn update_bars(&self, py: Python, bars: &Bound<'_, PyAny>) -> PyResult<()> {
+        for bar in from_py_bars(bars)? {
+            self.update_bar_internal(py, bar)?;
+        }
+        Ok(())
+    }
+
+    /// 强制推送进行中的分钟K线（标记 is_forced），默认保留K线自身的时间（截断到分钟）；
+    /// use_current_time=True 时改用当前时间减一分钟，供 generate_bar_event 补推缺失的分钟K线；
+    /// 传入 dt 时使用 dt（截断到分钟），优先于 use_current_time，便于回测和测试
+    #[pyo3(signature = (use_current_time=false, dt=None))]
+    fn generate(&self, py: Python, use_current_time: bool, dt: Option<&Bound<'_, PyAny>>) -> PyResult<()> {
+        let _gate = self.update_gate.enter(py)?;
+        // 先从 inner 中取出 bar，释放写锁
+        let bar_to_callback = {
+            let mut inner = self.write_inner();
+            inner.bar.take()
+        };
+
+        if let Some(bar) = bar_to_callback {
+            let mut new_bar = bar;
+
+            if let Some(dt) = dt {
+                new_bar.datetime = Some(to_py_datetime(py, &py_datetime_to_chrono(dt)?.with_timezone(&self.config.tz))?);
+            } else if use_current_time {
+                let now = self.clock_now(&self.read_inner());
+                if let Some(now) = now {
+                    new_bar.datetime = Some(to_py_datetime(py, &(now - Duration::minutes(1)))?);
+                }
+            }
+            new_bar.is_forced = true;
+
+            let trimmed_bar = trim_bar_time(py, new_bar, &self.config.tz)?;
+            self.emit_bar(py, trimmed_bar)?;
+        }
+        Ok(())
+    }
+
+    /// 程序退出前推送未走完的K线：进行中的 bar 通过 on_bar、window_bar（含 add_window 注册的窗口）
+    /// 通过对应回调推送，均标记 is_forced、is_partial；推送后状态清空，重复调用不会再推送
+    fn flush(&self, py: Python) -> PyResult<()> {
+        let _gate = self.update_gate.enter(py)?;
+        // 先推送分钟K线，on_bar 中调用 update_bar 时该K线会并入窗口K线
+        let bar = self.write_inner().bar.take();
+        if let Some(mut bar) = bar {
+            bar.is_forced = true;
+            bar.is_partial = true;
+            let trimmed_bar = trim_bar_time(py, bar, &self.config.tz)?;
+            self.emit_bar(py, trimmed_bar)?;
+        }
+
+        let (window_bar, extra_bars) = {
+            let mut inner = self.write_inner();
+            let window_bar = inner.window_bar.take();
+            inner.interval_count = 0;
+            inner.reset_count = 0;
+            inner.window_bucket = None;
+            inner.bar_push_status.clear();
+            let window_bar = window_bar.map(|raw_bar| self.finish_window_bar(py, &mut inner, raw_bar));
+            let extra_bars: Vec<(Py<PyAny>, RustBarData)> = inner.extra_windows.iter_mut()
+                .filter_map(|extra| {
+                    extra.interval_count = 0;
+                    extra.window_bar.take().map(|bar| (extra.callback.clone_ref(py), bar))
+                })
+                .collect();
+            (window_bar, extra_bars)
+        };
+
+        if let Some(mut window_bar) = window_bar {
+            window_bar.is_forced = true;
+            window_bar.is_partial = true;
+            self.emit_window_bar(py, window_bar)?;
+        }
+        for (callback, mut window_bar) in extra_bars {
+            window_bar.is_forced = true;
+            window_bar.is_partial = true;
+            self.emit_extra_window_bar(py, &callback, window_bar)?;
+        }
+        Ok(())
+    }
+
+    /// 回测结束或收盘时调用：先 flush 推送未走完的分钟K线和窗口K线（标记 is_partial），
+    /// 再清空全部合成状态（上一个Tick、砖型图/平均K线/失衡K线状态等），回调和 add_window 注册的窗口保留；
+    /// 重复调用不会再推送
+    fn finalize(&self, py: Python) -> PyResult<()> {
+        self.flush(py)?;
+        self.clear_state();
+        Ok(())
+    }
+
+    /// 丢弃全部合成状态（进行中的K线、窗口K线、上一个Tick/K线、计数和推送记录等），不推送任何K线；
+    /// 回调和 add_window 注册的窗口保留，可在回调中调用，用于换月或策略重启
+    fn reset(&self) {
+        self.clear_state();
+    }
+
+    /// 只丢弃窗口聚合状态（含 add_window 注册的窗口），进行中的分钟K线和上一个Tick保留
+    fn reset_window(&self) {
+        self.write_inner().clear_window();
+    }
+
+    /// 导出合成状态（进行中的K线、窗口K线、上一个Tick/K线、计数和推送记录等）为可 pickle 的字典，
+    /// datetime 保存为微秒时间戳；进程重启后用 set_state 恢复
+    fn get_state<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let inner = self.read_inner();
+        let optional_bar = |bar: &Option<RustBarData>| -> PyResult<Option<Bound<'py, PyDict>>> {
+            bar.as_ref().map(|bar| bar_to_state(py, bar)).transpose()
+        };
+
+        let state = PyDict::new(py);
+        state.set_item("version", STATE_VERSION)?;
+        state.set_item("interval", self.config.rule.interval.value())?;
+        state.set_item("window", self.config.rule.window)?;
+        state.set_item("interval_slice", self.config.rule.interval_slice)?;
+        state.set_item("bar", optional_bar(&inner.bar)?)?;
+        state.set_item("window_bar", optional_bar(&inner.window_bar)?)?;
+        state.set_item("last_bar", optional_bar(&inner.last_bar)?)?;
+        state.set_item("raw_window_bar", optional_bar(&inner.raw_window_bar)?)?;
+        state.set_item("last_tick", inner.last_tick.as_ref().map(|tick| tick_to_state(py, tick)).transpose()?)?;
+        state.set_item("interval_count", inner.interval_count)?;
+        state.set_item("reset_count", inner.reset_count)?;
+        state.set_item("window_open_latched", inner.window_open_latched)?;
+        state.set_item("bar_push_status", inner.bar_push_status.clone())?;
+        state.set_item("renko_close", inner.renko_close)?;
+        state.set_item("renko_direction", inner.renko_direction)?;
+        state.set_item("renko_volume", inner.renko_volume)?;
+        state.set_item("ha_prev", inner.ha_prev)?;
+        state.set_item("imbalance_sign", inner.imbalance_sign)?;
+        state.set_item("imbalance_threshold", inner.imbalance_threshold)?;
+        state.set_item("window_bucket", inner.window_bucket)?;
+
+        let extra_windows = PyList::empty(py);
+        for extra in inner.extra_windows.iter() {
+            let extra_state = PyDict::new(py);
+            extra_state.set_item("interval", extra.rule.interval.value())?;
+            extra_state.set_item("window", extra.rule.window)?;
+            extra_state.set_item("window_bar", optional_bar(&extra.window_bar)?)?;
+            extra_state.set_item("window_open_latched", extra.window_open_latched)?;
+            extra_state.set_item("interval_count", extra.interval_count)?;
+            extra_windows.append(extra_state)?;
+        }
+        state.set_item("extra_windows", extra_windows)?;
+        state.set_item("stats", inner.stats.to_dict(py)?)?;
+        state.set_item("data_time", inner.data_time.map(|dt| dt.timestamp_micros()))?;
+        state.set_item("max_tick_time", inner.max_tick_time.map(|dt| dt.timestamp_micros()))?;
+        state.set_item("callback_context", inner.callback_context.as_ref().map(|context| context.clone_ref(py)))?;
+        state.set_item("rolling_stats", inner.rolling_stats.as_ref().map(|stats| stats.to_dict(py)).transpose()?)?;
+        Ok(state)
+    }
+
+    /// 恢复 get_state 导出的状态；interval/window/interval_slice 以及 add_window 注册的窗口
+    /// 必须与当前生成器一致，否则抛出 ValueError。未设置 on_bar 时缓存的K线不受影响
+    fn set_state(&self, py: Python, state: &Bound<'_, PyDict>) -> PyResult<()> {
+        let version = state_item(state, "version")?.extract::<u32>()?;
+        if version == 0 || version > STATE_VERSION {
+            return Err(PyValueError::new_err(format!("不支持的状态版本: {}", version)));
+        }
+        let interval = RustInterval::from_py_any(&state_item(state, "interval")?)?;
+        let window = state_item(state, "window")?.extract::<usize>()?;
+        let interval_slice = state_item(state, "interval_slice")?.extract::<bool>()?;
+        if interval != self.config.rule.interval || window != self.config.rule.window || interval_slice != self.config.rule.interval_slice {
+            return Err(PyValueError::new_err(format!(
+                "状态与生成器配置不一致: 状态为 {}x{:?}（interval_slice={}），生成器为 {}x{:?}（interval_slice={}）",
+                window, interval, interval_slice, self.config.rule.window, self.config.rule.interval, self.config.rule.interval_slice
+            )));
+        }
+
+        let bar_cls = py.get_type::<RustBarData>();
+        let tick_cls = py.get_type::<RustTickData>();
+        let bar_state = |key: &str| -> PyResult<Option<RustBarData>> {
+            object_from_state(py, bar_cls.clone(), &state_item(state, key)?, version)
+        };
+
+        let mut restored = BarGeneratorInner::new(state_item(state, "imbalance_threshold")?.extract::<f64>()?);
+        restored.bar = bar_state("bar")?;
+        restored.window_bar = bar_state("window_bar")?;
+        restored.last_bar = bar_state("last_bar")?;
+        restored.raw_window_bar = bar_state("raw_window_bar")?;
+        restored.last_tick = object_from_state(py, tick_cls, &state_item(state, "last_tick")?, version)?;
+        restored.interval_count = state_item(state, "interval_count")?.extract()?;
+        restored.reset_count = state_item(state, "reset_count")?.extract()?;
+        restored.window_open_latched = state_item(state, "window_open_latched")?.extract()?;
+        restored.bar_push_status = state_item(state, "bar_push_status")?.extract()?;
+        restored.renko_close = state_item(state, "renko_close")?.extract()?;
+        restored.renko_direction = state_item(state, "renko_direction")?.extract()?;
+        restored.renko_volume = state_item(state, "renko_volume")?.extract()?;
+        restored.ha_prev = state_item(state, "ha_prev")?.extract()?;
+        restored.imbalance_sign = state_item(state, "imbalance_sign")?.extract()?;
+        restored.window_bucket = state_item(state, "window_bucket")?.extract()?;
+
+        let extra_states = state_item(state, "extra_windows")?.cast_into::<PyList>()?;
+        let mut inner = self.write_inner();
+        if extra_states.len() != inner.extra_windows.len() {
+            return Err(PyValueError::new_err(format!(
+                "状态中有 {} 个 add_window 窗口，生成器注册了 {} 个",
+                extra_states.len(), inner.extra_windows.len()
+            )));
+        }
+        let mut extra_windows = Vec::with_capacity(extra_states.len());
+        for (extra, extra_state) in inner.extra_windows.iter().zip(extra_states.iter()) {
+            let extra_state = extra_state.cast_into::<PyDict>()?;
+            let interval = RustInterval::from_py_any(&state_item(&extra_state, "interval")?)?;
+            let window = state_item(&extra_state, "window")?.extract::<usize>()?;
+            if interval != extra.rule.interval || window != extra.rule.window {
+                return Err(PyValueError::new_err(format!(
+                    "add_window 窗口不一致: 状态为 {}x{:?}，生成器为 {}x{:?}",
+                    window, interval, extra.rule.window, extra.rule.interval
+                )));
+            }
+            extra_windows.push(ExtraWindow {
+                rule: extra.rule.clone(),
+                callback: extra.callback.clone_ref(py),
+                window_bar: object_from_state(py, bar_cls.clone(), &state_item(&extra_state, "window_bar")?, version)?,
+                window_open_latched: state_item(&extra_state, "window_open_latched")?.extract()?,
+                interval_count: state_item(&extra_state, "interval_count")?.extract()?,
+            });
+        }
+        restored.extra_windows = extra_windows;
+        restored.bar_buffer = std::mem::take(&mut inner.bar_buffer);
+        restored.window_bar_buffer = std::mem::take(&mut inner.window_bar_buffer);
+        restored.stats = match state.get_item("stats")? {
+            Some(stats) => GeneratorStats::from_dict(&stats.cast_into::<PyDict>()?)?,
+            None => inner.stats,
+        };
+        let optional_time = |key: &str| -> PyResult<Option<DateTime<chrono_tz::Tz>>> {
+            Ok(match state.get_item(key)? {
+                Some(ts) => ts.extract::<Option<i64>>()?
+                    .and_then(|ts| datetime_from_state(ts, version))
+                    .map(|dt| dt.with_timezone(&self.config.tz)),
+                None => None,
+            })
+        };
+        restored.data_time = optional_time("data_time")?;
+        restored.max_tick_time = optional_time("max_tick_time")?;
+        // 状态中没有回调上下文时保留构造时传入的
+        restored.callback_context = match state.get_item("callback_context")? {
+            Some(context) if !context.is_none() => Some(context.unbind()),
+            _ => inner.callback_context.take(),
+        };
+        // attach_stats 的滚动统计同理，状态中没有时保留生成器当前的
+        restored.rolling_stats = match state.get_item("rolling_stats")? {
+            Some(stats) if !stats.is_none() => Some(RollingStats::from_dict(&stats.cast_into::<PyDict>()?)?),
+            _ => inner.rolling_stats.take(),
+        };
+        *inner = restored;
+        Ok(())
+    }
+
+    /// 构造时传入（或由 set_state 恢复）的回调上下文
+    #[getter]
+    fn callback_context(&self, py: Python) -> Option<Py<PyAny>> {
+        self.read_inner().callback_context.as_ref().map(|context| context.clone_ref(py))
+    }
+
+    /// 正在合成的分钟K线（或砖型图/区间K线）的副本，没有时返回 None；只读取状态，可在回调中调用
+    fn current_bar(&self, py: Python) -> Option<RustBarData> {
+        let inner = self.read_inner();
+        inner.bar.as_ref().map(|bar| bar.clone_with_py(py))
+    }
+
+    /// 正在合成的窗口K线的副本，没有时返回 None
+    fn current_window_bar(&self, py: Python) -> Option<RustBarData> {
+        let inner = self.read_inner();
+        inner.window_bar.as_ref().map(|bar| bar.clone_with_py(py))
+    }
+
+    /// 最近一个 Tick 的副本，没有时返回 None
+    fn last_tick(&self, py: Python) -> Option<RustTickData> {
+        let inner = self.read_inner();
+        inner.last_tick.as_ref().map(|tick| tick.clone_with_py(py))
+    }
+
+    /// 最近一根通过 update_bar 并入窗口的K线的副本，没有时返回 None
+    fn last_bar(&self, py: Python) -> Option<RustBarData> {
+        let inner = self.read_inner();
+        inner.last_bar.as_ref().map(|bar| bar.clone_with_py(py))
+    }
+
+    /// heikin_ashi=True 时 on_window_bar 收到的是平均K线，该方法返回最近一根对应的原始窗口K线
+    fn raw_window_bar(&self, py: Python) -> Option<RustBarData> {
+        let inner = self.read_inner();
+        inner.raw_window_bar.as_ref().map(|bar| bar.clone_with_py(py))
+    }
+
+    /// 取出未设置 on_bar 时缓存的全部已完成K线，并清空缓冲区
+    fn drain_bars(&self) -> Vec<RustBarData> {
+        let mut inner = self.write_inner();
+        std::mem::take(&mut inner.bar_buffer).into()
+    }
+
+    /// 未设置 on_bar 时缓存的已完成K线，clear=True 时同时清空缓冲区
+    #[pyo3(signature = (clear=false))]
+    fn get_bars(&self, py: Python, clear: bool) -> Vec<RustBarData> {
+        let mut inner = self.write_inner();
+        take_buffer(py, &mut inner.bar_buffer, clear)
+    }
+
+    /// 未设置 on_window_bar 时缓存的主窗口K线，clear=True 时同时清空缓冲区
+    #[pyo3(signature = (clear=false))]
+    fn get_window_bars(&self, py: Python, clear: bool) -> Vec<RustBarData> {
+        let mut inner = self.write_inner();
+        take_buffer(py, &mut inner.window_bar_buffer, clear)
+    }
+
+    /// 取出上次 drain 以来完成的主窗口K线（未设置 on_window_bar 时），返回迭代器；
+    /// 缓冲区在写锁内整体取出，不会与并发的 update 交错
+    fn drain(&self) -> BarIterator {
+        let bars: Vec<RustBarData> = std::mem::take(&mut self.write_inner().window_bar_buffer).into();
+        BarIterator { bars: bars.into_iter() }
+    }
+
+    /// 定时器事件中调用：进行中的分钟K线距今超过 force_generate_after_seconds 仍未收到新 Tick 时，
+    /// force_generate=True 强制推送该K线，False 只写日志；发现缺失时返回
+    /// {"vt_symbol", "bar_time", "forced"}，否则返回 None。同一根K线只处理一次
+    /// 当前时间取 now，未传入时按 clock 取系统时间或数据时钟
+    #[pyo3(signature = (_event, now=None))]
+    fn generate_bar_event<'py>(
+        &self,
+        py: Python<'py>,
+        _event: Bound<'_, PyAny>,
+        now: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<Option<Bound<'py, PyDict>>> {
+        // 检查与强制合成之间不能插入其他线程的更新
+        let _gate = self.update_gate.enter(py)?;
+        // 先检查并获取必要的数据，然后释放锁
+        // 修改：将 bar_dt 加入返回元组，使其能在作用域外使用
+        // 砖型图、区间K线不按时间切分，无需强制合成
+        if self.config.tick_bar_mode != TickBarMode::Time {
+            return Ok(None);
+        }
+
+        let (should_generate, bar_timestamp, vt_symbol, bar_dt, now_datetime) = {
+            let inner = self.read_inner();
+            
+            let Some(ref bar) = inner.bar else {
+                return Ok(None);
+            };
+            let bar_dt = bar.get_datetime_chrono(py)?
+                .ok_or_else(|| PyValueError::new_err("Bar缺少datetime"))?
+                .with_timezone(&self.config.tz);
+            let bar_timestamp = bar_dt.timestamp_millis();
+            if let Some(&status) = inner.bar_push_status.get(&bar_timestamp)
+                && status
+            {
+                return Ok(None);
+            }
+            // 显式传入的 now 优先，其次为 clock 对应的时钟；数据时钟尚未开始时不判断
+            let now_datetime = match now {
+                Some(now) => py_datetime_to_chrono(now)?.with_timezone(&self.config.tz),
+                None => match self.clock_now(&inner) {
+                    Some(now) => now,
+                    None => return Ok(None),
+                },
+            };
+            let time_delta = now_datetime.signed_duration_since(bar_dt);
+            
+            let should_generate = time_delta > self.config.force_generate_after;
+            let vt_symbol = bar.vt_symbol.clone();
+            
+            // 返回 bar_dt (DateTime<Tz> 实现了 Copy)
+            (should_generate, bar_timestamp, vt_symbol, bar_dt, now_datetime)
+        };
+        
+        if !should_generate {
+            return Ok(None);
+        }
+
+        let message = if self.config.force_generate {
+            format!("合约：{}，最新bar时间：{}，分钟bar缺失即将强制合成分钟bar", vt_symbol, bar_dt)
+        } else {
+            format!("合约：{}，最新bar时间：{}，分钟bar缺失", vt_symbol, bar_dt)
+        };
+        log_message(py, LOG_WARNING, &message, None);
+
+        // 更新状态
+        {
+            let mut inner = self.write_inner();
+            // 只保留最近 push_status_retention 分钟内的记录，避免窗口迟迟不完成时无限增长
+            let horizon = bar_timestamp - self.config.push_status_retention as i64 * 60_000;
+            inner.bar_push_status.retain(|&timestamp, _| timestamp > horizon);
+            inner.bar_push_status.insert(bar_timestamp, true);
+        }
+
+        // 调用 generate（写锁已释放），K线时间取判断所用时间的前一分钟
+        if self.config.force_generate {
+            let bar_time = to_py_datetime(py, &(now_datetime - Duration::minutes(1)))?;
+            self.generate(py, false, Some(bar_time.bind(py)))?;
+        }
+
+        let result = PyDict::new(py);
+        result.set_item("vt_symbol", vt_symbol)?;
+        result.set_item("bar_time", to_py_datetime(py, &bar_dt)?)?;
+        result.set_item("forced", self.config.force_generate)?;
+        Ok(Some(result))
+    }
+
+    /// 启动后台线程，每隔 interval_seconds 秒执行一次 generate_bar_event 的检查，不再需要从定时器事件调用；
+    /// clock="data" 时数据时钟由行情推进，后台线程不起作用，直接报错。生成器被回收或解释器退出时线程自动停止
+    #[pyo3(signature = (interval_seconds=10.0))]
+    fn start_auto_generate(slf: &Bound<'_, Self>, interval_seconds: f64) -> PyResult<()> {
+        let this = slf.borrow();
+        if this.config.clock == Clock::Data {
+            return Err(PyValueError::new_err("clock=\"data\" 时不能启动自动强制合成，回测中请使用 generate_bar_event(event, now=dt)"));
+        }
+        if !(interval_seconds.is_finite() && interval_seconds > 0.0) {
+            return Err(PyValueError::new_err(format!("interval_seconds 必须大于 0: {}", interval_seconds)));
+        }
+        let mut auto_generate = this.auto_generate.lock().unwrap_or_else(PoisonError::into_inner);
+        if auto_generate.is_some() {
+            return Err(PyValueError::new_err("自动强制合成已经启动，请先调用 stop_auto_generate"));
+        }
+
+        let generator = PyWeakrefReference::new(slf.as_any())?.unbind();
+        let signal = Arc::new(StopSignal::default());
+        let interval = std::time::Duration::from_secs_f64(interval_seconds);
+        let thread_signal = signal.clone();
+        let handle = std::thread::Builder::new()
+            .name("bar-auto-generate".to_string())
+            .spawn(move || auto_generate_loop(generator, thread_signal, interval))
+            .map_err(|e| PyValueError::new_err(format!("无法启动自动强制合成线程: {}", e)))?;
+
+        let mut signals = AUTO_GENERATE_SIGNALS.lock().unwrap_or_else(PoisonError::into_inner);
+        signals.retain(|signal| signal.strong_count() > 0);
+        signals.push(Arc::downgrade(&signal));
+        *auto_generate = Some(AutoGenerate { signal, handle });
+        Ok(())
+    }
+
+    /// 停止 start_auto_generate 启动的后台线程并等待其退出，未启动时什么也不做
+    fn stop_auto_generate(&self, py: Python) {
+        let auto_generate = self.auto_generate.lock().unwrap_or_else(PoisonError::into_inner).take();
+        let Some(auto_generate) = auto_generate else {
+            return;
+        };
+        auto_generate.signal.stop();
+        // 在后台线程的回调中调用时不能等待自己退出
+        if auto_generate.handle.thread().id() != std::thread::current().id() {
+            let _ = py.detach(|| auto_generate.handle.join());
+        }
+    }
+
+    /// 自动强制合成线程是否在运行
+    #[getter]
+    fn auto_generate_running(&self) -> bool {
+        self.auto_generate.lock().unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .is_some_and(|auto_generate| !auto_generate.handle.is_finished())
+    }
+
+    #[getter]
+    fn window(&self) -> usize {
+        self.config.rule.window
+    }
+
+    #[getter]
+    fn interval(&self) -> RustInterval {
+        self.config.rule.interval
+    }
+
+    #[getter]
+    fn interval_slice(&self) -> bool {
+        self.config.rule.interval_slice
+    }
+
+    #[getter]
+    fn on_bar(&self, py: Python) -> Option<Py<PyAny>> {
+        self.config.on_bar.as_ref().map(|f| f.clone_ref(py))
+    }
+
+    #[getter]
+    fn on_window_bar(&self, py: Python) -> Option<Py<PyAny>> {
+        self.config.on_window_bar.as_ref().map(|f| f.clone_ref(py))
+    }
+
+    /// 分钟周期下完成窗口的分钟值
+    #[getter]
+    fn target_minutes<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyFrozenSet>> {
+        PyFrozenSet::new(py, &self.config.rule.target_minutes)
+    }
+
+    /// 小时周期下完成窗口的小时值
+    #[getter]
+    fn target_hours<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyFrozenSet>> {
+        PyFrozenSet::new(py, &self.config.rule.target_hours)
+    }
+
+    /// 月周期下完成窗口的月份
+    #[getter]
+    fn target_months<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyFrozenSet>> {
+        PyFrozenSet::new(py, &self.config.rule.target_months)
+    }
+
+    /// clock="data" 时推进数据时钟，回放历史数据时按模拟时间调用；早于已推送行情时间的 dt 会被忽略
+    fn set_backtest_time(&self, dt: &Bound<'_, PyAny>) -> PyResult<()> {
+        let dt = py_datetime_to_chrono(dt)?.with_timezone(&self.config.tz);
+        let mut inner = self.write_inner();
+        inner.data_time = Some(inner.data_time.map_or(dt, |data_time| data_time.max(dt)));
+        Ok(())
+    }
+
+    /// 当前时钟时间：clock="data" 时为数据时钟（尚无行情时为 None），否则为系统时间
+    #[getter]
+    fn clock_time(&self, py: Python) -> PyResult<Option<Py<PyAny>>> {
+        self.clock_now(&self.read_inner())
+            .map(|now| to_py_datetime(py, &now))
+            .transpose()
+    }
+
+    /// 窗口网格的偏移秒数
+    #[getter]
+    fn offset_seconds(&self) -> f64 {
+        self.config.rule.offset.num_microseconds().unwrap_or(0) as f64 / 1e6
+    }
+
+    /// 切分窗口和输出K线时间所用的时区名称
+    #[getter]
+    fn tz(&self) -> &'static str {
+        self.config.tz.name()
+    }
+
+    /// 运行统计：处理和忽略的 Tick 数（rejected_* 为按原因的忽略数）、并入窗口的K线数、推送的K线和窗口K线数，
+    /// 以及最近一个 Tick 的毫秒时间戳 last_tick_time（没有时为 None）
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let inner = self.read_inner();
+        let stats = inner.stats.to_dict(py)?;
+        let last_tick_time = match inner.last_tick {
+            Some(ref tick) => tick.get_datetime_chrono(py)?.map(|dt| dt.timestamp_millis()),
+            None => None,
+        };
+        stats.set_item("last_tick_time", last_tick_time)?;
+        Ok(stats)
+    }
+
+    /// 清零统计计数，不影响合成状态
+    fn reset_stats(&self) {
+        self.write_inner().stats = GeneratorStats::default();
+    }
+
+    /// 挂载滚动统计：此后每根完成的窗口K线（不含 is_partial）计入最近 period 根的 ATR、收盘价均值和标准差，
+    /// 重复调用会丢弃已有数据重新累积
+    fn attach_stats(&self, period: usize) -> PyResult<()> {
+        if period == 0 {
+            return Err(PyValueError::new_err("period 必须大于 0"));
+        }
+        self.write_inner().rolling_stats = Some(RollingStats::new(period));
+        Ok(())
+    }
+
+    /// 卸载滚动统计
+    fn detach_stats(&self) {
+        self.write_inner().rolling_stats = None;
+    }
+
+    /// 最近 period 根窗口K线真实波幅的简单平均，未挂载或数据不足时返回 None
+    fn atr(&self) -> Option<f64> {
+        self.read_inner().rolling_stats.as_ref().and_then(RollingStats::atr)
+    }
+
+    /// 最近 period 根窗口K线收盘价的简单平均，未挂载或数据不足时返回 None
+    fn sma_close(&self) -> Option<f64> {
+        self.read_inner().rolling_stats.as_ref().and_then(RollingStats::sma_close)
+    }
+
+    /// 最近 period 根窗口K线收盘价的总体标准差，未挂载或数据不足时返回 None
+    fn std_close(&self) -> Option<f64> {
+        self.read_inner().rolling_stats.as_ref().and_then(RollingStats::std_close)
+    }
+
+    /// raise_callback_errors=False 时最近一次被忽略的回调异常，没有时返回 None
+    #[getter]
+    fn last_callback_error(&self, py: Python) -> Option<Py<PyAny>> {
+        let inner = self.read_inner();
+        inner.last_callback_error.as_ref().map(|e| e.clone_ref(py))
+    }
+
+    /// 是否已经积累了合成状态（进行中的K线、窗口K线、上一个Tick/K线或砖型图基准价）
+    fn is_active(&self) -> bool {
+        let inner = self.read_inner();
+        inner.bar.is_some()
+            || inner.window_bar.is_some()
+            || inner.last_tick.is_some()
+            || inner.last_bar.is_some()
+            || inner.renko_close.is_some()
+            || inner.extra_windows.iter().any(|extra| extra.window_bar.is_some())
+    }
+
+    /// 在同一个生成器上注册额外的窗口，update_bar 时与主窗口共用一次时间解析，完成时调用 callback
+    #[pyo3(signature = (window, interval, callback, interval_slice=true))]
+    fn add_window(
+        &self,
+        py: Python,
+        window: usize,
+        interval: &Bound<'_, PyAny>,
+        callback: Py<PyAny>,
+        interval_slice: bool,
+    ) -> PyResult<()> {
+        if window == 0 {
+            return Err(PyValueError::new_err("window must be >= 1"));
+        }
+        let rule = WindowRule::new(RustInterval::from_py_any(interval)?, window, interval_slice);
+        rule.warn_uneven_slice(py);
+        self.push_extra_window(rule, callback);
+        Ok(())
+    }
+
+    /// 假设下一根K线的时间为 dt，判断按当前配置窗口K线是否会在该K线处完成，不修改任何状态
+    fn would_complete_at(&self, py: Python, dt: &Bound<'_, PyAny>) -> PyResult<bool> {
+        let now_dt = py_datetime_to_chrono(dt)?.with_timezone(&self.config.tz);
+        let inner = self.read_inner();
+        let last_dt = match inner.last_bar {
+            Some(ref last_bar) => last_bar.get_datetime_chrono(py)?.map(|dt| dt.with_timezone(&self.config.tz)),
+            None => None,
+        };
+        Ok(self.config.rule.closes_period_before(last_dt.as_ref(), &now_dt)
+            || self.config.rule.window_decision(last_dt.as_ref(), &now_dt, inner.interval_count, None).0)
+    }
+
+    /// 用配置字典创建生成器，键与构造参数同名，便于从 YAML/JSON 配置重建；未知的键抛出 ValueError
+    #[staticmethod]
+    fn from_config(py: Python, config: Bound<'_, PyDict>) -> PyResult<Py<BarGenerator>> {
+        let cls = py.get_type::<BarGenerator>();
+        let parameters = py.import("inspect")?.call_method1("signature", (&cls,))?.getattr("parameters")?;
+        let unknown = config.keys().iter()
+            .filter(|key| !parameters.contains(key).unwrap_or(false))
+            .map(|key| key.str().map(|s| s.to_string()))
+            .collect::<PyResult<Vec<_>>>()?;
+        if !unknown.is_empty() {
+            return Err(PyValueError::new_err(format!("from_config 不支持的配置项: {}", unknown.join("、"))));
+        }
+        Ok(cls.call((), Some(&config))?.cast_into::<BarGenerator>()?.unbind())
+    }
+
+    /// 返回与构造参数同名的配置字典，可传给 from_config 重建相同配置的生成器；事件循环无法 pickle，不包含在内
+    fn get_config<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let config = self.config.to_kwargs(py)?;
+        config.set_item("callback_context", self.callback_context(py))?;
+        Ok(config)
+    }
+
+    /// 计算 datetime 所属交易日，可脱离生成器单独使用
+    #[staticmethod]
+    fn trading_day<'py>(py: Python<'py>, dt: &Bound<'py, PyAny>, exchange: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyDate>> {
+        let rust_exchange = AnyExchange::from_py_any(exchange)?;
+        let local_dt = py_datetime_to_chrono(dt)?;
+        let day = trading_day_of(local_dt.naive_local(), &rust_exchange);
+        PyDate::new(py, day.year(), day.month() as u8, day.day() as u8)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("BarGenerator(interval={:?}, window={})", self.config.rule.interval, self.config.rule.window)
+    }
+
+    /// 仅供测试：持有写锁时 panic，使合成状态的锁中毒，用于验证中毒后仍能继续合成
+    fn _poison_lock(&self) {
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(|_| {}));
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _guard = self.inner.write();
+            panic!("poison");
+        }));
+        std::panic::set_hook(hook);
+    }
+}
+
+impl BarGenerator {
+    /// 由合成状态和配置创建生成器，构造函数与 fork 共用
+    fn with_config(inner: BarGeneratorInner, config: BarGeneratorConfig) -> Self {
+        BarGenerator {
+            inner: RwLock::new(inner),
+            config,
+            async_errors: Arc::new(Mutex::new(Vec::new())),
+            update_gate: UpdateGate::default(),
+            auto_generate: Mutex::new(None),
+        }
+    }
+
+    /// 获取合成状态的读锁，锁中毒时恢复而不是 panic
+    fn read_inner(&self) -> RwLockReadGuard<'_, BarGeneratorInner> {
+        read_lock(&self.inner, "BarGenerator")
+    }
+
+    /// 获取合成状态的写锁，锁中毒时恢复而不是 panic
+    fn write_inner(&self) -> RwLockWriteGuard<'_, BarGeneratorInner> {
+        write_lock(&self.inner, "BarGenerator")
+    }
+
+    /// 清空全部合成状态，保留 add_window 注册的窗口、K线缓冲区和统计计数
+    fn clear_state(&self) {
+        let mut inner = self.write_inner();
+        let extra_windows = std::mem::take(&mut inner.extra_windows);
+        let bar_buffer = std::mem::take(&mut inner.bar_buffer);
+        let window_bar_buffer = std::mem::take(&mut inner.window_bar_buffer);
+        let stats = inner.stats;
+        let rolling_stats = inner.rolling_stats.take();
+        let callback_context = inner.callback_context.take();
+        *inner = BarGeneratorInner::new(self.initial_imbalance_threshold());
+        inner.callback_context = callback_context;
+        inner.extra_windows = extra_windows;
+        inner.bar_buffer = bar_buffer;
+        inner.window_bar_buffer = window_bar_buffer;
+        inner.stats = stats;
+        inner.rolling_stats = rolling_stats;
+        inner.clear_window();
+    }
+
+    /// 构造时设置的失衡K线阈值（启用 EWMA 后 inner 中的阈值会随行情调整）
+    fn initial_imbalance_threshold(&self) -> f64 {
+        match self.config.tick_bar_mode {
+            TickBarMode::Imbalance(threshold) => threshold,
+            _ => 0.0,
+        }
+    }
+
+    /// generate_bar_event 使用的当前时间，数据时钟尚未开始时返回 None
+    fn clock_now(&self, inner: &BarGeneratorInner) -> Option<DateTime<chrono_tz::Tz>> {
+        match self.config.clock {
+            Clock::Wall => Some(chrono::Utc::now().with_timezone(&self.config.tz)),
+            Clock::Data => inner.data_time,
+        }
+    }
+
+    /// clock="data" 时用行情时间推进数据时钟，数据时钟只前进不后退
+    fn advance_data_clock(&self, inner: &mut BarGeneratorInner, dt: DateTime<chrono_tz::Tz>) {
+        if self.config.clock == Clock::Data && inner.data_time.is_none_or(|data_time| dt > data_time) {
+            inner.data_time = Some(dt);
+        }
+    }
+
+    /// 按 Tick 时间推进数据时钟并检查过期和乱序，返回需要忽略的原因
+    fn check_tick_time(&self, py: Python, tick: &RustTickData) -> PyResult<Option<TickRejection>> {
+        if self.config.clock != Clock::Data && self.config.out_of_order == OutOfOrderPolicy::Accept && self.config.max_tick_age.is_none() {
+            return Ok(None);
+        }
+        let Some(tick_dt) = tick.get_datetime_chrono(py)? else {
+            return Ok(None);
+        };
+        let tick_dt = tick_dt.with_timezone(&self.config.tz);
+        let mut inner = self.write_inner();
+        // 数据时钟取此前行情的最新时间，断线重连后按顺序补推的 Tick 不会被判为过期
+        if let Some(max_age) = self.config.max_tick_age
+            && let Some(now) = self.clock_now(&inner)
+            && now - tick_dt > max_age
+        {
+            return Ok(Some(TickRejection::Stale));
+        }
+        self.advance_data_clock(&mut inner, tick_dt);
+        if self.config.out_of_order == OutOfOrderPolicy::Accept {
+            return Ok(None);
+        }
+        match inner.max_tick_time {
+            // 与最新 Tick 时间相同的 Tick 照常合成
+            Some(max_tick_time) if tick_dt < max_tick_time => {
+                if self.config.out_of_order == OutOfOrderPolicy::Raise {
+                    inner.stats.record_rejection(TickRejection::OutOfOrder);
+                    return Err(PyValueError::new_err(format!(
+                        "Tick时间早于已收到的最新Tick: {} < {}", tick_dt, max_tick_time
+                    )));
+                }
+                Ok(Some(TickRejection::OutOfOrder))
+            }
+            _ => {
+                inner.max_tick_time = Some(tick_dt);
+                Ok(None)
+            }
+        }
+    }
+
+    /// 同一快照被重复推送：时间、最新价、累计成交量都与上一个 Tick 相同
+    fn is_duplicate_tick(&self, py: Python, tick: &RustTickData) -> bool {
+        let inner = self.read_inner();
+        match inner.last_tick {
+            Some(ref last_tick) => {
+                last_tick.last_price == tick.last_price
+                    && last_tick.volume == tick.volume
+                    && match (&last_tick.datetime, &tick.datetime) {
+                        (Some(a), Some(b)) => same_datetime(py, a, b),
+                        _ => last_tick.rust_datetime.is_some() && last_tick.rust_datetime == tick.rust_datetime,
                     }
-                    _ => bar_dt,
+            }
+            None => false,
+        }
+    }
+
+    /// 设置了 max_price_change_pct 时，最新价相对上一个 Tick 的涨跌幅（百分比）是否超过该值；
+    /// 紧接着的 Tick 与被拒绝的价格接近时视为行情确实跳变，不再拒绝
+    fn is_price_spike(&self, tick: &RustTickData) -> bool {
+        let Some(max_pct) = self.config.max_price_change_pct else {
+            return false;
+        };
+        let change_pct = |from: f64| ((tick.last_price - from) / from).abs() * 100.0;
+        let mut inner = self.write_inner();
+        let spike = match inner.last_tick {
+            Some(ref last_tick) if last_tick.last_price != 0.0 => change_pct(last_tick.last_price) > max_pct,
+            _ => false,
+        };
+        let confirmed = spike && inner.spike_price.is_some_and(|price| change_pct(price) <= max_pct);
+        inner.spike_price = (spike && !confirmed).then_some(tick.last_price);
+        spike && !confirmed
+    }
+
+    /// 记录被忽略的 Tick，并调用 on_tick_rejected(tick, reason)；最新价为 0 的 Tick 只计数
+    fn reject_tick(&self, py: Python, tick: RustTickData, rejection: TickRejection) -> PyResult<()> {
+        self.write_inner().stats.record_rejection(rejection);
+        match self.config.on_tick_rejected {
+            Some(ref callback) if rejection != TickRejection::ZeroPrice => {
+                let result = callback.call1(py, (tick, rejection.reason())).map(drop);
+                self.check_callback_result(py, result, "on_tick_rejected")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 按相同配置（含 add_window 注册的窗口）创建一个全新状态的生成器
+    fn fork(&self, py: Python) -> BarGenerator {
+        let mut inner = BarGeneratorInner::new(self.initial_imbalance_threshold());
+        inner.extra_windows = self.read_inner().extra_windows.iter()
+            .map(|extra| ExtraWindow {
+                rule: extra.rule.clone(),
+                callback: extra.callback.clone_ref(py),
+                window_bar: None,
+                window_open_latched: false,
+                interval_count: 0,
+            })
+            .collect();
+        inner.callback_context = self.callback_context(py);
+        BarGenerator::with_config(inner, self.config.clone_ref(py))
+    }
+
+    fn push_extra_window(&self, rule: WindowRule, callback: Py<PyAny>) {
+        let mut inner = self.write_inner();
+        inner.extra_windows.push(ExtraWindow {
+            rule: rule.with_offset(self.config.rule.offset)
+                .with_day_start_hour(self.config.rule.day_start_hour)
+                .with_anchor(self.config.rule.anchor)
+                .with_label(self.config.rule.label),
+            callback,
+            window_bar: None,
+            window_open_latched: false,
+            interval_count: 0,
+        });
+    }
+
+    /// 释放 GIL 合成 len 根不含 Python 对象的K线（row 按下标生成），完成后写回状态并按顺序回调，
+    /// 供 update_bars_numpy、update_bars_df 共用。逐根处理与 update_bar 相同：缺口报告、补齐、重复与乱序处理
+    fn update_plain_bars<F>(
+        &self,
+        py: Python,
+        len: usize,
+        row: F,
+        template: &BarTemplate,
+        return_bars: bool,
+    ) -> PyResult<Option<Vec<RustBarData>>>
+    where
+        F: Fn(usize) -> Result<PlainBar, String> + Sync,
+    {
+        let _gate = self.update_gate.enter(py)?;
+        self.check_bar_input()?;
+        if self.filter_out(&template.symbol, &template.exchange, len) {
+            return Ok(return_bars.then(Vec::new));
+        }
+        // 取出当前状态，合成结束后写回，与逐根 update_bar 的状态衔接
+        let mut run = {
+            let inner = self.read_inner();
+            let (last_dt, last_bar) = match inner.last_bar {
+                Some(ref last_bar) => (
+                    last_bar.get_datetime_chrono(py)?.map(|dt| dt.with_timezone(&self.config.tz)),
+                    PlainBar::from_bar(py, last_bar)?,
+                ),
+                None => (None, None),
+            };
+            let mut windows = vec![PlainWindow {
+                rule: self.config.rule.clone(),
+                extra_index: None,
+                window_bar: match inner.window_bar {
+                    Some(ref bar) => PlainBar::from_bar(py, bar)?,
+                    None => None,
+                },
+                open_latched: inner.window_open_latched,
+                interval_count: inner.interval_count,
+            }];
+            for (index, extra) in inner.extra_windows.iter().enumerate() {
+                windows.push(PlainWindow {
+                    rule: extra.rule.clone(),
+                    extra_index: Some(index),
+                    window_bar: match extra.window_bar {
+                        Some(ref bar) => PlainBar::from_bar(py, bar)?,
+                        None => None,
+                    },
+                    open_latched: extra.window_open_latched,
+                    interval_count: extra.interval_count,
+                });
+            }
+            let snapshot = match inner.window_snapshot {
+                Some(ref snapshot) => {
+                    let mut plain_windows = Vec::with_capacity(snapshot.windows.len());
+                    for (window_bar, open_latched, interval_count) in snapshot.windows.iter() {
+                        let window_bar = match window_bar {
+                            Some(bar) => PlainBar::from_bar(py, bar)?,
+                            None => None,
+                        };
+                        plain_windows.push((window_bar, *open_latched, *interval_count));
+                    }
+                    Some(WindowSnapshot { last_dt: snapshot.last_dt, windows: plain_windows })
+                }
+                None => None,
+            };
+            PlainRun {
+                last_dt,
+                last_bar,
+                windows,
+                snapshot,
+                last_gap_end: inner.last_gap_end,
+                events: Vec::new(),
+                bars_updated: 0,
+                gaps_detected: 0,
+                missing_bars: 0,
+            }
+        };
+
+        let result: Result<(), String> = py.detach(|| {
+            for index in 0..len {
+                self.push_plain_bar(&mut run, row(index)?, index)?;
+            }
+            Ok(())
+        });
+        result.map_err(PyValueError::new_err)?;
+        if self.config.drop_partial_windows {
+            run.events.retain(|event| !matches!(event, PlainEvent::Window(_, _, window_bar) if window_bar.is_partial));
+        }
+
+        // 写回状态，并准备回调；gaps 记录每个缺口应在第几根窗口K线之前回调
+        let mut to_callback: Vec<(Option<Py<PyAny>>, RustBarData)> = Vec::with_capacity(run.events.len());
+        let mut gaps = Vec::new();
+        {
+            let mut inner = self.write_inner();
+            inner.stats.bars_updated += run.bars_updated;
+            inner.stats.gaps_detected += run.gaps_detected;
+            inner.stats.missing_bars += run.missing_bars;
+            inner.last_gap_end = run.last_gap_end;
+            if run.bars_updated > 0
+                && let Some(dt) = run.last_dt
+            {
+                self.advance_data_clock(&mut inner, dt);
+            }
+            inner.window_snapshot = match run.snapshot {
+                Some(saved) => {
+                    let mut saved_windows = Vec::with_capacity(saved.windows.len());
+                    for (window, (window_bar, open_latched, interval_count)) in run.windows.iter().zip(saved.windows) {
+                        let window_bar = match window_bar {
+                            Some(bar) => Some(bar.to_bar(py, template, window.rule.interval)?),
+                            None => None,
+                        };
+                        saved_windows.push((window_bar, open_latched, interval_count));
+                    }
+                    Some(WindowSnapshot { last_dt: saved.last_dt, windows: saved_windows })
+                }
+                None => None,
+            };
+            for window in run.windows {
+                let window_bar = match window.window_bar {
+                    Some(bar) => Some(bar.to_bar(py, template, window.rule.interval)?),
+                    None => None,
                 };
+                match window.extra_index {
+                    None => {
+                        inner.window_bar = window_bar;
+                        inner.window_open_latched = window.open_latched;
+                        inner.interval_count = window.interval_count;
+                    }
+                    Some(index) => {
+                        let extra = &mut inner.extra_windows[index];
+                        extra.window_bar = window_bar;
+                        extra.window_open_latched = window.open_latched;
+                        extra.interval_count = window.interval_count;
+                    }
+                }
+            }
+            // last_bar 之后会被 update_bar 读回，时间带上 tzinfo，不按主机本地时区解释
+            if run.bars_updated > 0
+                && let Some(last) = run.last_bar
+            {
+                let mut last_bar = last.to_bar(py, template, RustInterval::MINUTE)?;
+                last_bar.datetime = Some(to_py_datetime_fixed(py, &last.dt, &mut None)?);
+                inner.last_bar = Some(last_bar);
+            }
+            for event in run.events {
+                match event {
+                    PlainEvent::Gap(last_dt, new_dt, missing) => gaps.push((to_callback.len(), last_dt, new_dt, missing)),
+                    PlainEvent::Window(_, None, plain) => {
+                        let raw_bar = plain.to_bar(py, template, self.config.rule.interval)?;
+                        inner.reset_count = 0;
+                        inner.bar_push_status.clear();
+                        to_callback.push((None, self.finish_window_bar(py, &mut inner, raw_bar)));
+                    }
+                    PlainEvent::Window(_, Some(index), plain) => {
+                        let extra = &inner.extra_windows[index];
+                        let bar = plain.to_bar(py, template, extra.rule.interval)?;
+                        to_callback.push((Some(extra.callback.clone_ref(py)), bar));
+                    }
+                }
+            }
+        }
+
+        let vt_symbol = make_vt_symbol(&template.symbol, &template.exchange, &template.gateway_name);
+        let mut gaps = gaps.into_iter().peekable();
+        let mut returned = Vec::new();
+        for (position, (callback, window_bar)) in to_callback.into_iter().enumerate() {
+            while let Some((_, last_dt, new_dt, missing)) = gaps.next_if(|gap| gap.0 <= position) {
+                self.call_gap(py, &vt_symbol, &last_dt, &new_dt, missing)?;
+            }
+            match callback {
+                Some(callback) => self.emit_extra_window_bar(py, &callback, window_bar)?,
+                None if return_bars => returned.push(window_bar),
+                None => self.emit_window_bar(py, window_bar)?,
+            }
+        }
+        for (_, last_dt, new_dt, missing) in gaps {
+            self.call_gap(py, &vt_symbol, &last_dt, &new_dt, missing)?;
+        }
+        Ok(return_bars.then_some(returned))
+    }
+
+    /// update_plain_bars 中处理一根K线，顺序与 update_bar_internal 一致：
+    /// 先报告缺口、补齐短缺口，再处理重复与乱序，最后更新各窗口
+    fn push_plain_bar(&self, run: &mut PlainRun, bar: PlainBar, index: usize) -> Result<(), String> {
+        let dt = bar.dt;
+        if let Some(last_dt) = run.last_dt
+            && let Some((gap_start, gap_end, missing)) = self.detect_gap(last_dt, dt, 1)
+            && run.last_gap_end != Some(gap_end)
+        {
+            run.last_gap_end = Some(gap_end);
+            run.gaps_detected += 1;
+            run.missing_bars += missing as u64;
+            run.events.push(PlainEvent::Gap(gap_start, gap_end, missing));
+        }
+        if self.config.fill_window_gaps > 0
+            && let (Some(last_dt), Some(last_bar)) = (run.last_dt, run.last_bar)
+        {
+            let missing = (dt - last_dt).num_minutes() - 1;
+            if missing >= 1 && missing <= self.config.fill_window_gaps as i64 {
+                for minute in 1..=missing {
+                    let filler = PlainBar {
+                        dt: last_dt + Duration::minutes(minute),
+                        open_price: last_bar.close_price,
+                        high_price: last_bar.close_price,
+                        low_price: last_bar.close_price,
+                        volume: 0.0,
+                        trade_count: 0,
+                        touched_limit_up: false,
+                        touched_limit_down: false,
+                        ..last_bar
+                    };
+                    self.push_plain_bar(run, filler, index)?;
+                }
+            }
+        }
+
+        if run.last_dt == Some(dt) {
+            match self.config.on_duplicate_bar {
+                DuplicateBarPolicy::Skip => return Ok(()),
+                DuplicateBarPolicy::Add => {}
+                DuplicateBarPolicy::Replace => match run.snapshot.take() {
+                    Some(saved) => {
+                        for (window, (window_bar, open_latched, interval_count)) in run.windows.iter_mut().zip(saved.windows) {
+                            window.window_bar = window_bar;
+                            window.open_latched = open_latched;
+                            window.interval_count = interval_count;
+                        }
+                        run.last_dt = saved.last_dt;
+                    }
+                    None => return Ok(()),
+                },
+            }
+        }
+        if self.config.strict_bar_order
+            && let Some(last) = run.last_dt
+            && dt <= last
+        {
+            return Err(format!("第{}根bar时间未严格递增: {} <= 上一根 {}", index, dt, last));
+        }
+        if self.config.on_duplicate_bar == DuplicateBarPolicy::Replace {
+            run.snapshot = Some(WindowSnapshot {
+                last_dt: run.last_dt,
+                windows: run.windows.iter().map(|w| (w.window_bar, w.open_latched, w.interval_count)).collect(),
+            });
+        }
+        let start = run.events.len();
+        for window in run.windows.iter_mut() {
+            if window.rule.closes_period_before(run.last_dt.as_ref(), &dt)
+                && let Some(window_bar) = window.window_bar.take()
+            {
+                run.events.push(PlainEvent::Window(window.rule.span_minutes(), window.extra_index, window_bar));
+            }
+            window.rule.update_plain_window_bar(&mut window.window_bar, &mut window.open_latched, &bar);
+            let (finished, interval_count) = window.rule.window_decision(run.last_dt.as_ref(), &dt, window.interval_count, None);
+            window.interval_count = interval_count;
+            if finished && let Some(window_bar) = window.window_bar.take() {
+                window.interval_count = 0;
+                run.events.push(PlainEvent::Window(window.rule.span_minutes(), window.extra_index, window_bar));
+            }
+        }
+        if run.events.len() > start {
+            run.snapshot = None;
+        }
+        // 同一根K线完成多个窗口时，按窗口时长从小到大回调
+        run.events[start..].sort_by_key(|event| match event {
+            PlainEvent::Window(span, _, _) => *span,
+            PlainEvent::Gap(..) => 0,
+        });
+        run.last_dt = Some(dt);
+        run.last_bar = Some(bar);
+        run.bars_updated += 1;
+        Ok(())
+    }
+
+    /// 处理一个 Tick，返回本次推送给 on_bar 的已完成K线数量
+    /// 推送单个 Tick，返回它是否开始了一根新的分钟K线
+    fn update_tick_opened(&self, py: Python, tick: RustTickData) -> PyResult<bool> {
+        let _gate = self.update_gate.enter(py)?;
+        let time_bar = self.config.tick_bar_mode == TickBarMode::Time;
+        self.write_inner().new_bar_opened = false;
+        self.update_tick_internal(py, tick)?;
+        Ok(time_bar && self.read_inner().new_bar_opened)
+    }
+
+    /// window_from_tick=True 时窗口K线直接由 Tick 合成，与 update_* 推送的K线共用窗口状态，
+    /// 再推送K线会重复计算成交量，直接报错
+    fn check_bar_input(&self) -> PyResult<()> {
+        if self.config.window_from_tick {
+            return Err(PyValueError::new_err(
+                "window_from_tick=True 时窗口K线直接由 Tick 合成，不能再调用 update_bar 等方法推送K线"
+            ));
+        }
+        Ok(())
+    }
+
+    /// 行情的合约代码、交易所与构造时的 symbol/exchange 不一致时把 count 条行情计入过滤数并返回 true
+    fn filter_out(&self, symbol: &str, exchange: &AnyExchange, count: usize) -> bool {
+        let mismatched = self.config.symbol_filter.as_ref().is_some_and(|expected| expected != symbol)
+            || self.config.exchange_filter.as_ref().is_some_and(|expected| expected != exchange);
+        if mismatched {
+            self.write_inner().stats.filtered_count += count as u64;
+        }
+        mismatched
+    }
+
+    fn update_tick_internal(&self, py: Python, mut tick: RustTickData) -> PyResult<usize> {
+        let _gate = self.update_gate.enter(py)?;
+        if self.filter_out(&tick.symbol, &tick.exchange, 1) {
+            return Ok(0);
+        }
+        self.config.price_source.apply(&mut tick);
+        let rejection = match TickRejection::check(&tick) {
+            Some(rejection) => Some(rejection),
+            None if self.config.dedup_ticks && self.is_duplicate_tick(py, &tick) => Some(TickRejection::Duplicate),
+            None if self.is_price_spike(&tick) => Some(TickRejection::Spike),
+            None => self.check_tick_time(py, &tick)?,
+        };
+        if let Some(rejection) = rejection {
+            self.reject_tick(py, tick, rejection)?;
+            return Ok(0);
+        }
+
+        let emitted = match self.config.tick_bar_mode {
+            TickBarMode::Time => self.update_time_bar(py, tick),
+            TickBarMode::Renko(brick_size) => self.update_renko_bar(py, tick, brick_size),
+            TickBarMode::Range(range_size) => self.update_range_bar(py, tick, range_size),
+            TickBarMode::Imbalance(_) => self.update_imbalance_bar(py, tick),
+        }?;
+        self.emit_bar_update(py)?;
+        Ok(emitted)
+    }
+
+    /// 用正在合成的K线副本调用 on_bar_update，距上次调用不足 bar_update_interval 时跳过
+    fn emit_bar_update(&self, py: Python) -> PyResult<()> {
+        let Some(ref callback) = self.config.on_bar_update else {
+            return Ok(());
+        };
+        let bar = {
+            let mut inner = self.write_inner();
+            let now = std::time::Instant::now();
+            if inner.last_bar_update.is_some_and(|last| now.duration_since(last) < self.config.bar_update_interval) {
+                return Ok(());
+            }
+            let Some(ref bar) = inner.bar else {
+                return Ok(());
+            };
+            let bar = bar.clone_with_py(py);
+            inner.last_bar_update = Some(now);
+            bar
+        };
+        let bar = trim_bar_time(py, bar, &self.config.tz)?;
+        self.call_callback(py, callback, bar, "on_bar_update")
+    }
+
+    /// 用 Tick 创建新的K线，开高低收均取最新价，成交量由调用方累加
+    fn bar_from_tick(py: Python, tick: &RustTickData, interval: RustInterval) -> RustBarData {
+        RustBarData {
+            symbol: tick.symbol.clone(),
+            exchange: tick.exchange.clone(),
+            datetime: tick.datetime.as_ref().map(|dt| dt.clone_ref(py)),
+            rust_datetime: tick.rust_datetime,
+            interval: Some(interval),
+            volume: 0.0,
+            open_interest: tick.open_interest,
+            open_price: tick.last_price,
+            high_price: tick.last_price,
+            low_price: tick.last_price,
+            close_price: tick.last_price,
+            buy_volume: 0.0,
+            sell_volume: 0.0,
+            trade_count: 1,
+            sub_bar_count: 0,
+            expected_sub_bars: 0,
+            gateway_name: tick.gateway_name.clone(),
+            vt_symbol: tick.vt_symbol.clone(),
+            is_forced: false,
+            is_partial: false,
+            touched_limit_up: tick.touched_limit_up(),
+            touched_limit_down: tick.touched_limit_down(),
+        }
+    }
+
+    /// 将 Tick 合并进进行中的K线：更新最高最低价、收盘价、时间、持仓量和涨跌停标记
+    fn merge_tick_into_bar(py: Python, bar: &mut RustBarData, tick: &RustTickData) {
+        bar.latch_open(tick.last_price);
+        bar.high_price = bar.high_price.max(tick.last_price);
+        bar.low_price = bar.low_price.min(tick.last_price);
+        bar.close_price = tick.last_price;
+        bar.datetime = tick.datetime.as_ref().map(|dt| dt.clone_ref(py));
+        bar.rust_datetime = tick.rust_datetime;
+        bar.open_interest = tick.open_interest;
+        bar.touched_limit_up |= tick.touched_limit_up();
+        bar.touched_limit_down |= tick.touched_limit_down();
+        bar.trade_count += 1;
+    }
+
+    /// Tick 带来的成交量：累计模式为相对上一个 Tick 的变化（没有上一个 Tick 时为 0），逐笔模式为 last_volume
+    #[inline(always)]
+    fn tick_volume_change(&self, inner: &mut BarGeneratorInner, tick: &RustTickData) -> f64 {
+        let last_volume = inner.last_tick.as_ref().map(|last_tick| last_tick.volume);
+        // 累计成交量回落超过容差视为计数器重置（新交易时段或交易所更正），本 Tick 的累计成交量即为变化量
+        if let Some(tolerance) = self.config.volume_reset_tolerance
+            && let Some(last_volume) = last_volume
+            && self.config.volume_mode.is_cumulative(tick)
+            && last_volume - tick.volume > tolerance
+        {
+            inner.stats.volume_resets += 1;
+            return tick.volume;
+        }
+        self.config.volume_mode.volume_change(last_volume, tick)
+    }
+
+    /// 按分钟切分的时间K线
+    fn update_time_bar(&self, py: Python, tick: RustTickData) -> PyResult<usize> {
+        let tick_dt = tick.get_datetime_chrono(py)?
+            .ok_or_else(|| PyValueError::new_err("Tick缺少datetime"))?
+            .with_timezone(&self.config.tz);
+
+        // Tick 直接合成小时窗口时，计算 Tick 所属窗口的起始时间
+        let tick_bucket = if self.config.window_from_tick {
+            let hour = tick_dt.hour() - tick_dt.hour() % self.config.rule.window as u32;
+            let start = tick_dt.with_hour(hour).and_then(|dt| dt.with_minute(0))
+                .and_then(|dt| dt.with_second(0)).and_then(|dt| dt.with_nanosecond(0))
+                .ok_or_else(|| PyValueError::new_err("无法计算小时窗口起始时间"))?;
+            Some(start)
+        } else {
+            None
+        };
+
+        // 计算成交量变化和检查新分钟，只在这一段持有写锁
+        let (volume_change, new_minute, old_bar, old_book, old_window_bar, bar_volume_added, window_volume_added) = {
+            let mut inner = self.write_inner();
+            
+            let volume_change = self.tick_volume_change(&mut inner, &tick);
+
+            let new_minute = if let Some(ref bar) = inner.bar {
+                let bar_dt = bar.get_datetime_chrono(py)?
+                    .ok_or_else(|| PyValueError::new_err("Bar缺少datetime"))?
+                    .with_timezone(&self.config.tz);
+                // 比较截断到分钟的时间戳，而不只是分钟数，长时间无成交后同一分钟数的 Tick 不会并入旧K线
+                bar_dt.timestamp().div_euclid(60) != tick_dt.timestamp().div_euclid(60)
+            } else {
+                true
+            };
+
+            let to_old_bar = self.config.boundary_volume == BoundaryVolume::OldBar;
+            let mut old_bar = if new_minute {
+                inner.bar.take()
+            } else {
+                None
+            };
+            // 新 Tick 写入 last_tick 之前，last_tick 即旧K线所在分钟的最后一个 Tick
+            let old_book = match (&old_bar, &self.config.on_bar_book, &inner.last_tick) {
+                (Some(_), Some(_), Some(last_tick)) => Some(last_tick.book_levels()),
+                _ => None,
+            };
+            // boundary_volume="old_bar" 时成交量并入即将完成的K线，新K线不再累加
+            let bar_volume_added = to_old_bar && match old_bar {
+                Some(ref mut bar) => {
+                    bar.volume += volume_change;
+                    true
+                }
+                None => false,
+            };
+
+            let mut window_volume_added = false;
+            let old_window_bar = match tick_bucket {
+                Some(start) if inner.window_bucket != Some(start.timestamp()) => {
+                    inner.window_bucket = Some(start.timestamp());
+                    inner.window_bar.take().map(|mut raw_bar| {
+                        if to_old_bar {
+                            raw_bar.volume += volume_change;
+                            window_volume_added = true;
+                        }
+                        self.finish_window_bar(py, &mut inner, raw_bar)
+                    })
+                }
+                _ => None,
+            };
+
+            (volume_change, new_minute, old_bar, old_book, old_window_bar, bar_volume_added, window_volume_added)
+        };  // inner 写锁在这里释放
+
+        // 处理旧 bar 的回调（在写锁释放后），回调异常在状态更新完成后再抛出
+        let emitted = old_bar.is_some() as usize;
+        let gap_from = match old_bar {
+            Some(ref bar) => bar.get_datetime_chrono(py)?,
+            None => None,
+        };
+        let mut callback_result = Ok(());
+        if let Some(bar_data) = old_bar {
+            let trimmed_bar = trim_bar_time(py, bar_data, &self.config.tz)?;
+            let closed_bar = self.config.on_bar_with_tick.as_ref().map(|_| trimmed_bar.clone_with_py(py));
+            let book_bar = old_book.map(|book| (trimmed_bar.clone_with_py(py), book));
+            callback_result = self.emit_bar(py, trimmed_bar);
+            if let (Some(callback), Some(closed_bar)) = (self.config.on_bar_with_tick.as_ref(), closed_bar) {
+                let result = self.call_bar_with_tick(py, callback, closed_bar, &tick);
+                callback_result = callback_result.and(result);
+            }
+            if let (Some(callback), Some((book_bar, book))) = (self.config.on_bar_book.as_ref(), book_bar) {
+                let result = self.call_bar_book(py, callback, book_bar, book);
+                callback_result = callback_result.and(result);
+            }
+        }
+        if let Some(window_bar) = old_window_bar {
+            let result = self.emit_window_bar(py, window_bar);
+            callback_result = callback_result.and(result);
+        }
+        if let Some(last_dt) = gap_from {
+            let result = self.report_gap(py, &tick.vt_symbol, last_dt.with_timezone(&self.config.tz), tick_dt, 1);
+            callback_result = callback_result.and(result);
+        }
+
+        // 重新获取写锁，创建或更新 bar
+        {
+            let mut inner = self.write_inner();
+            
+            inner.new_bar_opened = new_minute;
+            if new_minute {
+                inner.bar = Some(Self::bar_from_tick(py, &tick, RustInterval::MINUTE));
+            } else if let Some(ref mut bar) = inner.bar {
+                Self::merge_tick_into_bar(py, bar, &tick);
+            }
+
+            if !bar_volume_added && let Some(ref mut bar) = inner.bar {
+                bar.volume += volume_change;
+            }
+
+            if let Some(start) = tick_bucket {
+                match inner.window_bar {
+                    Some(ref mut window_bar) => {
+                        window_bar.high_price = window_bar.high_price.max(tick.last_price);
+                        window_bar.low_price = window_bar.low_price.min(tick.last_price);
+                        window_bar.close_price = tick.last_price;
+                        window_bar.open_interest = tick.open_interest;
+                        window_bar.touched_limit_up |= tick.touched_limit_up();
+                        window_bar.touched_limit_down |= tick.touched_limit_down();
+                        window_bar.trade_count += 1;
+                    }
+                    None => {
+                        let mut window_bar = Self::bar_from_tick(py, &tick, RustInterval::HOUR);
+                        window_bar.datetime = Some(PyDateTime::new(
+                            py,
+                            start.year(),
+                            start.month() as u8,
+                            start.day() as u8,
+                            start.hour() as u8,
+                            0,
+                            0,
+                            0,
+                            None
+                        )?.into_any().unbind());
+                        inner.window_bar = Some(window_bar);
+                    }
+                }
+                if !window_volume_added && let Some(ref mut window_bar) = inner.window_bar {
+                    window_bar.volume += volume_change;
+                }
+            }
+
+            inner.last_tick = Some(tick);
+            inner.stats.ticks_processed += 1;
+        }
+        
+        callback_result.map(|_| emitted)
+    }
+
+    /// 砖型图：价格相对上一块砖收盘价移动满一个砖块时生成新砖，反转需要移动两个砖块；
+    /// 一个 Tick 跨越多个砖块时依次生成多块砖，成交量记在该 Tick 完成的最后一块砖上
+    fn update_renko_bar(&self, py: Python, tick: RustTickData, brick_size: f64) -> PyResult<usize> {
+        let tolerance = brick_size * 1e-9;
+        let price = tick.last_price;
+
+        let bricks = {
+            let mut inner = self.write_inner();
+
+            let volume_change = self.tick_volume_change(&mut inner, &tick);
+            inner.renko_volume += volume_change;
+
+            let mut bricks: Vec<RustBarData> = Vec::new();
+            if let Some(mut close) = inner.renko_close {
+                loop {
+                    let direction = inner.renko_direction;
+                    let up_steps = if direction < 0 { 2.0 } else { 1.0 };
+                    let down_steps = if direction > 0 { 2.0 } else { 1.0 };
+
+                    let (open, new_close, new_direction) = if price >= close + up_steps * brick_size - tolerance {
+                        let open = if direction < 0 { close + brick_size } else { close };
+                        (open, open + brick_size, 1)
+                    } else if price <= close - down_steps * brick_size + tolerance {
+                        let open = if direction > 0 { close - brick_size } else { close };
+                        (open, open - brick_size, -1)
+                    } else {
+                        break;
+                    };
+
+                    bricks.push(RustBarData {
+                        symbol: tick.symbol.clone(),
+                        exchange: tick.exchange.clone(),
+                        datetime: tick.datetime.as_ref().map(|dt| dt.clone_ref(py)),
+                        rust_datetime: tick.rust_datetime,
+                        interval: Some(RustInterval::TICK),
+                        volume: 0.0,
+                        open_interest: tick.open_interest,
+                        open_price: open,
+                        high_price: open.max(new_close),
+                        low_price: open.min(new_close),
+                        close_price: new_close,
+                        buy_volume: 0.0,
+                        sell_volume: 0.0,
+                        trade_count: 0,
+                        sub_bar_count: 0,
+                        expected_sub_bars: 0,
+                        gateway_name: tick.gateway_name.clone(),
+                        vt_symbol: tick.vt_symbol.clone(),
+                        is_forced: false,
+                        is_partial: false,
+                        touched_limit_up: tick.touched_limit_up(),
+                        touched_limit_down: tick.touched_limit_down(),
+                    });
+                    close = new_close;
+                    inner.renko_direction = new_direction;
+                }
+                inner.renko_close = Some(close);
+            } else {
+                // 首个 Tick 作为第一块砖的基准价
+                inner.renko_close = Some(price);
+            }
+
+            if let Some(last_brick) = bricks.last_mut() {
+                last_brick.volume = inner.renko_volume;
+                inner.renko_volume = 0.0;
+            }
+
+            inner.last_tick = Some(tick);
+            inner.stats.ticks_processed += 1;
+            bricks
+        };
+
+        let emitted = bricks.len();
+        let mut callback_result = Ok(());
+        for brick in bricks {
+            let result = self.emit_bar(py, brick);
+            callback_result = callback_result.and(result);
+        }
+
+        callback_result.map(|_| emitted)
+    }
+
+    /// 区间K线：最高价与最低价之差达到 range_size 时收盘，下一根K线以收盘 Tick 的价格开盘；
+    /// 一个 Tick 跳空超过一个区间时直接在该 Tick 收盘，不补中间K线。
+    /// 发出的K线 high_price - low_price 即实际达到的区间
+    fn update_range_bar(&self, py: Python, tick: RustTickData, range_size: f64) -> PyResult<usize> {
+        let tolerance = range_size * 1e-9;
+        let tick_day = if self.config.range_session_reset {
+            let tick_dt = tick.get_datetime_chrono(py)?
+                .ok_or_else(|| PyValueError::new_err("Tick缺少datetime"))?;
+            Some(trading_day_of(tick_dt.naive_local(), &tick.exchange))
+        } else {
+            None
+        };
+
+        let finished_bars = {
+            let mut inner = self.write_inner();
+            let volume_change = self.tick_volume_change(&mut inner, &tick);
+            let mut finished_bars: Vec<RustBarData> = Vec::new();
+
+            // 跨交易日时先结束上一交易日未走完区间的K线
+            if let Some(day) = tick_day
+                && let Some(ref bar) = inner.bar
+            {
+                let bar_dt = bar.get_datetime_chrono(py)?
+                    .ok_or_else(|| PyValueError::new_err("Bar缺少datetime"))?;
+                if trading_day_of(bar_dt.naive_local(), &bar.exchange) != day {
+                    finished_bars.extend(inner.bar.take());
+                }
+            }
+
+            match inner.bar {
+                Some(ref mut bar) => Self::merge_tick_into_bar(py, bar, &tick),
+                None => inner.bar = Some(Self::bar_from_tick(py, &tick, RustInterval::TICK)),
+            }
+
+            let reached = match inner.bar {
+                Some(ref mut bar) => {
+                    bar.volume += volume_change;
+                    bar.high_price - bar.low_price >= range_size - tolerance
+                }
+                None => false,
+            };
+            if reached {
+                finished_bars.extend(inner.bar.take());
+                inner.bar = Some(Self::bar_from_tick(py, &tick, RustInterval::TICK));
+            }
+
+            inner.last_tick = Some(tick);
+            inner.stats.ticks_processed += 1;
+            finished_bars
+        };
+
+        let emitted = finished_bars.len();
+        let mut callback_result = Ok(());
+        for bar in finished_bars {
+            let result = self.emit_bar(py, bar);
+            callback_result = callback_result.and(result);
+        }
+
+        callback_result.map(|_| emitted)
+    }
+
+    /// 成交量失衡K线：按 tick rule 给成交量变化分配方向（价格上涨为买、下跌为卖，价格不变沿用上一方向），
+    /// 主动买卖量差值的绝对值达到阈值时收盘；设置 imbalance_ewma_alpha 时阈值随已完成K线的失衡量做指数平滑
+    fn update_imbalance_bar(&self, py: Python, tick: RustTickData) -> PyResult<usize> {
+        let finished_bar = {
+            let mut inner = self.write_inner();
+            let volume_change = self.tick_volume_change(&mut inner, &tick);
+
+            if let Some(ref last_tick) = inner.last_tick {
+                let price_change = tick.last_price - last_tick.last_price;
+                if price_change != 0.0 {
+                    inner.imbalance_sign = price_change.signum();
+                }
+            }
+            let sign = inner.imbalance_sign;
+
+            match inner.bar {
+                Some(ref mut bar) => Self::merge_tick_into_bar(py, bar, &tick),
+                None => inner.bar = Some(Self::bar_from_tick(py, &tick, RustInterval::TICK)),
+            }
+
+            let threshold = inner.imbalance_threshold;
+            let reached = match inner.bar {
+                Some(ref mut bar) => {
+                    bar.volume += volume_change;
+                    if sign > 0.0 {
+                        bar.buy_volume += volume_change;
+                    } else if sign < 0.0 {
+                        bar.sell_volume += volume_change;
+                    }
+                    (bar.buy_volume - bar.sell_volume).abs() >= threshold
+                }
+                None => false,
+            };
+
+            inner.last_tick = Some(tick);
+            inner.stats.ticks_processed += 1;
+
+            if reached {
+                let bar = inner.bar.take();
+                if let (Some(alpha), Some(bar)) = (self.config.imbalance_ewma_alpha, bar.as_ref()) {
+                    let imbalance = (bar.buy_volume - bar.sell_volume).abs();
+                    inner.imbalance_threshold = alpha * imbalance + (1.0 - alpha) * threshold;
+                }
+                bar
+            } else {
+                None
+            }
+        };
 
-                let py_dt = PyDateTime::new(
-                    py,
-                    dt.year(),
-                    dt.month() as u8,
-                    dt.day() as u8,
-                    dt.hour() as u8,
-                    dt.minute() as u8,
-                    dt.second() as u8,
-                    dt.nanosecond() / 1000,
-                    None
-                )?;
-
-                let new_window_bar = RustBarData {
-                    symbol: bar.symbol.clone(),
-                    exchange: bar.exchange,
-                    datetime: Some(py_dt.into()),
-                    interval: Some(self.interval),
-                    volume: 0.0,
-                    open_interest: bar.open_interest,
-                    open_price: bar.open_price,
-                    high_price: bar.high_price,
-                    low_price: bar.low_price,
-                    close_price: bar.close_price,
-                    gateway_name: bar.gateway_name.clone(),
-                    vt_symbol: bar.vt_symbol.clone(),
-                };
-                inner.window_bar = Some(new_window_bar);
+        let emitted = finished_bar.is_some() as usize;
+        if let Some(bar) = finished_bar {
+            self.emit_bar(py, bar)?;
+        }
+
+        Ok(emitted)
+    }
+
+    /// 窗口K线完成时的收尾处理：开启 heikin_ashi 时转换为平均K线并保留原始K线
+    fn finish_window_bar(&self, py: Python, inner: &mut BarGeneratorInner, raw_bar: RustBarData) -> RustBarData {
+        if self.config.heikin_ashi {
+            let ha_bar = Self::heikin_ashi_bar(py, &mut inner.ha_prev, &raw_bar);
+            inner.raw_window_bar = Some(raw_bar);
+            ha_bar
+        } else {
+            raw_bar
+        }
+    }
+
+    /// 调用推送K线的回调。raise_callback_errors=True 时异常包装为 ValueError 抛出（原异常为 __cause__），
+    /// 否则以 ERROR 级别写入日志、记录到 last_callback_error 后继续合成
+    fn call_callback(&self, py: Python, callback: &Py<PyAny>, bar: RustBarData, name: &str) -> PyResult<()> {
+        let async_errors = std::mem::take(&mut *self.async_errors.lock().unwrap_or_else(PoisonError::into_inner));
+        for (callback_name, err) in async_errors {
+            self.check_callback_result(py, Err(err), &callback_name)?;
+        }
+        let result = match self.callback_context(py) {
+            Some(context) => callback.call1(py, (bar, context)),
+            None => callback.call1(py, (bar,)),
+        };
+        let result = result.and_then(|ret| self.schedule_coroutine(py, ret.bind(py), name));
+        self.check_callback_result(py, result, name)
+    }
+
+    /// 调用 on_bar_with_tick(bar, tick)，设置了 callback_context 时追加为第三个参数；异常处理与 call_callback 一致
+    fn call_bar_with_tick(&self, py: Python, callback: &Py<PyAny>, bar: RustBarData, tick: &RustTickData) -> PyResult<()> {
+        let tick = tick.clone_with_py(py);
+        let result = match self.callback_context(py) {
+            Some(context) => callback.call1(py, (bar, tick, context)),
+            None => callback.call1(py, (bar, tick)),
+        };
+        let result = result.and_then(|ret| self.schedule_coroutine(py, ret.bind(py), "on_bar_with_tick"));
+        self.check_callback_result(py, result, "on_bar_with_tick")
+    }
+
+    /// 调用 on_bar_book(bar, bid_prices, bid_volumes, ask_prices, ask_volumes)，设置了 callback_context 时追加为最后一个参数
+    fn call_bar_book(&self, py: Python, callback: &Py<PyAny>, bar: RustBarData, book: [[f64; 5]; 4]) -> PyResult<()> {
+        let [bid_prices, bid_volumes, ask_prices, ask_volumes] = book;
+        let result = match self.callback_context(py) {
+            Some(context) => callback.call1(py, (bar, bid_prices, bid_volumes, ask_prices, ask_volumes, context)),
+            None => callback.call1(py, (bar, bid_prices, bid_volumes, ask_prices, ask_volumes)),
+        };
+        let result = result.and_then(|ret| self.schedule_coroutine(py, ret.bind(py), "on_bar_book"));
+        self.check_callback_result(py, result, "on_bar_book")
+    }
+
+    /// 回调返回协程时用 asyncio.run_coroutine_threadsafe 交给 event_loop（未传入时取当前线程正在运行的事件循环）执行。
+    /// await_callbacks=True 时等待协程完成，异常直接按回调异常处理；否则协程的异常记入 async_errors，
+    /// 在下次推送K线时处理。没有可用的事件循环时关闭协程并报错
+    fn schedule_coroutine(&self, py: Python, ret: &Bound<'_, PyAny>, name: &str) -> PyResult<()> {
+        if ret.is_none() || !py.import("inspect")?.call_method1("iscoroutine", (ret,))?.is_truthy()? {
+            return Ok(());
+        }
+        let asyncio = py.import("asyncio")?;
+        let running_loop = asyncio.call_method0("get_running_loop").ok();
+        let event_loop = match (self.config.event_loop.as_ref(), running_loop.as_ref()) {
+            (Some(event_loop), _) => event_loop.bind(py).clone(),
+            (None, Some(running_loop)) => running_loop.clone(),
+            (None, None) => {
+                ret.call_method0("close")?;
+                return Err(PyValueError::new_err("回调返回了协程，需要在构造时传入 event_loop 或在事件循环中调用"));
+            }
+        };
+        if self.config.await_callbacks && running_loop.as_ref().is_some_and(|running_loop| running_loop.is(&event_loop)) {
+            ret.call_method0("close")?;
+            return Err(PyValueError::new_err("await_callbacks=True 时不能在 event_loop 所在的线程中推送行情，否则会死锁"));
+        }
+        let future = asyncio.call_method1("run_coroutine_threadsafe", (ret, &event_loop))?;
+        if self.config.await_callbacks {
+            return future.call_method0("result").map(drop);
+        }
+        let async_errors = Arc::clone(&self.async_errors);
+        let name = name.to_string();
+        let on_done = PyCFunction::new_closure(py, None, None, move |args: &Bound<'_, PyTuple>, _kwargs: Option<&Bound<'_, PyDict>>| -> PyResult<()> {
+            let future = args.get_item(0)?;
+            if future.call_method0("cancelled")?.is_truthy()? {
+                return Ok(());
+            }
+            let exception = future.call_method0("exception")?;
+            if !exception.is_none() {
+                async_errors.lock().unwrap_or_else(PoisonError::into_inner).push((name.clone(), PyErr::from_value(exception)));
+            }
+            Ok(())
+        })?;
+        future.call_method1("add_done_callback", (on_done,))?;
+        Ok(())
+    }
+
+    /// 按 raise_callback_errors 处理回调的返回结果
+    fn check_callback_result(&self, py: Python, result: PyResult<()>, name: &str) -> PyResult<()> {
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) if self.config.raise_callback_errors => {
+                let err = PyValueError::new_err(format!("{}回调处理错误：{:#?}", name, e));
+                err.set_cause(py, Some(e));
+                Err(err)
+            }
+            Err(e) => {
+                log_message(py, LOG_ERROR, &format!("{}回调处理错误", name), Some(&e));
+                self.write_inner().last_callback_error = Some(e.into_value(py).into_any());
+                Ok(())
+            }
+        }
+    }
+
+    /// last_dt 与 new_dt 之间缺失 unit_minutes 周期的K线时计数并调用 on_gap(vt_symbol, last_dt, new_dt, missing_count)；
+    /// 分钟周期按截断到分钟的时间比较，缺口超过 max_gap_minutes 时视为休市
+    fn report_gap(
+        &self,
+        py: Python,
+        vt_symbol: &str,
+        last_dt: DateTime<chrono_tz::Tz>,
+        new_dt: DateTime<chrono_tz::Tz>,
+        unit_minutes: i64,
+    ) -> PyResult<()> {
+        let Some((last_dt, new_dt, missing)) = self.detect_gap(last_dt, new_dt, unit_minutes) else {
+            return Ok(());
+        };
+        {
+            let mut inner = self.write_inner();
+            if inner.last_gap_end == Some(new_dt) {
+                return Ok(());
+            }
+            inner.last_gap_end = Some(new_dt);
+            inner.stats.gaps_detected += 1;
+            inner.stats.missing_bars += missing as u64;
+        }
+        self.call_gap(py, vt_symbol, &last_dt, &new_dt, missing)
+    }
+
+    /// 返回截断后的 last_dt、new_dt 与缺失数量，没有缺口或视为休市时返回 None
+    fn detect_gap(
+        &self,
+        last_dt: DateTime<chrono_tz::Tz>,
+        new_dt: DateTime<chrono_tz::Tz>,
+        unit_minutes: i64,
+    ) -> Option<(DateTime<chrono_tz::Tz>, DateTime<chrono_tz::Tz>, i64)> {
+        let (last_dt, new_dt) = if unit_minutes == 1 {
+            let truncate = |dt: DateTime<chrono_tz::Tz>| {
+                dt - Duration::seconds(dt.second() as i64) - Duration::nanoseconds(dt.nanosecond() as i64)
+            };
+            (truncate(last_dt), truncate(new_dt))
+        } else {
+            (last_dt, new_dt)
+        };
+        let elapsed = (new_dt - last_dt).num_minutes();
+        let missing = elapsed / unit_minutes - 1;
+        if missing < 1 || self.config.max_gap_minutes.is_some_and(|max| elapsed - unit_minutes > max as i64) {
+            return None;
+        }
+        Some((last_dt, new_dt, missing))
+    }
+
+    fn call_gap(
+        &self,
+        py: Python,
+        vt_symbol: &str,
+        last_dt: &DateTime<chrono_tz::Tz>,
+        new_dt: &DateTime<chrono_tz::Tz>,
+        missing: i64,
+    ) -> PyResult<()> {
+        match self.config.on_gap {
+            Some(ref callback) => {
+                let args = (vt_symbol, to_py_datetime(py, last_dt)?, to_py_datetime(py, new_dt)?, missing);
+                let result = callback.call1(py, args).map(drop);
+                self.check_callback_result(py, result, "on_gap")
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// 上一根分钟K线与 bar_dt 之间缺失不超过 fill_window_gaps 分钟时，用上一根收盘价补入成交量为 0 的K线，
+    /// 使计数切分的窗口按实际经过的分钟数完成；更长的缺口（休市）不补
+    /// 返回补入的K线最后完成的主窗口K线
+    fn fill_window_gap(&self, py: Python, bar_dt: &DateTime<chrono_tz::Tz>) -> PyResult<Option<RustBarData>> {
+        let last_bar = match self.read_inner().last_bar {
+            Some(ref last_bar) => last_bar.clone_with_py(py),
+            None => return Ok(None),
+        };
+        let Some(last_dt) = last_bar.get_datetime_chrono(py)? else {
+            return Ok(None);
+        };
+        let last_dt = last_dt.with_timezone(&self.config.tz);
+        let missing = (*bar_dt - last_dt).num_minutes() - 1;
+        if missing < 1 || missing > self.config.fill_window_gaps as i64 {
+            return Ok(None);
+        }
+        // 补入的K线随后作为 last_bar 被读回，时间带上 tzinfo，不按主机本地时区解释
+        let mut completed = None;
+        let mut tzinfo_cache = None;
+        for minute in 1..=missing {
+            let mut filler = last_bar.clone_with_py(py);
+            filler.datetime = Some(to_py_datetime_fixed(py, &(last_dt + Duration::minutes(minute)), &mut tzinfo_cache)?);
+            filler.open_price = last_bar.close_price;
+            filler.high_price = last_bar.close_price;
+            filler.low_price = last_bar.close_price;
+            filler.volume = 0.0;
+            filler.buy_volume = 0.0;
+            filler.sell_volume = 0.0;
+            filler.trade_count = 0;
+            filler.touched_limit_up = false;
+            filler.touched_limit_down = false;
+            if let Some(window_bar) = self.update_bar_internal(py, filler)? {
+                completed = Some(window_bar);
+            }
+        }
+        Ok(completed)
+    }
+
+    /// 推送已完成的窗口K线
+    fn emit_window_bar(&self, py: Python, bar: RustBarData) -> PyResult<()> {
+        {
+            let mut inner = self.write_inner();
+            inner.stats.window_bars_emitted += 1;
+            if !bar.is_partial
+                && let Some(ref mut rolling_stats) = inner.rolling_stats
+            {
+                rolling_stats.push(&bar);
+            }
+        }
+        match self.config.on_window_bar {
+            Some(ref callback) => self.call_callback(py, callback, bar, "on_window_bar"),
+            None => {
+                let mut inner = self.write_inner();
+                push_bounded(&mut inner.window_bar_buffer, bar, self.config.max_buffered_bars);
+                Ok(())
+            }
+        }
+    }
+
+    /// 推送已完成的K线：设置了 on_bar 时调用回调，否则存入缓冲区等待 get_bars/drain_bars 取出
+    fn emit_bar(&self, py: Python, bar: RustBarData) -> PyResult<()> {
+        let mut inner = self.write_inner();
+        inner.stats.bars_emitted += 1;
+        match self.config.on_bar {
+            Some(ref callback) => {
+                drop(inner);
+                self.call_callback(py, callback, bar, "on_bar")
+            }
+            None => {
+                push_bounded(&mut inner.bar_buffer, bar, self.config.max_buffered_bars);
+                Ok(())
+            }
+        }
+    }
+
+    /// 推送 add_window 注册的窗口完成的K线
+    fn emit_extra_window_bar(&self, py: Python, callback: &Py<PyAny>, bar: RustBarData) -> PyResult<()> {
+        self.write_inner().stats.window_bars_emitted += 1;
+        self.call_callback(py, callback, bar, "add_window")
+    }
+
+    fn update_bar_internal(&self, py: Python, mut bar: RustBarData) -> PyResult<Option<RustBarData>> {
+        let _gate = self.update_gate.enter(py)?;
+        self.check_bar_input()?;
+        if self.filter_out(&bar.symbol, &bar.exchange, 1) {
+            return Ok(None);
+        }
+        let bar_dt = bar.get_datetime_chrono(py)?
+            .ok_or_else(|| PyValueError::new_err("Bar缺少datetime"))?
+            .with_timezone(&self.config.tz);
+        // 缺口在补齐之前报告，补入的K线与上一根K线连续，不会重复报告
+        let gap_unit = match bar.interval {
+            None | Some(RustInterval::MINUTE) => Some(1),
+            Some(RustInterval::HOUR) => Some(60),
+            _ => None,
+        };
+        let last_bar_dt = match self.read_inner().last_bar {
+            Some(ref last_bar) if gap_unit.is_some() => last_bar.get_datetime_chrono(py)?,
+            _ => None,
+        };
+        if let (Some(unit), Some(last_dt)) = (gap_unit, last_bar_dt) {
+            self.report_gap(py, &bar.vt_symbol, last_dt.with_timezone(&self.config.tz), bar_dt, unit)?;
+        }
+        let mut completed = None;
+        if self.config.fill_window_gaps > 0 && bar.interval.is_none_or(|interval| interval == RustInterval::MINUTE) {
+            completed = self.fill_window_gap(py, &bar_dt)?;
+        }
+
+        // 第一阶段：获取 last_bar 时间并处理 window_bar 初始化和更新
+        let window_bar_to_callback = {
+            let mut inner = self.write_inner();
+            self.advance_data_clock(&mut inner, bar_dt);
+            
+            let mut last_dt_opt = if let Some(ref last_bar) = inner.last_bar {
+                last_bar.get_datetime_chrono(py)?.map(|dt| dt.with_timezone(&self.config.tz))
             } else {
-                if let Some(ref mut window_bar) = inner.window_bar {
-                    window_bar.high_price = window_bar.high_price.max(bar.high_price);
-                    window_bar.low_price = window_bar.low_price.min(bar.low_price);
+                None
+            };
+            if last_dt_opt == Some(bar_dt) {
+                match self.config.on_duplicate_bar {
+                    DuplicateBarPolicy::Skip => return Ok(None),
+                    DuplicateBarPolicy::Add => {}
+                    // 上一根K线已完成窗口并推送时无法撤回，按 skip 处理
+                    DuplicateBarPolicy::Replace => match inner.window_snapshot.take() {
+                        Some(snapshot) => last_dt_opt = inner.restore_windows(snapshot),
+                        None => return Ok(None),
+                    },
                 }
             }
+            if self.config.strict_bar_order
+                && let Some(last_dt) = last_dt_opt
+                && bar_dt <= last_dt
+            {
+                return Err(PyValueError::new_err(format!(
+                    "K线时间未严格递增: {} <= 上一根 {}", bar_dt, last_dt
+                )));
+            }
 
-            // 更新 close_price, volume, open_interest
-            if let Some(ref mut window_bar) = inner.window_bar {
-                window_bar.close_price = bar.close_price;
-                window_bar.volume += bar.volume;
-                window_bar.open_interest = bar.open_interest;
+            // 缺少 interval 的K线沿用上一根K线的周期，上一根也没有时由两根K线的时间差推断
+            if self.config.infer_interval && bar.interval.is_none() {
+                bar.interval = match inner.last_bar.as_ref().and_then(|last_bar| last_bar.interval) {
+                    Some(interval) => Some(interval),
+                    None => last_dt_opt.and_then(|last_dt| interval_from_delta(bar_dt - last_dt)),
+                };
+                if let Some(ref mut last_bar) = inner.last_bar
+                    && last_bar.interval.is_none()
+                {
+                    last_bar.interval = bar.interval;
+                }
             }
 
-            // 计算是否需要触发回调
-            let now_value = self.get_interval_value_from_dt(&bar_dt);
-            let mut finished = false;
-
-            if let Some(ref last_dt) = last_dt_opt {
-                let last_value = self.get_interval_value_from_dt(last_dt);
-
-                if now_value != last_value {
-                    // 判断是否使用目标时间点检查模式
-                    let use_target_check = match self.interval {
-                        RustInterval::MINUTE => {
-                            if self.interval_slice {
-                                if self.window < 60 {
-                                    60 % self.window == 0
-                                } else {
-                                    1440 % self.window == 0
-                                }
-                            } else {
-                                false
-                            }
-                        }
-                        RustInterval::HOUR => self.interval_slice && 24 % self.window == 0,
-                        RustInterval::DAILY => self.interval_slice && 7 % self.window == 0,
-                        RustInterval::WEEKLY => self.interval_slice && 52 % self.window == 0,
-                        _ => self.interval_slice,
-                    };
+            if self.config.on_duplicate_bar == DuplicateBarPolicy::Replace {
+                inner.window_snapshot = Some(inner.snapshot_windows(py, last_dt_opt));
+            }
 
-                    if use_target_check && self.check_target_value(now_value) {
-                        finished = true;
-                    } else if !use_target_check {
-                        // 对于 DAILY/WEEKLY/MONTHLY 或不能整除的情况，使用计数器方式
-                        // 每次日期值变化时递增计数器
-                        inner.interval_count += 1;
-                        
-                        // 当计数达到 window 时触发
-                        if inner.interval_count % self.window == 0 {
-                            finished = true;
-                        }
-                    }
+            // 日历周期窗口在并入进入下一周期的K线之前先完成
+            let inner = &mut *inner;
+            let period_window_bar = if self.config.rule.closes_period_before(last_dt_opt.as_ref(), &bar_dt) {
+                inner.reset_count = 0;
+                inner.bar_push_status.clear();
+                inner.window_bar.take().map(|raw_bar| self.finish_window_bar(py, inner, raw_bar))
+            } else {
+                None
+            };
+
+            // 初始化或更新 window_bar
+            self.config.rule.update_window_bar(py, &mut inner.window_bar, &mut inner.window_open_latched, &bar, &bar_dt)?;
+
+            // 其余窗口依次更新，记录完成的窗口K线
+            let mut extra_finished: Vec<(u64, Py<PyAny>, RustBarData)> = Vec::new();
+            for extra in inner.extra_windows.iter_mut() {
+                if extra.rule.closes_period_before(last_dt_opt.as_ref(), &bar_dt)
+                    && let Some(window_bar) = extra.window_bar.take()
+                {
+                    extra_finished.push((extra.rule.span_minutes(), extra.callback.clone_ref(py), window_bar));
+                }
+                extra.rule.update_window_bar(py, &mut extra.window_bar, &mut extra.window_open_latched, &bar, &bar_dt)?;
+                let (finished, interval_count) = extra.rule.window_decision(last_dt_opt.as_ref(), &bar_dt, extra.interval_count, bar.interval);
+                extra.interval_count = interval_count;
+                if finished && let Some(window_bar) = extra.window_bar.take() {
+                    extra.interval_count = 0;
+                    extra_finished.push((extra.rule.span_minutes(), extra.callback.clone_ref(py), window_bar));
                 }
             }
 
+            // 计算是否需要触发回调
+            let (finished, interval_count) = self.config.rule.window_decision(last_dt_opt.as_ref(), &bar_dt, inner.interval_count, bar.interval);
+            inner.interval_count = interval_count;
+
             // 如果需要触发回调，取出 window_bar
-            let window_bar_to_callback = if finished {
+            let window_bar = if finished {
                 let wb = inner.window_bar.take();
                 inner.reset_count = 0;
                 inner.interval_count = 0;
                 inner.bar_push_status.clear();
-                wb
+                wb.map(|raw_bar| self.finish_window_bar(py, inner, raw_bar))
             } else {
                 None
             };
 
-            (last_dt_opt, window_bar_to_callback)
-        };  // inner 借用在这里释放
-
-        // 第二阶段：在 RefCell 借用释放后执行回调
-        if let Some(window_bar_data) = window_bar_to_callback {
-            if let Some(ref callback) = self.on_window_bar {
-                // 将 panic 改为返回 PyResult 错误
-                callback.call1(py, (window_bar_data,)).map_err(|e| {
-                    PyValueError::new_err(format!("on_window_bar回调处理错误：{:#?}", e))
-                })?;
+            // 同一根K线完成多个窗口时，按窗口时长从小到大回调，主窗口的回调为 None
+            let mut finished_windows: Vec<(u64, Option<Py<PyAny>>, RustBarData)> = extra_finished
+                .into_iter()
+                .map(|(span, callback, bar)| (span, Some(callback), bar))
+                .collect();
+            if let Some(window_bar) = period_window_bar {
+                finished_windows.push((self.config.rule.span_minutes(), None, window_bar));
+            }
+            if let Some(window_bar) = window_bar {
+                finished_windows.push((self.config.rule.span_minutes(), None, window_bar));
             }
+            if !finished_windows.is_empty() {
+                inner.window_snapshot = None;
+            }
+            if self.config.drop_partial_windows {
+                finished_windows.retain(|(_, _, window_bar)| !window_bar.is_partial);
+            }
+            finished_windows.sort_by_key(|(span, _, _)| *span);
+            finished_windows
+        };  // inner 写锁在这里释放
+
+        // 第二阶段：在写锁释放后执行回调，回调异常在 last_bar 更新后再抛出
+        let mut callback_result = Ok(());
+        for (_, callback, window_bar_data) in window_bar_to_callback {
+            let result = match callback {
+                Some(callback) => self.emit_extra_window_bar(py, &callback, window_bar_data),
+                None => {
+                    completed = Some(window_bar_data.clone_with_py(py));
+                    self.emit_window_bar(py, window_bar_data)
+                }
+            };
+            callback_result = callback_result.and(result);
         }
 
         // 第三阶段：更新 last_bar
         {
-            let mut inner = self.inner.write().unwrap();
+            let mut inner = self.write_inner();
             // 最后更新 last_bar
             inner.last_bar = Some(bar);
+            inner.stats.bars_updated += 1;
         }
         
-        Ok(())
+        callback_result.map(|_| completed)
     }
 
-    #[inline(always)]
-    fn get_interval_value_from_dt(&self, dt: &DateTime<chrono_tz::Tz>) -> u32 {
-        match self.interval {
-            RustInterval::MINUTE => {
-                if self.interval_slice && self.window >= 60 {
-                    // 对于大于等于60分钟的窗口，返回从0点开始的总分钟数
-                    dt.hour() * 60 + dt.minute()
-                } else {
-                    dt.minute()
-                }
+    /// 由原始窗口K线计算平均K线（Heikin-Ashi），成交量和持仓量原样保留；
+    /// 第一根平均K线的开盘价取原始K线开盘价与收盘价的均值
+    fn heikin_ashi_bar(py: Python, ha_prev: &mut Option<(f64, f64)>, raw_bar: &RustBarData) -> RustBarData {
+        let ha_close = (raw_bar.open_price + raw_bar.high_price + raw_bar.low_price + raw_bar.close_price) / 4.0;
+        let ha_open = match *ha_prev {
+            Some((prev_open, prev_close)) => (prev_open + prev_close) / 2.0,
+            None => (raw_bar.open_price + raw_bar.close_price) / 2.0,
+        };
+        *ha_prev = Some((ha_open, ha_close));
+
+        let mut ha_bar = raw_bar.clone_with_py(py);
+        ha_bar.open_price = ha_open;
+        ha_bar.close_price = ha_close;
+        ha_bar.high_price = raw_bar.high_price.max(ha_open).max(ha_close);
+        ha_bar.low_price = raw_bar.low_price.min(ha_open).min(ha_close);
+        ha_bar
+    }
+}
+
+// ================================================================================================
+// BarIterator - drain() 返回的一次性K线迭代器
+// ================================================================================================
+#[pyclass(module = "rust_bar_generator")]
+pub struct BarIterator {
+    bars: std::vec::IntoIter<RustBarData>,
+}
+
+#[pymethods]
+impl BarIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> Option<RustBarData> {
+        self.bars.next()
+    }
+
+    /// 剩余未迭代的K线数量
+    fn __len__(&self) -> usize {
+        self.bars.len()
+    }
+}
+
+// ================================================================================================
+// SessionAccumulator - 从上次 reset 起不断累积的K线（开高低收、成交量、成交额），不按时间切分，
+// 用于锚定 VWAP、盘中统计等场景
+// ================================================================================================
+#[pyclass(module = "rust_bar_generator")]
+pub struct SessionAccumulator {
+    inner: RwLock<SessionState>,
+    volume_mode: VolumeMode,
+}
+
+#[derive(Default)]
+struct SessionState {
+    bar: Option<RustBarData>,
+    // 成交额按 Tick 最新价 × 成交量累加
+    turnover: f64,
+    last_volume: Option<f64>,
+}
+
+#[pymethods]
+impl SessionAccumulator {
+    /// volume_mode 与 BarGenerator 相同
+    #[new]
+    #[pyo3(signature = (volume_mode="cumulative"))]
+    fn new(volume_mode: &str) -> PyResult<Self> {
+        Ok(SessionAccumulator {
+            inner: RwLock::new(SessionState::default()),
+            volume_mode: VolumeMode::parse(volume_mode)?,
+        })
+    }
+
+    /// 将 Tick 并入累积K线，最新价为 0 的 Tick 忽略
+    fn update_tick(&self, py: Python, tick: Bound<'_, PyAny>) -> PyResult<()> {
+        let tick = RustTickData::from_py_tick(py, &tick)?;
+        if tick.last_price == 0.0 {
+            return Ok(());
+        }
+        let mut state = write_lock(&self.inner, "SessionAccumulator");
+        let volume_change = self.volume_mode.volume_change(state.last_volume, &tick);
+        match state.bar {
+            Some(ref mut bar) => BarGenerator::merge_tick_into_bar(py, bar, &tick),
+            None => {
+                let mut bar = BarGenerator::bar_from_tick(py, &tick, RustInterval::TICK);
+                bar.interval = None;
+                state.bar = Some(bar);
             }
-            RustInterval::HOUR => dt.hour(),
-            RustInterval::DAILY => dt.day(),
-            RustInterval::WEEKLY => dt.iso_week().week(),
-            RustInterval::MONTHLY => dt.month(),
-            _ => 0,
         }
+        if let Some(ref mut bar) = state.bar {
+            bar.volume += volume_change;
+        }
+        state.turnover += tick.last_price * volume_change;
+        state.last_volume = Some(tick.volume);
+        Ok(())
     }
 
-    fn check_target_value(&self, value: u32) -> bool {
-        match self.interval {
-            RustInterval::MINUTE => {
-                if self.interval_slice && self.window >= 60 {
-                    // 对于大于等于60分钟的窗口，检查总分钟数是否是window的倍数
-                    (value as usize) % self.window == 0
-                } else {
-                    self.target_minutes.contains(&value)
-                }
+    /// 当前累积K线的副本（datetime 为最新 Tick 的时间），还没有 Tick 时返回 None
+    fn snapshot(&self, py: Python) -> Option<RustBarData> {
+        let state = read_lock(&self.inner, "SessionAccumulator");
+        state.bar.as_ref().map(|bar| bar.clone_with_py(py))
+    }
+
+    #[getter]
+    fn turnover(&self) -> f64 {
+        read_lock(&self.inner, "SessionAccumulator").turnover
+    }
+
+    /// 成交量加权均价，没有成交时返回 None
+    #[getter]
+    fn vwap(&self) -> Option<f64> {
+        let state = read_lock(&self.inner, "SessionAccumulator");
+        match state.bar {
+            Some(ref bar) if bar.volume > 0.0 => Some(state.turnover / bar.volume),
+            _ => None,
+        }
+    }
+
+    /// 清空累积状态，下一个 Tick 开始新的累积
+    fn reset(&self) {
+        *write_lock(&self.inner, "SessionAccumulator") = SessionState::default();
+    }
+
+    fn __repr__(&self) -> String {
+        let state = read_lock(&self.inner, "SessionAccumulator");
+        match state.bar {
+            Some(ref bar) => format!(
+                "SessionAccumulator(open={}, high={}, low={}, close={}, volume={})",
+                bar.open_price, bar.high_price, bar.low_price, bar.close_price, bar.volume
+            ),
+            None => "SessionAccumulator(empty)".to_string(),
+        }
+    }
+}
+
+// ================================================================================================
+// MultiBarGenerator - 多合约K线生成器，按 vt_symbol 将行情路由到各自的生成器状态
+// 回调为所有合约共用，每个合约独立维护 bar、last_tick、window_bar 和推送状态
+// ================================================================================================
+#[pyclass(module = "rust_bar_generator")]
+pub struct MultiBarGenerator {
+    // 新合约首次出现时，以该生成器的配置创建独立状态
+    template: Py<BarGenerator>,
+    generators: RwLock<HashMap<String, Py<BarGenerator>>>,
+}
+
+#[pymethods]
+impl MultiBarGenerator {
+    /// 参数与 BarGenerator 完全相同
+    #[new]
+    #[pyo3(signature = (*args, **kwargs))]
+    fn new(py: Python, args: &Bound<'_, PyTuple>, kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let template = py.get_type::<BarGenerator>().call(args, kwargs)?
+            .cast_into::<BarGenerator>()?
+            .unbind();
+        Ok(MultiBarGenerator {
+            template,
+            generators: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// 返回该 Tick 是否开始了一根新的分钟K线
+    fn update_tick(&self, py: Python, tick: Bound<'_, PyAny>) -> PyResult<bool> {
+        let rust_tick = RustTickData::from_py_tick(py, &tick)?;
+        let generator = self.generator_for(py, &rust_tick.vt_symbol)?;
+        let opened = generator.borrow(py).update_tick_opened(py, rust_tick)?;
+        Ok(opened)
+    }
+
+    /// 批量推送 Tick，各合约按 vt_symbol 路由，参见 BarGenerator.update_ticks
+    fn update_ticks(&self, py: Python, ticks: &Bound<'_, PyAny>) -> PyResult<usize> {
+        let mut emitted = 0;
+        for tick in from_py_ticks(py, ticks)? {
+            let generator = self.generator_for(py, &tick.vt_symbol)?;
+            emitted += generator.borrow(py).update_tick_internal(py, tick)?;
+        }
+        Ok(emitted)
+    }
+
+    fn update_bar(&self, py: Python, bar: Bound<'_, PyAny>) -> PyResult<Option<RustBarData>> {
+        let rust_bar = RustBarData::from_py_bar(&bar)?;
+        let generator = self.generator_for(py, &rust_bar.vt_symbol)?;
+        generator.borrow(py).update_bar_internal(py, rust_bar)
+    }
+
+    /// 批量推送K线，各合约按 vt_symbol 路由，参见 BarGenerator.update_bars
+    fn update_bars(&self, py: Python, bars: &Bound<'_, PyAny>) -> PyResult<()> {
+        for bar in from_py_bars(bars)? {
+            let generator = self.generator_for(py, &bar.vt_symbol)?;
+            generator.borrow(py).update_bar_internal(py, bar)?;
+        }
+        Ok(())
+    }
+
+    /// 对所有合约执行 generate_bar_event，返回发现分钟K线缺失的合约的结果列表
+    #[pyo3(signature = (event, now=None))]
+    fn generate_bar_event<'py>(
+        &self,
+        py: Python<'py>,
+        event: Bound<'_, PyAny>,
+        now: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let mut results = Vec::new();
+        for generator in self.all_generators(py) {
+            results.extend(generator.borrow(py).generate_bar_event(py, event.clone(), now)?);
+        }
+        Ok(results)
+    }
+
+    /// 对所有合约执行 flush，推送未走完的K线
+    fn flush(&self, py: Python) -> PyResult<()> {
+        for generator in self.all_generators(py) {
+            generator.borrow(py).flush(py)?;
+        }
+        Ok(())
+    }
+
+    /// 对所有合约执行 finalize，推送未走完的K线并清空合成状态
+    fn finalize(&self, py: Python) -> PyResult<()> {
+        for generator in self.all_generators(py) {
+            generator.borrow(py).finalize(py)?;
+        }
+        Ok(())
+    }
+
+    /// 为所有合约（包括之后新出现的合约）注册额外窗口，参见 BarGenerator.add_window
+    #[pyo3(signature = (window, interval, callback, interval_slice=true))]
+    fn add_window(
+        &self,
+        py: Python,
+        window: usize,
+        interval: &Bound<'_, PyAny>,
+        callback: Py<PyAny>,
+        interval_slice: bool,
+    ) -> PyResult<()> {
+        if window == 0 {
+            return Err(PyValueError::new_err("window must be >= 1"));
+        }
+        let rule = WindowRule::new(RustInterval::from_py_any(interval)?, window, interval_slice);
+        rule.warn_uneven_slice(py);
+        for generator in self.all_generators(py) {
+            generator.borrow(py).push_extra_window(rule.clone(), callback.clone_ref(py));
+        }
+        self.template.borrow(py).push_extra_window(rule, callback);
+        Ok(())
+    }
+
+    /// 已收到过行情的合约列表（按 vt_symbol 排序）
+    fn symbols(&self) -> Vec<String> {
+        let mut symbols: Vec<String> = read_lock(&self.generators, "MultiBarGenerator").keys().cloned().collect();
+        symbols.sort();
+        symbols
+    }
+
+    /// 返回合约对应的生成器，合约未出现过时返回 None
+    fn get(&self, py: Python, vt_symbol: &str) -> Option<Py<BarGenerator>> {
+        read_lock(&self.generators, "MultiBarGenerator").get(vt_symbol).map(|g| g.clone_ref(py))
+    }
+
+    /// 丢弃合约的全部状态，下次收到该合约的行情时重新开始合成；不传 vt_symbol 时重置所有合约
+    #[pyo3(signature = (vt_symbol=None))]
+    fn reset(&self, vt_symbol: Option<&str>) {
+        let mut generators = write_lock(&self.generators, "MultiBarGenerator");
+        match vt_symbol {
+            Some(vt_symbol) => {
+                generators.remove(vt_symbol);
             }
-            RustInterval::HOUR => self.target_hours.contains(&value),
-            RustInterval::DAILY => self.target_days.contains(&value),
-            RustInterval::WEEKLY => self.target_weeks.contains(&value),
-            RustInterval::MONTHLY => self.target_months.contains(&value),
-            _ => false,
+            None => generators.clear(),
         }
     }
 
+    /// 取出所有合约在未设置 on_bar 时缓存的已完成K线
+    fn drain_bars(&self, py: Python) -> Vec<RustBarData> {
+        self.all_generators(py)
+            .into_iter()
+            .flat_map(|generator| generator.borrow(py).drain_bars())
+            .collect()
+    }
+
+    /// 所有合约在未设置 on_bar 时缓存的已完成K线，参见 BarGenerator.get_bars
+    #[pyo3(signature = (clear=false))]
+    fn get_bars(&self, py: Python, clear: bool) -> Vec<RustBarData> {
+        self.all_generators(py)
+            .into_iter()
+            .flat_map(|generator| generator.borrow(py).get_bars(py, clear))
+            .collect()
+    }
+
+    /// 所有合约在未设置 on_window_bar 时缓存的主窗口K线，参见 BarGenerator.get_window_bars
+    #[pyo3(signature = (clear=false))]
+    fn get_window_bars(&self, py: Python, clear: bool) -> Vec<RustBarData> {
+        self.all_generators(py)
+            .into_iter()
+            .flat_map(|generator| generator.borrow(py).get_window_bars(py, clear))
+            .collect()
+    }
+
+    fn __len__(&self) -> usize {
+        read_lock(&self.generators, "MultiBarGenerator").len()
+    }
+
+    fn __contains__(&self, vt_symbol: &str) -> bool {
+        read_lock(&self.generators, "MultiBarGenerator").contains_key(vt_symbol)
+    }
+
+    fn __repr__(&self, py: Python) -> String {
+        let template = self.template.borrow(py);
+        format!(
+            "MultiBarGenerator(interval={:?}, window={}, symbols={})",
+            template.config.rule.interval,
+            template.config.rule.window,
+            self.__len__()
+        )
+    }
+}
+
+impl MultiBarGenerator {
+    /// 取出合约对应的生成器，不存在时按模板创建；返回前释放 HashMap 的锁，回调中可以再次调用本对象
+    fn generator_for(&self, py: Python, vt_symbol: &str) -> PyResult<Py<BarGenerator>> {
+        if let Some(generator) = read_lock(&self.generators, "MultiBarGenerator").get(vt_symbol) {
+            return Ok(generator.clone_ref(py));
+        }
+        let mut generators = write_lock(&self.generators, "MultiBarGenerator");
+        if let Some(generator) = generators.get(vt_symbol) {
+            return Ok(generator.clone_ref(py));
+        }
+        let generator = Py::new(py, self.template.borrow(py).fork(py))?;
+        generators.insert(vt_symbol.to_string(), generator.clone_ref(py));
+        Ok(generator)
+    }
 
+    fn all_generators(&self, py: Python) -> Vec<Py<BarGenerator>> {
+        read_lock(&self.generators, "MultiBarGenerator").values().map(|g| g.clone_ref(py)).collect()
+    }
 }
 
 // ================================================================================================
 // Python 模块定义
 // ================================================================================================
-#[pymodule]
+#[pymodule(gil_used = false)]
 fn rust_bar_generator(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<RustInterval>()?;
     m.add_class::<RustExchange>()?;
+    m.add_class::<CustomExchange>()?;
     m.add_class::<RustBarData>()?;
     m.add_class::<RustTickData>()?;
     m.add_class::<BarGenerator>()?;
+    m.add_class::<BarIterator>()?;
+    m.add_class::<SessionAccumulator>()?;
+    m.add_function(wrap_pyfunction!(set_log_callback, m)?)?;
+    m.add_class::<MultiBarGenerator>()?;
+    m.add_class::<TimezoneConverter>()?;
     m.add_function(wrap_pyfunction!(get_local_datetime, m)?)?;
+    m.add_function(wrap_pyfunction!(from_py_bars, m)?)?;
+    m.add_function(wrap_pyfunction!(to_py_bars, m)?)?;
+    m.add_function(wrap_pyfunction!(resample_bars, m)?)?;
+    m.add_function(wrap_pyfunction!(bars_from_ticks, m)?)?;
+    m.add_function(wrap_pyfunction!(ticks_to_bars, m)?)?;
+    m.add_function(wrap_pyfunction!(floor_datetime, m)?)?;
+    m.add_function(wrap_pyfunction!(ceil_datetime, m)?)?;
+    m.add_function(wrap_pyfunction!(recommended_interval_slice, m)?)?;
+    m.add_function(wrap_pyfunction!(register_exchange, m)?)?;
+    let stop_auto_generate_threads = wrap_pyfunction!(_stop_auto_generate_threads, m)?;
+    PyModule::import(m.py(), "atexit")?.call_method1("register", (stop_auto_generate_threads,))?;
     Ok(())
 }
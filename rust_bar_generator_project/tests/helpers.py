@@ -0,0 +1,52 @@
+"""测试公用的行情构造函数。
+
+无时区的 datetime 按进程本地时区解释，测试统一固定为上海时间，与生成器默认的 tz 一致；
+需要模拟其他时区主机的用例使用 host_timezone。
+"""
+import contextlib
+import datetime as dt
+import os
+import time
+
+import rust_bar_generator as rbg
+
+os.environ["TZ"] = "Asia/Shanghai"
+time.tzset()
+
+T0 = dt.datetime(2024, 1, 2, 9, 0)
+
+
+def make_tick(seconds, price, volume=0.0, start=T0, **kwargs):
+    """start 之后 seconds 秒的 rb/SHFE Tick"""
+    return rbg.RustTickData(
+        "rb", "SHFE", "CTP", start + dt.timedelta(seconds=seconds),
+        last_price=price, volume=volume, **kwargs,
+    )
+
+
+def make_bar(minute, price=None, volume=1.0, interval="1m", start=T0, **kwargs):
+    """start 之后第 minute 分钟的 rb/SHFE K线，开高低收默认都为 price（默认等于 minute），可单独指定"""
+    price = minute if price is None else price
+    prices = dict(open_price=price, high_price=price, low_price=price, close_price=price)
+    prices.update(kwargs)
+    return rbg.RustBarData(
+        symbol="rb", exchange="SHFE", gateway_name="CTP",
+        datetime=start + dt.timedelta(minutes=minute), interval=interval, volume=volume, **prices,
+    )
+
+
+def _set_host_timezone(name):
+    os.environ["TZ"] = name
+    time.tzset()
+    # chrono 读取本地时区后缓存 1 秒，等缓存过期后扩展模块才会看到新的时区
+    time.sleep(1.1)
+
+
+@contextlib.contextmanager
+def host_timezone(name):
+    """临时把进程本地时区切换为 name"""
+    _set_host_timezone(name)
+    try:
+        yield
+    finally:
+        _set_host_timezone("Asia/Shanghai")
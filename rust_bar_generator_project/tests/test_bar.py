@@ -0,0 +1,41 @@
+import unittest
+
+import rust_bar_generator as rbg
+
+from helpers import make_bar
+
+
+def ohlcv(bar):
+    return bar.open_price, bar.high_price, bar.low_price, bar.close_price, bar.volume
+
+
+class MergeTest(unittest.TestCase):
+    def test_ohlcv(self):
+        first = make_bar(0, open_price=10, high_price=12, low_price=9, close_price=11, volume=3, open_interest=100)
+        second = make_bar(0, open_price=11, high_price=14, low_price=8, close_price=13, volume=5, open_interest=120)
+        merged = first.merge(second)
+        self.assertEqual(ohlcv(merged), (10, 14, 8, 13, 8))
+        self.assertEqual(merged.open_interest, 120)
+        self.assertEqual(merged.datetime, first.datetime)
+        # 原K线不变
+        self.assertEqual(ohlcv(first), (10, 12, 9, 11, 3))
+
+    def test_unset_prices_take_other(self):
+        empty = make_bar(0, price=0, volume=0)
+        bar = make_bar(0, open_price=10, high_price=12, low_price=9, close_price=11, volume=3)
+        self.assertEqual(ohlcv(empty.merge(bar)), (10, 12, 9, 11, 3))
+        self.assertEqual(ohlcv(bar.merge(empty))[:3], (10, 12, 9))
+
+    def test_mismatched_datetime(self):
+        with self.assertRaisesRegex(ValueError, "只能合并同一时间的K线"):
+            make_bar(0).merge(make_bar(1))
+
+    def test_mismatched_symbol(self):
+        other = make_bar(0)
+        other.symbol = "hc"
+        with self.assertRaisesRegex(ValueError, "只能合并同一合约的K线"):
+            make_bar(0).merge(other)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,261 @@
+import array
+import datetime as dt
+import math
+import types
+import unittest
+import zoneinfo
+
+import rust_bar_generator as rbg
+
+from helpers import host_timezone, make_bar, make_tick
+
+try:
+    import pandas as pd
+except ImportError:
+    pd = None
+
+
+def window_fields(bars):
+    return [
+        (bar.datetime, bar.open_price, bar.high_price, bar.low_price, bar.close_price, bar.volume)
+        for bar in bars
+    ]
+
+
+class UpdateBarsTest(unittest.TestCase):
+    def record(self, bars, batch):
+        events = []
+        bg = rbg.BarGenerator(
+            lambda bar: events.append(("bar", bar.datetime)), 3,
+            lambda bar: events.append(("window", bar.datetime, bar.volume)), "1m",
+        )
+        bg.add_window(5, "1m", lambda bar: events.append(("window5", bar.datetime, bar.volume)))
+        if batch:
+            bg.update_bars(bars)
+        else:
+            for bar in bars:
+                bg.update_bar(bar)
+        return events
+
+    def test_callback_order_matches_update_bar(self):
+        bars = [make_bar(minute, volume=minute + 1) for minute in range(16)]
+        expected = self.record(bars, batch=False)
+        self.assertIn("window5", [event[0] for event in expected])
+        self.assertEqual(self.record(bars, batch=True), expected)
+
+    def test_error_names_failing_index(self):
+        bg = rbg.BarGenerator(lambda bar: None)
+        with self.assertRaisesRegex(ValueError, "第2根bar转换失败"):
+            bg.update_bars([make_bar(0), make_bar(1), object(), make_bar(3)])
+        # 转换在合成之前完成，失败时不处理任何K线
+        self.assertEqual(bg.stats()["bars_updated"], 0)
+
+
+class FromPyBarsTest(unittest.TestCase):
+    SHANGHAI = zoneinfo.ZoneInfo("Asia/Shanghai")
+
+    def vnpy_bar(self, timestamp, price):
+        fields = dict(open_price=price, high_price=price, low_price=price, close_price=price, volume=1, open_interest=0)
+        return types.SimpleNamespace(symbol="rb", exchange="SHFE", gateway_name="G", datetime=timestamp, interval="1m", **fields)
+
+    def test_epoch_and_string_datetimes_are_aware(self):
+        # 2024-01-02 01:01 UTC 即上海时间 09:01，分别以整数秒、浮点秒和 UTC 字符串给出
+        start = dt.datetime(2024, 1, 2, 9, 1, tzinfo=self.SHANGHAI)
+        epoch = int(start.timestamp())
+        bars = [
+            self.vnpy_bar(epoch, 10),
+            self.vnpy_bar(epoch + 60.5, 11),
+            self.vnpy_bar("2024-01-02 01:03:00", 12),
+        ]
+        windows = []
+        with host_timezone("UTC"):
+            converted = rbg.from_py_bars(bars)
+            bg = rbg.BarGenerator(None, 3, windows.append, "1m")
+            for bar in bars:
+                bg.update_bar(bar)
+        for bar in converted:
+            self.assertIsNotNone(bar.datetime.tzinfo)
+            self.assertEqual(bar.datetime.utcoffset(), dt.timedelta(hours=8))
+        expected = [start, start + dt.timedelta(seconds=60.5), start + dt.timedelta(minutes=2)]
+        self.assertEqual([bar.datetime for bar in converted], expected)
+        # 主机时区为 UTC 时三根K线仍合成 09:01～09:03 的一个窗口
+        self.assertEqual([(bar.datetime.hour, bar.datetime.minute, bar.high_price, bar.volume) for bar in windows], [(9, 1, 12, 3)])
+
+
+class TicksToBarsTest(unittest.TestCase):
+    # (秒, 价格, 累计成交量, 逐笔成交量)：跨分钟、跳过一分钟、成交量回退（换日清零）、重复时间
+    TICKS = [
+        (5, 10.0, 100, 0), (20, 10.5, 103, 3), (59.5, 9.8, 110, 7),
+        (60, 9.9, 115, 5), (95, 10.2, 111, 0), (110, 10.4, 120, 9),
+        (185, 10.1, 121, 1), (185, 10.3, 124, 3), (240, 10.0, 124, 0), (250, 10.6, 130, 6),
+    ]
+
+    def ticks(self):
+        return [make_tick(sec, price, volume, last_volume=last_volume) for sec, price, volume, last_volume in self.TICKS]
+
+    def live(self, **kwargs):
+        bars = []
+        bg = rbg.BarGenerator(bars.append, **kwargs)
+        for tick in self.ticks():
+            bg.update_tick(tick)
+        return bg, bars
+
+    def test_matches_live_update_tick(self):
+        for volume_mode in ("cumulative", "delta"):
+            with self.subTest(volume_mode=volume_mode):
+                bg, expected = self.live(volume_mode=volume_mode)
+                bars, partial = rbg.ticks_to_bars(self.ticks(), volume_mode=volume_mode)
+                self.assertEqual(window_fields(bars), window_fields(expected))
+                self.assertEqual(len(bars), 3)
+                current = bg.current_bar()
+                self.assertEqual(window_fields([partial])[0][1:], window_fields([current])[0][1:])
+                self.assertTrue(partial.is_partial)
+
+    def test_structured_array_matches_tick_list(self):
+        arrays = {
+            "datetime_ns": array.array("q", [int(tick.datetime.timestamp() * 10**9) for tick in self.ticks()]),
+            "last_price": array.array("d", [price for _, price, _, _ in self.TICKS]),
+            "volume": array.array("d", [volume for _, _, volume, _ in self.TICKS]),
+        }
+
+        class Columns(dict):
+            dtype = None
+
+        bars, partial = rbg.ticks_to_bars(Columns(arrays), symbol="rb", exchange="SHFE", gateway_name="CTP")
+        expected, expected_partial = rbg.ticks_to_bars(self.ticks())
+        self.assertEqual(window_fields(bars), window_fields(expected))
+        self.assertEqual(window_fields([partial]), window_fields([expected_partial]))
+
+    def test_window_bars_match_live_window(self):
+        # 实盘由 on_bar 调用 update_bar 合成窗口；ticks_to_bars 把最后未走完的分钟K线也并入窗口，对应实盘的 flush
+        expected = []
+        bg = rbg.BarGenerator(lambda bar: bg.update_bar(bar), 2, expected.append)
+        for tick in self.ticks():
+            bg.update_tick(tick)
+        bg.flush()
+        bars, partial = rbg.ticks_to_bars(self.ticks(), window=2)
+        self.assertEqual(window_fields(bars), window_fields(expected))
+        self.assertEqual(len(bars), 2)
+        self.assertIsNone(partial)
+
+
+class BatchParityTest(unittest.TestCase):
+    """update_bars_numpy / update_bars_df 与逐根 update_bar 的结果一致"""
+
+    # 第 3、4 分钟缺失（补齐），第 8～19 分钟缺失（只报告不补齐）
+    MINUTES = [0, 1, 2, 5, 6, 7, 20, 21, 22, 23]
+
+    def make_generator(self, windows, gaps, **kwargs):
+        return rbg.BarGenerator(
+            lambda bar: None, 3, windows.append, "1m",
+            on_gap=lambda *args: gaps.append(args[1:]), fill_window_gaps=2, **kwargs,
+        )
+
+    def run_update_bar(self, bars, **kwargs):
+        windows, gaps = [], []
+        bg = self.make_generator(windows, gaps, **kwargs)
+        for bar in bars:
+            bg.update_bar(bar)
+        return windows, gaps, bg.stats()
+
+    def run_numpy(self, bars, **kwargs):
+        windows, gaps = [], []
+        bg = self.make_generator(windows, gaps, **kwargs)
+        ts_ms = array.array("q", [int(bar.datetime.timestamp() * 1000) for bar in bars])
+        column = lambda name: array.array("d", [getattr(bar, name) for bar in bars])
+        bg.update_bars_numpy(
+            ts_ms, column("open_price"), column("high_price"), column("low_price"),
+            column("close_price"), column("volume"), symbol="rb", exchange="SHFE", gateway_name="CTP",
+        )
+        return windows, gaps, bg.stats()
+
+    def test_gaps_and_fill_match_update_bar(self):
+        bars = [make_bar(minute, volume=minute + 1) for minute in self.MINUTES]
+        expected = self.run_update_bar(bars)
+        actual = self.run_numpy(bars)
+
+        self.assertEqual(len(expected[1]), 2)
+        self.assertEqual(window_fields(actual[0]), window_fields(expected[0]))
+        self.assertEqual(actual[1], expected[1])
+        for key in ("bars_updated", "gaps_detected", "missing_bars", "window_bars_emitted"):
+            self.assertEqual(actual[2][key], expected[2][key], key)
+
+    def test_numpy_continues_update_bar_state(self):
+        bars = [make_bar(minute, volume=minute + 1) for minute in self.MINUTES]
+        expected = self.run_update_bar(bars)
+
+        windows, gaps = [], []
+        bg = self.make_generator(windows, gaps)
+        for bar in bars[:4]:
+            bg.update_bar(bar)
+        rest = bars[4:]
+        bg.update_bars_numpy(
+            array.array("q", [int(bar.datetime.timestamp() * 1000) for bar in rest]),
+            *[array.array("d", [getattr(bar, name) for bar in rest])
+              for name in ("open_price", "high_price", "low_price", "close_price", "volume")],
+            symbol="rb", exchange="SHFE", gateway_name="CTP",
+        )
+        self.assertEqual(window_fields(windows), window_fields(expected[0]))
+        self.assertEqual(gaps, expected[1])
+
+    def test_numpy_skips_nan_rows(self):
+        windows = []
+        bg = rbg.BarGenerator(lambda bar: None, 3, windows.append, "1m")
+        bars = [make_bar(minute) for minute in range(4)]
+        ts_ms = array.array("q", [int(bar.datetime.timestamp() * 1000) for bar in bars])
+        close = array.array("d", [0, 1, math.nan, 3])
+        prices = array.array("d", [0, 1, 2, 3])
+        bg.update_bars_numpy(ts_ms, prices, prices, prices, close, array.array("d", [1] * 4))
+
+        self.assertEqual(bg.stats()["bars_updated"], 3)
+        self.assertTrue(windows)
+        self.assertFalse(any(math.isnan(bar.close_price) for bar in windows))
+
+    def test_numpy_applies_symbol_filter(self):
+        bars = [make_bar(minute) for minute in self.MINUTES]
+        windows, _, stats = self.run_numpy(bars, symbol="ag", exchange="SHFE")
+        self.assertEqual(windows, [])
+        self.assertEqual(stats["filtered_count"], len(bars))
+        self.assertEqual(stats["bars_updated"], 0)
+
+        windows, _, stats = self.run_numpy(bars, symbol="rb", exchange="SHFE")
+        self.assertTrue(windows)
+        self.assertEqual(stats["filtered_count"], 0)
+
+    def test_update_bar_after_numpy_independent_of_host_timezone(self):
+        start = dt.datetime(2024, 1, 2, 9, 40, tzinfo=zoneinfo.ZoneInfo("Asia/Shanghai"))
+        bars = [make_bar(minute, start=start) for minute in range(3)]
+        windows = []
+        with host_timezone("UTC"):
+            bg = rbg.BarGenerator(None, 5, windows.append, "1m", strict_bar_order=True)
+            bg.update_bars_numpy(
+                array.array("q", [int(bar.datetime.timestamp() * 1000) for bar in bars]),
+                *[array.array("d", [getattr(bar, name) for bar in bars])
+                  for name in ("open_price", "high_price", "low_price", "close_price", "volume")],
+            )
+            for minute in range(3, 6):
+                bg.update_bar(make_bar(minute, start=start))
+        self.assertEqual([(bar.datetime.hour, bar.datetime.minute, bar.volume) for bar in windows], [(9, 40, 1), (9, 41, 5)])
+
+    @unittest.skipIf(pd is None, "需要 pandas")
+    def test_df_matches_update_bar(self):
+        bars = [make_bar(minute, volume=minute + 1) for minute in self.MINUTES]
+        expected = self.run_update_bar(bars)
+
+        windows, gaps = [], []
+        bg = self.make_generator(windows, gaps)
+        df = pd.DataFrame({
+            "datetime": [bar.datetime for bar in bars],
+            "open": [bar.open_price for bar in bars],
+            "high": [bar.high_price for bar in bars],
+            "low": [bar.low_price for bar in bars],
+            "close": [bar.close_price for bar in bars],
+            "volume": [bar.volume for bar in bars],
+        })
+        bg.update_bars_df(df, "rb", "SHFE", "CTP")
+        self.assertEqual(window_fields(windows), window_fields(expected[0]))
+        self.assertEqual(gaps, expected[1])
+
+
+if __name__ == "__main__":
+    unittest.main()
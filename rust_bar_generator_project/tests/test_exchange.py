@@ -0,0 +1,113 @@
+import pickle
+import unittest
+
+import rust_bar_generator as rbg
+
+from helpers import make_bar
+
+Exchange = rbg.RustExchange
+
+# 枚举中全部内置交易所
+BUILTIN = {name: getattr(Exchange, name) for name in dir(Exchange) if isinstance(getattr(Exchange, name), Exchange)}
+
+
+class ExchangeCategoryTest(unittest.TestCase):
+    def test_all_lists_every_builtin_exchange(self):
+        builtin = [repr(exchange) for exchange in Exchange.all() if isinstance(exchange, Exchange)]
+        self.assertEqual(len(builtin), len(BUILTIN))
+        self.assertEqual(set(builtin), {repr(exchange) for exchange in BUILTIN.values()})
+
+    def test_every_exchange_has_a_category(self):
+        categories = {"chinese", "global_stock", "global_future", "crypto"}
+        for name, exchange in BUILTIN.items():
+            with self.subTest(exchange=name):
+                if name in ("IDEALPRO", "LOCAL"):
+                    self.assertEqual(exchange.category(), "other")
+                else:
+                    self.assertIn(exchange.category(), categories)
+
+    def test_easily_missed_chinese_exchanges(self):
+        for exchange in (Exchange.SGE, Exchange.WXE, Exchange.CFETS):
+            self.assertEqual(exchange.category(), "chinese")
+            self.assertTrue(exchange.is_chinese())
+            self.assertFalse(exchange.is_crypto())
+
+
+
+class UnregisteredExchange:
+    def __reduce__(self):
+        return rbg.CustomExchange, ("TESTG", "TG", "crypto")
+
+
+class RegisterExchangeTest(unittest.TestCase):
+    # 注册表在进程内全局有效，每个用例使用不同的交易所名称
+
+    def test_register_and_parse(self):
+        exchange = rbg.register_exchange("TESTA", "TA", category="crypto")
+        self.assertIsInstance(exchange, rbg.CustomExchange)
+        self.assertEqual(exchange.value, "TA")
+        self.assertEqual(str(exchange), "TA")
+        self.assertEqual(exchange.canonical_name(), "TESTA")
+        self.assertEqual(exchange.category(), "crypto")
+        self.assertEqual(Exchange.parse("TA"), exchange)
+        self.assertEqual(Exchange.parse("testa"), exchange)
+        self.assertIn(repr(exchange), [repr(item) for item in Exchange.all()])
+
+        bar = make_bar(0)
+        bar = rbg.RustBarData(symbol="btc", exchange="TA", gateway_name="G", datetime=bar.datetime)
+        self.assertEqual(bar.exchange, exchange)
+        self.assertEqual(bar.vt_symbol, "btc_TA/G")
+
+    def test_same_registration_returns_equal_exchange(self):
+        first = rbg.register_exchange("TESTB")
+        self.assertEqual(rbg.register_exchange("testb"), first)
+        self.assertEqual(rbg.CustomExchange("TESTB"), first)
+        self.assertEqual(len({first, rbg.register_exchange("TESTB")}), 1)
+
+    def test_no_registration_limit(self):
+        exchanges = [rbg.register_exchange(f"MANY{i}") for i in range(40)]
+        self.assertEqual([exchange.value for exchange in exchanges], [f"MANY{i}" for i in range(40)])
+        self.assertEqual(Exchange.parse("MANY39"), exchanges[-1])
+
+    def test_conflicting_registration(self):
+        rbg.register_exchange("TESTC", "TC")
+        with self.assertRaises(ValueError):
+            rbg.register_exchange("TESTC", "TC2")
+        with self.assertRaises(ValueError):
+            rbg.register_exchange("TESTC", "TC", category="chinese")
+        with self.assertRaises(ValueError):
+            rbg.register_exchange("OTHER", "TC")
+        with self.assertRaises(ValueError):
+            rbg.register_exchange("SHFE")
+        with self.assertRaises(ValueError):
+            Exchange.parse("NOT_REGISTERED")
+
+    def test_generator_exchange_filter(self):
+        exchange = rbg.register_exchange("TESTH")
+        bars = []
+        bg = rbg.BarGenerator(bars.append, exchange="TESTH")
+        self.assertEqual(bg.get_config()["exchange"], exchange)
+        restored = pickle.loads(pickle.dumps(bg))
+        self.assertEqual(restored.get_config()["exchange"], exchange)
+
+    def test_pickle_round_trip(self):
+        exchange = rbg.register_exchange("TESTE", "TE", category="global_future")
+        bar = rbg.RustBarData(symbol="btc", exchange=exchange, gateway_name="G", datetime=make_bar(0).datetime)
+        self.assertEqual(pickle.loads(pickle.dumps(exchange)), exchange)
+        self.assertEqual(pickle.loads(pickle.dumps(Exchange.SHFE)), Exchange.SHFE)
+        restored = pickle.loads(pickle.dumps(bar))
+        self.assertEqual(restored.exchange, exchange)
+        self.assertEqual(restored.exchange.category(), "global_future")
+        self.assertEqual(restored.vt_symbol, "btc_TE/G")
+
+    def test_pickle_registers_on_load(self):
+        # 与另一进程中 register_exchange("TESTG", "TG") 的 pickle 相同，本进程尚未注册
+        data = pickle.dumps(UnregisteredExchange())
+        with self.assertRaises(ValueError):
+            Exchange.parse("TG")
+        self.assertEqual(pickle.loads(data).canonical_name(), "TESTG")
+        self.assertEqual(Exchange.parse("TG").value, "TG")
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,59 @@
+import datetime as dt
+import unittest
+import zoneinfo
+
+import rust_bar_generator as rbg
+
+
+SHANGHAI = zoneinfo.ZoneInfo("Asia/Shanghai")
+
+
+def trading_day(exchange, text):
+    return rbg.BarGenerator.trading_day(dt.datetime.fromisoformat(text).replace(tzinfo=SHANGHAI), exchange)
+
+
+class TradingDayTest(unittest.TestCase):
+    # 2024-01-03 为周三，2024-01-05 为周五，2024-01-08 为周一
+    WEDNESDAY, THURSDAY, MONDAY = dt.date(2024, 1, 3), dt.date(2024, 1, 4), dt.date(2024, 1, 8)
+
+    def assert_days(self, exchange, expected):
+        for text, day in expected.items():
+            self.assertEqual(trading_day(exchange, text), day, f"{exchange} {text}")
+
+    def test_night_session_past_midnight(self):
+        for exchange in ("SHFE", "INE"):
+            self.assert_days(exchange, {
+                "2024-01-03 20:59": self.THURSDAY,
+                "2024-01-03 23:30": self.THURSDAY,
+                "2024-01-04 01:00": self.THURSDAY,
+                "2024-01-04 02:30:00.500": self.THURSDAY,
+                # 夜盘收盘后到日盘之前不属于夜盘
+                "2024-01-04 03:00": self.THURSDAY,
+                "2024-01-04 10:00": self.THURSDAY,
+                "2024-01-05 21:30": self.MONDAY,
+                "2024-01-06 01:00": self.MONDAY,
+            })
+
+    def test_night_session_ending_at_23(self):
+        for exchange in ("DCE", "CZCE"):
+            self.assert_days(exchange, {
+                "2024-01-03 20:59": self.THURSDAY,
+                "2024-01-03 23:00:00.500": self.THURSDAY,
+                # 23:00 收盘之后的行情按自然日处理，不再归属下一交易日
+                "2024-01-03 23:30": self.WEDNESDAY,
+                "2024-01-04 01:00": self.THURSDAY,
+                "2024-01-05 21:30": self.MONDAY,
+            })
+
+    def test_exchanges_without_night_session(self):
+        for exchange in ("CFFEX", "GFEX", "SSE"):
+            self.assert_days(exchange, {
+                "2024-01-03 20:59": self.WEDNESDAY,
+                "2024-01-03 23:30": self.WEDNESDAY,
+                "2024-01-04 01:00": self.THURSDAY,
+                "2024-01-05 21:30": dt.date(2024, 1, 5),
+            })
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,253 @@
+import inspect
+import pickle
+import unittest
+
+import rust_bar_generator as rbg
+
+from helpers import make_bar, make_tick
+
+
+class ResetTest(unittest.TestCase):
+    def test_first_bar_after_reset_starts_from_scratch(self):
+        bars = []
+        bg = rbg.BarGenerator(bars.append, 3, lambda bar: None, "1m")
+        for seconds, price, volume in [(5, 10, 100), (30, 12, 150), (65, 11, 170)]:
+            bg.update_tick(make_tick(seconds, price, volume))
+        self.assertEqual((bars[0].high_price, bars[0].volume), (12, 50))
+
+        bg.reset()
+        for seconds, price, volume in [(70, 20, 500), (90, 21, 520), (125, 22, 530)]:
+            bg.update_tick(make_tick(seconds, price, volume))
+
+        bar = bars[-1]
+        self.assertEqual(len(bars), 2)
+        # 重置前的 09:01 K线（价格 11、累计成交量 170）不计入
+        self.assertEqual((bar.open_price, bar.high_price, bar.low_price), (20, 21, 20))
+        self.assertEqual(bar.volume, 20)
+
+    def test_reset_window_keeps_feeding(self):
+        windows = []
+        bg = rbg.BarGenerator(lambda bar: None, 3, windows.append, "1m")
+        bg.update_bar(make_bar(0))
+        bg.update_bar(make_bar(1))
+        bg.reset_window()
+        bg.update_bar(make_bar(2, volume=5))
+        bg.update_bar(make_bar(3))
+
+        window = windows[-1]
+        self.assertEqual((window.open_price, window.close_price, window.volume), (2, 3, 6))
+
+    def test_reset_inside_callback(self):
+        bars = []
+        bg = None
+
+        def on_bar(bar):
+            bars.append(bar)
+            bg.reset()
+
+        bg = rbg.BarGenerator(on_bar, 1, lambda bar: None, "1m")
+        for seconds, price, volume in [(5, 10, 100), (65, 11, 110), (70, 12, 130), (125, 13, 150)]:
+            bg.update_tick(make_tick(seconds, price, volume))
+
+        # 回调中的 reset 不会死锁；触发回调的 09:01 Tick 在 reset 之后开始新的K线
+        self.assertEqual(len(bars), 2)
+        self.assertEqual((bars[0].datetime.minute, bars[0].close_price), (0, 10))
+        self.assertEqual((bars[1].datetime.minute, bars[1].open_price, bars[1].close_price), (1, 11, 12))
+
+
+
+def chained_generator(windows):
+    """on_bar 中把分钟K线推送给自身的 5 分钟窗口"""
+    holder = []
+    bg = rbg.BarGenerator(lambda bar: holder[0].update_bar(bar), 5, windows.append, "1m")
+    holder.append(bg)
+    return bg
+
+
+def window_fields(bar):
+    return (bar.datetime, bar.open_price, bar.high_price, bar.low_price, bar.close_price, bar.volume)
+
+
+class StateTest(unittest.TestCase):
+    # 每 20 秒一个 Tick，共 11 分钟
+    TICKS = [(seconds, 100 + (seconds // 20) % 7, 10 * seconds) for seconds in range(5, 660, 20)]
+
+    def test_restored_generator_emits_identical_window_bar(self):
+        expected = []
+        reference = chained_generator(expected)
+        for tick in self.TICKS:
+            reference.update_tick(make_tick(*tick))
+
+        windows = []
+        first = chained_generator(windows)
+        half = len(self.TICKS) // 3
+        for tick in self.TICKS[:half]:
+            first.update_tick(make_tick(*tick))
+        state = pickle.loads(pickle.dumps(first.get_state()))
+
+        restored = chained_generator(windows)
+        restored.set_state(state)
+        for tick in self.TICKS[half:]:
+            restored.update_tick(make_tick(*tick))
+
+        self.assertEqual(len(expected), 2)
+        self.assertEqual([window_fields(bar) for bar in windows], [window_fields(bar) for bar in expected])
+
+    def test_rolling_stats_survive_set_state(self):
+        bg = rbg.BarGenerator(lambda bar: None, 1, lambda bar: None, "1m")
+        bg.attach_stats(3)
+        for minute in range(5):
+            bg.update_bar(make_bar(minute, price=10 + minute))
+        self.assertEqual(bg.sma_close(), 13.0)
+        atr = bg.atr()
+
+        restored = rbg.BarGenerator(lambda bar: None, 1, lambda bar: None, "1m")
+        restored.set_state(pickle.loads(pickle.dumps(bg.get_state())))
+        self.assertEqual((restored.sma_close(), restored.atr()), (13.0, atr))
+        restored.update_bar(make_bar(5, price=15))
+        bg.update_bar(make_bar(5, price=15))
+        self.assertEqual((restored.sma_close(), restored.std_close()), (bg.sma_close(), bg.std_close()))
+
+    def test_set_state_keeps_attached_stats_when_state_has_none(self):
+        bg = rbg.BarGenerator(lambda bar: None, 1, lambda bar: None, "1m")
+        bg.update_bar(make_bar(0))
+        state = bg.get_state()
+
+        restored = rbg.BarGenerator(lambda bar: None, 1, lambda bar: None, "1m")
+        restored.attach_stats(2)
+        for minute in range(3):
+            restored.update_bar(make_bar(minute, price=10 + minute))
+        restored.set_state(state)
+        self.assertEqual(restored.sma_close(), 11.5)
+
+    def test_set_state_rejects_other_window(self):
+        bg = rbg.BarGenerator(lambda bar: None, 5, lambda bar: None, "1m")
+        bg.update_bar(make_bar(0))
+        other = rbg.BarGenerator(lambda bar: None, 15, lambda bar: None, "1m")
+        with self.assertRaises(ValueError):
+            other.set_state(bg.get_state())
+
+
+
+RECORDED = {}
+
+
+class Recorder:
+    """可 pickle 的回调：按名字把K线记录到 RECORDED，反序列化后的副本仍写入同一个列表"""
+
+    def __init__(self, name):
+        self.name = name
+        RECORDED[name] = []
+
+    def __call__(self, bar):
+        RECORDED[self.name].append(bar_fields(bar))
+
+
+def bar_fields(bar):
+    return window_fields(bar) + (bar.sub_bar_count,)
+
+
+class PickleMidStreamTest(unittest.TestCase):
+    """中途 pickle 的生成器继续合成，结果与从未 pickle 的生成器一致"""
+
+    def assert_same_after_pickle(self, make_generator, feed, items, names):
+        control = make_generator("control")
+        for item in items:
+            feed(control, item)
+
+        resumed = make_generator("resumed")
+        half = len(items) // 2
+        for item in items[:half]:
+            feed(resumed, item)
+        restored = pickle.loads(pickle.dumps(resumed))
+        for item in items[half:]:
+            feed(restored, item)
+
+        for name in names:
+            self.assertTrue(RECORDED[f"control_{name}"], name)
+            self.assertEqual(RECORDED[f"resumed_{name}"], RECORDED[f"control_{name}"], name)
+
+    def test_windows_and_extra_windows(self):
+        def make_generator(prefix):
+            bg = rbg.BarGenerator(Recorder(f"{prefix}_bar"), 5, Recorder(f"{prefix}_window"), "1m")
+            bg.add_window(15, "1m", Recorder(f"{prefix}_window15"))
+            return bg
+
+        # 在 5 分钟窗口和 15 分钟窗口的中间 pickle
+        bars = [make_bar(minute, price=10 + minute % 7, volume=minute + 1) for minute in range(1, 47)]
+        self.assertEqual(len(bars) // 2, 23)
+        self.assert_same_after_pickle(make_generator, rbg.BarGenerator.update_bar, bars, ["window", "window15"])
+
+    def test_minute_bar_from_ticks(self):
+        def make_generator(prefix):
+            return rbg.BarGenerator(Recorder(f"{prefix}_bar"))
+
+        ticks = [make_tick(seconds, 100 + seconds // 20 % 5, seconds) for seconds in range(5, 400, 20)]
+        self.assert_same_after_pickle(make_generator, rbg.BarGenerator.update_tick, ticks, ["bar"])
+
+    def test_renko(self):
+        def make_generator(prefix):
+            return rbg.BarGenerator(Recorder(f"{prefix}_brick"), brick_size=1.0)
+
+        prices = [100, 100.6, 101.2, 100.4, 99.7, 98.9, 99.5, 100.8, 101.9, 102.4, 100.1, 99.2, 97.8, 98.5, 101.3]
+        ticks = [make_tick(i, price, i * 3) for i, price in enumerate(prices)]
+        self.assert_same_after_pickle(make_generator, rbg.BarGenerator.update_tick, ticks, ["brick"])
+
+
+def on_bar(bar):
+    pass
+
+
+class ConfigTest(unittest.TestCase):
+    CONFIG = dict(
+        on_bar=on_bar, window=5, interval="1h", interval_slice=False, range_size=2.5,
+        heikin_ashi=True, volume_mode="delta", strict_bar_order=True, raise_callback_errors=True,
+        push_status_retention=30, force_generate_after_seconds=1.5, tz="Asia/Tokyo", clock="data",
+        out_of_order="drop", offset_seconds=30.0, fill_window_gaps=3, max_tick_age_seconds=0.25,
+        max_price_change_pct=5.0, label="end", hour_anchor_minute=30, max_gap_minutes=90,
+        bar_update_interval_ms=200, max_buffered_bars=10, symbol="rb2405", exchange="SHFE",
+        expected_sub_bars=5, callback_context={"strategy": "demo"}, price_source="mid",
+    )
+
+    def test_config_covers_every_constructor_parameter(self):
+        parameters = set(inspect.signature(rbg.BarGenerator).parameters) - {"event_loop"}
+        self.assertEqual(set(rbg.BarGenerator().get_config()), parameters)
+
+    def test_config_round_trips(self):
+        bg = rbg.BarGenerator(**self.CONFIG)
+        config = bg.get_config()
+        for key, value in self.CONFIG.items():
+            self.assertEqual(config[key], value, key)
+
+        self.assertEqual(rbg.BarGenerator.from_config(config).get_config(), config)
+        for protocol in range(2, pickle.HIGHEST_PROTOCOL + 1):
+            self.assertEqual(pickle.loads(pickle.dumps(bg, protocol)).get_config(), config, protocol)
+
+    def test_defaults_match_constructor_signature(self):
+        defaults = {
+            name: parameter.default
+            for name, parameter in inspect.signature(rbg.BarGenerator).parameters.items()
+            if name not in ("event_loop", "interval", "anchor", "tz")
+        }
+        config = rbg.BarGenerator().get_config()
+        self.assertEqual({name: config[name] for name in defaults}, defaults)
+
+
+class PoisonedLockTest(unittest.TestCase):
+    def test_generator_keeps_working_after_poison(self):
+        bars = []
+        bg = rbg.BarGenerator(bars.append)
+        bg.update_tick(make_tick(5, 10, 100))
+        bg._poison_lock()
+
+        with self.assertLogs("rust_bar_generator", "WARNING") as logs:
+            bg.update_tick(make_tick(30, 12, 150))
+            bg.update_tick(make_tick(65, 11, 170))
+        self.assertEqual(len(logs.records), 1)
+        self.assertIn("中毒", logs.records[0].getMessage())
+        self.assertEqual((bars[0].open_price, bars[0].high_price, bars[0].volume), (10, 12, 50))
+        self.assertEqual(bg.stats()["ticks_processed"], 3)
+
+
+if __name__ == "__main__":
+    unittest.main()
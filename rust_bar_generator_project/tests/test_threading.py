@@ -0,0 +1,75 @@
+import threading
+import unittest
+
+import rust_bar_generator as rbg
+
+from helpers import T0, make_tick
+
+
+def run_threads(targets, timeout=30):
+    threads = [threading.Thread(target=target, daemon=True) for target in targets]
+    for thread in threads:
+        thread.start()
+    for thread in threads:
+        thread.join(timeout)
+    return [thread for thread in threads if thread.is_alive()]
+
+
+class ConcurrentUpdateTest(unittest.TestCase):
+    def test_threads_hammering_one_generator(self):
+        minutes, ticks_per_minute = 60, 50
+        ticks = [
+            make_tick(minute * 60 + second * 60 / ticks_per_minute, 10 + minute, minute * ticks_per_minute + second)
+            for minute in range(minutes) for second in range(ticks_per_minute)
+        ]
+        bars = []
+        bg = rbg.BarGenerator(bars.append, 1, None, "1m", out_of_order="drop")
+        source = iter(ticks)
+        source_lock = threading.Lock()
+
+        def worker():
+            while True:
+                with source_lock:
+                    tick = next(source, None)
+                if tick is None:
+                    return
+                bg.update_tick(tick)
+
+        self.assertEqual(run_threads([worker] * 8), [])
+        # 线程间交错最多让少数 Tick 乱序被丢弃，每分钟恰好推送一根K线
+        expected = [T0.replace(minute=minute) for minute in range(minutes - 1)]
+        self.assertEqual([bar.datetime for bar in bars], expected)
+        stats = bg.stats()
+        self.assertEqual(stats["ticks_processed"] + stats["rejected_out_of_order"], len(ticks))
+        self.assertEqual(stats["bars_emitted"], minutes - 1)
+
+    def test_callbacks_waiting_on_each_other_raise_instead_of_deadlock(self):
+        barrier = threading.Barrier(2)
+        errors = []
+        generators = {}
+
+        def on_bar(own, other):
+            def callback(bar):
+                barrier.wait(10)
+                try:
+                    generators[other].update_tick(make_tick(70, 10))
+                except RuntimeError as error:
+                    errors.append((own, str(error)))
+            return callback
+
+        generators["a"] = rbg.BarGenerator(on_bar("a", "b"))
+        generators["b"] = rbg.BarGenerator(on_bar("b", "a"))
+        for bg in generators.values():
+            bg.update_tick(make_tick(5, 10))
+
+        def feed(name):
+            return lambda: generators[name].update_tick(make_tick(65, 11))
+
+        self.assertEqual(run_threads([feed("a"), feed("b")], timeout=10), [])
+        # 后开始等待的一方报错，另一方在对方退出后继续
+        self.assertEqual(len(errors), 1)
+        self.assertIn("死锁", errors[0][1])
+
+
+if __name__ == "__main__":
+    unittest.main()
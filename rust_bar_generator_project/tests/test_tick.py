@@ -0,0 +1,413 @@
+import array
+import datetime as dt
+import random
+import struct
+import time
+import types
+import unittest
+import zoneinfo
+
+import rust_bar_generator as rbg
+
+from helpers import T0, host_timezone, make_bar, make_tick
+
+
+class WindowFromTickTest(unittest.TestCase):
+    def test_hour_bars_built_directly_from_ticks(self):
+        hours = []
+        bg = rbg.BarGenerator(lambda bar: None, 1, hours.append, "1h", window_from_tick=True)
+        for seconds, price, volume in [(5, 10, 100), (1800, 12, 130), (3605, 11, 160), (7205, 9, 170)]:
+            bg.update_tick(make_tick(seconds, price, volume))
+
+        self.assertEqual(len(hours), 2)
+        self.assertEqual(hours[0].datetime.hour, 9)
+        self.assertEqual((hours[0].open_price, hours[0].high_price, hours[0].close_price), (10, 12, 12))
+        self.assertEqual(hours[1].datetime.hour, 10)
+
+    def test_update_bar_rejected(self):
+        bg = rbg.BarGenerator(lambda bar: None, 1, lambda bar: None, "1h", window_from_tick=True)
+        with self.assertRaises(ValueError):
+            bg.update_bar(make_bar(0))
+        with self.assertRaises(ValueError):
+            bg.update_bars([make_bar(0), make_bar(1)])
+
+    def test_chaining_update_bar_in_on_bar_is_reported(self):
+        bg = None
+
+        def on_bar(bar):
+            bg.update_bar(bar)
+
+        bg = rbg.BarGenerator(on_bar, 1, lambda bar: None, "1h", window_from_tick=True, raise_callback_errors=True)
+        bg.update_tick(make_tick(5, 10, 100))
+        with self.assertRaises(ValueError):
+            bg.update_tick(make_tick(65, 11, 110))
+
+
+class UpdateTicksArrayTest(unittest.TestCase):
+    SECONDS = [5, 30, 65, 130]
+
+    def feed(self, ts_ns):
+        bars = []
+        bg = rbg.BarGenerator(bars.append, 1, None, "1m")
+        bg.update_ticks_array({
+            "datetime_ns": array.array("q", ts_ns),
+            "last_price": array.array("d", [10.0 + i for i in range(len(ts_ns))]),
+            "volume": array.array("d", [100.0 * (i + 1) for i in range(len(ts_ns))]),
+        }, "rb", "SHFE", "CTP")
+        return bars
+
+    def test_minute_bars(self):
+        bars = self.feed(self.ts_ns())
+        self.assertEqual([bar.datetime for bar in bars], [T0, T0 + dt.timedelta(minutes=1)])
+        self.assertEqual((bars[0].open_price, bars[0].close_price), (10, 11))
+
+    def test_bar_times_independent_of_host_timezone(self):
+        # 时间戳在上海时区下算好，只有合成过程在 UTC 主机上进行
+        ts_ns = self.ts_ns()
+        with host_timezone("UTC"):
+            bars = self.feed(ts_ns)
+        self.assertEqual([bar.datetime for bar in bars], [T0, T0 + dt.timedelta(minutes=1)])
+
+    def test_structured_array_matches_update_tick(self):
+        # 各字段是同一块交错内存上按步长取的视图，与 NumPy 结构化数组的字段视图一样不连续
+        seconds = [5, 30, 65, 90, 130, 200]
+        rows = [(ts, 10.0 + i, 100.0 * (i + 1), 50.0 + i) for i, ts in enumerate(self.ts_ns(seconds))]
+        bars = []
+        bg = rbg.BarGenerator(bars.append, 1, None, "1m")
+        bg.update_ticks_array(StructuredTicks(("datetime_ns", "last_price", "volume", "open_interest"), rows))
+
+        expected = []
+        control = rbg.BarGenerator(expected.append, 1, None, "1m")
+        for second, (_, price, volume, open_interest) in zip(seconds, rows):
+            control.update_tick(make_tick(second, price, volume, open_interest=open_interest))
+        self.assertEqual([bar_summary(bar) for bar in bars], [bar_summary(bar) for bar in expected])
+        self.assertEqual(len(bars), 3)
+
+    def test_missing_optional_fields_default_to_zero(self):
+        rows = [(ts, 10.0 + i) for i, ts in enumerate(self.ts_ns())]
+        bars = []
+        bg = rbg.BarGenerator(bars.append, 1, None, "1m")
+        bg.update_ticks_array(StructuredTicks(("datetime_ns", "last_price"), rows))
+        self.assertEqual([(bar.close_price, bar.volume, bar.open_interest) for bar in bars], [(11, 0, 0), (12, 0, 0)])
+        # 进行中的K线只在读取时生成带时区的 datetime
+        shanghai = zoneinfo.ZoneInfo("Asia/Shanghai")
+        self.assertEqual(bg.current_bar().datetime, (T0 + dt.timedelta(seconds=130)).replace(tzinfo=shanghai))
+
+    def test_mismatched_lengths(self):
+        bg = rbg.BarGenerator(lambda bar: None, 1, None, "1m")
+        with self.assertRaisesRegex(ValueError, "字段长度不一致.*volume"):
+            bg.update_ticks_array({
+                "datetime_ns": array.array("q", self.ts_ns()),
+                "last_price": array.array("d", [10.0] * 4),
+                "volume": array.array("d", [1.0] * 3),
+            })
+        self.assertEqual(bg.stats()["ticks_processed"], 0)
+
+    def ts_ns(self, seconds=SECONDS):
+        return [int((T0 + dt.timedelta(seconds=s)).timestamp()) * 10**9 for s in seconds]
+
+
+class StructuredTicks:
+    """模拟 NumPy 结构化数组：第一个字段为 int64，其余为 float64，按字段名返回不连续的视图"""
+
+    def __init__(self, names, rows):
+        self.dtype = types.SimpleNamespace(names=names)
+        record = struct.Struct("<q" + "d" * (len(names) - 1))
+        self.buffer = bytearray(b"".join(record.pack(*row) for row in rows))
+
+    def __getitem__(self, name):
+        index = self.dtype.names.index(name)
+        return memoryview(self.buffer).cast("q" if index == 0 else "d")[index::len(self.dtype.names)]
+
+
+def bar_summary(bar):
+    return (bar.datetime, bar.open_price, bar.high_price, bar.low_price, bar.close_price, bar.volume, bar.open_interest)
+
+
+class GenerateBarEventTest(unittest.TestCase):
+    def test_push_status_bounded_over_a_week(self):
+        bg = rbg.BarGenerator(lambda bar: None, 1, None, "1m", push_status_retention=60, force_generate=False)
+        with self.assertLogs("rust_bar_generator", "WARNING"):
+            for minute in range(7 * 24 * 60):
+                bg.update_tick(make_tick(minute * 60 + 5, 10, minute))
+                now = T0 + dt.timedelta(minutes=minute + 3)
+                self.assertIsNotNone(bg.generate_bar_event(None, now))
+                # 同一根K线在保留期内不会重复处理
+                self.assertIsNone(bg.generate_bar_event(None, now))
+                if minute % 60 == 0:
+                    self.assertLessEqual(len(bg.get_state()["bar_push_status"]), 60)
+
+        self.assertEqual(len(bg.get_state()["bar_push_status"]), 60)
+
+    def test_data_clock_gap_forces_one_bar(self):
+        bars = []
+        bg = rbg.BarGenerator(bars.append, clock="data")
+        bg.update_tick(make_tick(5, 10, 100))
+        bg.update_tick(make_tick(30, 11, 110))
+        # 系统时间远晚于行情，数据时钟未越过 force_generate_after_seconds（默认 120 秒）时不强制合成
+        self.assertIsNone(bg.generate_bar_event(None))
+        bg.set_backtest_time(T0 + dt.timedelta(minutes=3))
+        with self.assertLogs("rust_bar_generator", "WARNING"):
+            result = bg.generate_bar_event(None)
+        self.assertEqual((result["bar_time"].replace(tzinfo=None), result["forced"]), (T0 + dt.timedelta(seconds=30), True))
+        # 与定时器触发时一样，强制合成的K线时间取数据时钟的前一分钟
+        self.assertEqual(
+            [(bar.datetime, bar.close_price, bar.is_forced) for bar in bars], [(T0 + dt.timedelta(minutes=2), 11, True)]
+        )
+        # 同一根K线再次检查不会重复推送
+        self.assertIsNone(bg.generate_bar_event(None))
+        self.assertEqual(len(bars), 1)
+
+
+
+class MinuteRollTest(unittest.TestCase):
+    """分钟数相同但相差一小时的 Tick 开始新的K线，不会合并"""
+
+    def run_ticks(self, times):
+        bars = []
+        bg = rbg.BarGenerator(bars.append)
+        for index, (hour, minute, second) in enumerate(times):
+            bg.update_tick(make_tick(0, 10 + index, 100 + index, start=T0.replace(hour=hour, minute=minute, second=second)))
+        return [(bar.datetime, bar.open_price, bar.close_price) for bar in bars]
+
+    def test_tick_exactly_one_hour_later(self):
+        self.assertEqual(
+            self.run_ticks([(9, 0, 5), (10, 0, 5), (10, 1, 0)]),
+            [(T0.replace(hour=9), 10, 10), (T0.replace(hour=10), 11, 11)],
+        )
+
+    def test_tick_one_hour_later_with_earlier_second(self):
+        self.assertEqual(
+            self.run_ticks([(10, 5, 30), (11, 5, 10), (11, 6, 0)]),
+            [(T0.replace(hour=10, minute=5), 10, 10), (T0.replace(hour=11, minute=5), 11, 11)],
+        )
+
+
+class VolumeModeTest(unittest.TestCase):
+    """两种数据源按各自的模式合成，得到相同的K线成交量"""
+
+    # (累计成交量, 最新一笔成交量)：第一个 Tick 用自身的最新一笔成交量
+    CUMULATIVE_FEED = [(100, 3), (105, 5), (112, 7), (120, 8), (121, 1)]
+    # 逐笔成交推送的 volume 是滚动 24 小时统计，不能差分
+    DELTA_FEED = [(9000, 3), (8990, 5), (9100, 7), (8000, 8), (8500, 1)]
+
+    def run_feed(self, feed, exchange="SHFE", **kwargs):
+        bars = []
+        bg = rbg.BarGenerator(bars.append, **kwargs)
+        for index, (volume, last_volume) in enumerate(feed):
+            seconds = 60 if index == len(feed) - 1 else index * 10
+            bg.update_tick(rbg.RustTickData(
+                "btc", exchange, "G", T0 + dt.timedelta(seconds=seconds),
+                last_price=10, volume=volume, last_volume=last_volume,
+            ))
+        return [bar.volume for bar in bars]
+
+    def test_cumulative(self):
+        self.assertEqual(self.run_feed(self.CUMULATIVE_FEED, volume_mode="cumulative"), [23])
+        self.assertEqual(self.run_feed(self.CUMULATIVE_FEED), [23])
+
+    def test_delta(self):
+        self.assertEqual(self.run_feed(self.DELTA_FEED, volume_mode="delta"), [23])
+
+    def test_auto_picks_mode_by_exchange(self):
+        self.assertEqual(self.run_feed(self.DELTA_FEED, exchange="OKX", volume_mode="auto"), [23])
+        self.assertEqual(self.run_feed(self.CUMULATIVE_FEED, exchange="SHFE", volume_mode="auto"), [23])
+
+
+class BoundaryVolumeTest(unittest.TestCase):
+    TICKS = [(0, 100), (20, 110), (40, 130), (60, 160), (80, 170), (120, 200)]
+
+    def run_ticks(self, boundary_volume):
+        bars = []
+        bg = rbg.BarGenerator(bars.append, boundary_volume=boundary_volume)
+        for seconds, volume in self.TICKS:
+            bg.update_tick(make_tick(seconds, 10, volume))
+        return [bar.volume for bar in bars]
+
+    def test_new_bar(self):
+        # 09:01:00 相对 09:00:40 的 30 手记在 09:01 的K线上
+        self.assertEqual(self.run_ticks("new_bar"), [30, 40])
+
+    def test_old_bar(self):
+        self.assertEqual(self.run_ticks("old_bar"), [60, 40])
+
+
+class OutOfOrderTickTest(unittest.TestCase):
+    def test_drop_ignores_older_ticks_but_accepts_equal_timestamp(self):
+        bars, rejected = [], []
+        bg = rbg.BarGenerator(
+            bars.append, 1, None, "1m", out_of_order="drop",
+            on_tick_rejected=lambda tick, reason: rejected.append((tick.last_price, reason)),
+        )
+        for seconds, price, volume in [(5, 10, 100), (20, 11, 110), (20, 12, 120), (10, 13, 130), (65, 14, 140)]:
+            bg.update_tick(make_tick(seconds, price, volume))
+
+        # 与上一个 Tick 时间相同的 Tick 照常合成，更早的 Tick 被忽略
+        self.assertEqual(rejected, [(13, "out_of_order")])
+        self.assertEqual(bg.stats()["rejected_out_of_order"], 1)
+        self.assertEqual((bars[0].high_price, bars[0].close_price, bars[0].volume), (12, 12, 20))
+
+    def test_raise_reports_both_timestamps(self):
+        bg = rbg.BarGenerator(lambda bar: None, 1, None, "1m", out_of_order="raise")
+        bg.update_tick(make_tick(20, 10, 100))
+        bg.update_tick(make_tick(20, 11, 110))
+        with self.assertRaises(ValueError) as context:
+            bg.update_tick(make_tick(10, 12, 120))
+        self.assertIn("09:00:10", str(context.exception))
+        self.assertIn("09:00:20", str(context.exception))
+
+
+
+class PriceFilterTest(unittest.TestCase):
+    def feed(self, ticks, **kwargs):
+        bars, rejected = [], []
+        bg = rbg.BarGenerator(
+            bars.append, 1, None, "1m",
+            on_tick_rejected=lambda tick, reason: rejected.append((tick.last_price, reason)), **kwargs,
+        )
+        for seconds, price, volume in ticks:
+            bg.update_tick(make_tick(seconds, price, volume, limit_up=110, limit_down=90))
+        return bars, rejected, bg.stats()
+
+    def test_price_exactly_at_limit_accepted(self):
+        bars, rejected, stats = self.feed([(5, 100, 100), (20, 110, 110), (30, 90, 120), (40, 111, 130), (50, 89, 140), (65, 100, 150)])
+        self.assertEqual(rejected, [(111, "limit"), (89, "limit")])
+        self.assertEqual(stats["rejected_limit"], 2)
+        self.assertEqual((bars[0].high_price, bars[0].low_price), (110, 90))
+        self.assertTrue(bars[0].touched_limit_up)
+        self.assertTrue(bars[0].touched_limit_down)
+
+    def test_spike_rejected(self):
+        bars, rejected, stats = self.feed([(5, 100, 100), (20, 108, 110), (30, 101, 120), (65, 102, 130)], max_price_change_pct=5)
+        self.assertEqual(rejected, [(108, "spike")])
+        self.assertEqual(stats["rejected_spike"], 1)
+        self.assertEqual((bars[0].high_price, bars[0].close_price), (101, 101))
+
+
+
+class TickTimestampTest(unittest.TestCase):
+    """直接读取 datetime 字段得到的时间与 timestamp() 一致"""
+
+    def tick_time(self, value):
+        bg = rbg.BarGenerator(lambda bar: None)
+        bg.update_tick(rbg.RustTickData("rb", "SHFE", "CTP", value, last_price=10))
+        return bg.stats()["last_tick_time"]
+
+    def assert_matches_timestamp(self, values):
+        for value in values:
+            self.assertEqual(self.tick_time(value), round(value.timestamp() * 1000), value)
+
+    def random_datetimes(self, count, tzinfo=None):
+        rng = random.Random(1130)
+        start = dt.datetime(2000, 1, 1)
+        return [
+            (start + dt.timedelta(seconds=rng.randrange(36 * 365 * 86400), milliseconds=rng.randrange(1000)))
+            .replace(tzinfo=tzinfo, fold=rng.randrange(2))
+            for _ in range(count)
+        ]
+
+    def test_naive_and_shanghai_aware(self):
+        self.assert_matches_timestamp(self.random_datetimes(500))
+        self.assert_matches_timestamp(self.random_datetimes(500, zoneinfo.ZoneInfo("Asia/Shanghai")))
+
+    def test_dst_fold_and_gap(self):
+        new_york = zoneinfo.ZoneInfo("America/New_York")
+        # 2024-11-03 01:30 出现两次，2024-03-10 02:30 不存在
+        edge_cases = [
+            dt.datetime(2024, 11, 3, 1, 30, fold=fold) for fold in (0, 1)
+        ] + [
+            dt.datetime(2024, 3, 10, 2, 30, fold=fold) for fold in (0, 1)
+        ]
+        aware = [value.replace(tzinfo=new_york) for value in edge_cases]
+        self.assert_matches_timestamp(aware)
+        with host_timezone("America/New_York"):
+            self.assert_matches_timestamp(edge_cases + aware)
+            self.assert_matches_timestamp(self.random_datetimes(300))
+
+
+
+class CallbackErrorTest(unittest.TestCase):
+    def failing_on_bar(self, bars):
+        def on_bar(bar):
+            bars.append(bar)
+            if len(bars) == 1:
+                raise KeyError("strategy")
+        return on_bar
+
+    def test_default_logs_and_continues(self):
+        bars = []
+        bg = rbg.BarGenerator(self.failing_on_bar(bars))
+        with self.assertLogs("rust_bar_generator", "ERROR"):
+            for seconds in (5, 65, 125):
+                bg.update_tick(make_tick(seconds, 10, seconds))
+        self.assertEqual(len(bars), 2)
+        self.assertIsInstance(bg.last_callback_error, KeyError)
+
+    def test_raise_keeps_bar_emitted(self):
+        bars = []
+        bg = rbg.BarGenerator(self.failing_on_bar(bars), raise_callback_errors=True)
+        bg.update_tick(make_tick(5, 10, 5))
+        with self.assertRaises(ValueError) as caught:
+            bg.update_tick(make_tick(65, 11, 65))
+        self.assertIsInstance(caught.exception.__cause__, KeyError)
+        self.assertIsNone(bg.last_callback_error)
+        # 抛出前K线已推送，同一分钟的后续 Tick 不会再次回调
+        bg.update_tick(make_tick(70, 12, 70))
+        bg.update_tick(make_tick(125, 13, 125))
+        self.assertEqual([bar.datetime.minute for bar in bars], [0, 1])
+        self.assertEqual(bg.stats()["bars_emitted"], 2)
+
+
+class AutoGenerateTest(unittest.TestCase):
+    def wait_for(self, condition, timeout=5):
+        deadline = time.monotonic() + timeout
+        while not condition() and time.monotonic() < deadline:
+            time.sleep(0.02)
+        return condition()
+
+    def feed_current_tick(self, bg):
+        bg.update_tick(make_tick(0, 10, 100, start=dt.datetime.now() - dt.timedelta(seconds=1)))
+
+    def test_start_pushes_stale_bar_and_stop(self):
+        bars = []
+        bg = rbg.BarGenerator(bars.append, force_generate_after_seconds=0.2)
+        self.feed_current_tick(bg)
+        self.assertFalse(bg.auto_generate_running)
+
+        bg.start_auto_generate(0.05)
+        self.assertTrue(bg.auto_generate_running)
+        with self.assertRaises(ValueError):
+            bg.start_auto_generate(0.05)
+        with self.assertLogs("rust_bar_generator", "WARNING"):
+            self.assertTrue(self.wait_for(lambda: len(bars) == 1))
+
+        bg.stop_auto_generate()
+        self.assertFalse(bg.auto_generate_running)
+        bg.stop_auto_generate()
+        # 停止后可以重新启动
+        bg.start_auto_generate(0.05)
+        self.assertTrue(bg.auto_generate_running)
+        bg.stop_auto_generate()
+        self.assertEqual(len(bars), 1)
+
+    def test_respects_staleness_threshold(self):
+        bars = []
+        bg = rbg.BarGenerator(bars.append, force_generate_after_seconds=3600)
+        self.feed_current_tick(bg)
+        bg.start_auto_generate(0.02)
+        time.sleep(0.3)
+        bg.stop_auto_generate()
+        self.assertEqual(bars, [])
+
+    def test_rejected_for_data_clock_and_bad_interval(self):
+        with self.assertRaises(ValueError):
+            rbg.BarGenerator(lambda bar: None, clock="data").start_auto_generate()
+        bg = rbg.BarGenerator(lambda bar: None)
+        for interval in (0, -1, float("nan")):
+            with self.assertRaises(ValueError):
+                bg.start_auto_generate(interval)
+        self.assertFalse(bg.auto_generate_running)
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -0,0 +1,92 @@
+import unittest
+
+import rust_bar_generator as rbg
+
+from helpers import make_tick
+
+
+def feed(bg, ticks):
+    """ticks 为 (秒, 价格, 累计成交量)"""
+    for seconds, price, volume in ticks:
+        bg.update_tick(make_tick(seconds, price, volume))
+
+
+def prices(bars):
+    return [(bar.open_price, bar.high_price, bar.low_price, bar.close_price) for bar in bars]
+
+
+class RenkoTest(unittest.TestCase):
+    def make_generator(self):
+        bricks = []
+        return rbg.BarGenerator(bricks.append, brick_size=1.0), bricks
+
+    def test_up_trend(self):
+        bg, bricks = self.make_generator()
+        feed(bg, [(0, 100, 10), (1, 100.5, 12), (2, 101, 15), (3, 101.8, 16), (4, 102.2, 20)])
+        self.assertEqual(prices(bricks), [(100, 101, 100, 101), (101, 102, 101, 102)])
+        self.assertEqual([brick.volume for brick in bricks], [5, 5])
+        self.assertTrue(all(brick.interval == rbg.RustInterval.TICK for brick in bricks))
+
+    def test_reversal_needs_two_bricks(self):
+        bg, bricks = self.make_generator()
+        feed(bg, [(0, 100, 0), (1, 101, 1), (2, 102, 2)])
+        # 上一块砖收于 102，回落一个砖块不反转
+        feed(bg, [(3, 101.2, 3), (4, 101, 4)])
+        self.assertEqual(len(bricks), 2)
+        feed(bg, [(5, 100, 5), (6, 99, 6)])
+        self.assertEqual(prices(bricks[2:]), [(101, 101, 100, 100), (100, 100, 99, 99)])
+
+    def test_gap_spanning_five_bricks(self):
+        bg, bricks = self.make_generator()
+        feed(bg, [(0, 100, 0), (1, 105.3, 8)])
+        self.assertEqual(prices(bricks), [(100 + i, 101 + i, 100 + i, 101 + i) for i in range(5)])
+        tick_time = make_tick(1, 0).datetime
+        self.assertTrue(all(brick.datetime == tick_time for brick in bricks))
+        # 成交量只记在该 Tick 完成的最后一块砖上
+        self.assertEqual([brick.volume for brick in bricks], [0, 0, 0, 0, 8])
+
+
+
+class RangeBarTest(unittest.TestCase):
+    def test_bar_closes_when_range_reached(self):
+        bars = []
+        bg = rbg.BarGenerator(bars.append, range_size=2.0)
+        feed(bg, [(0, 100, 0), (1, 101, 1), (2, 102, 3), (3, 101, 4), (4, 100, 6)])
+        # 下一根K线以收盘 Tick 的价格开盘
+        self.assertEqual(prices(bars), [(100, 102, 100, 102), (102, 102, 100, 100)])
+        self.assertEqual([bar.volume for bar in bars], [3, 3])
+
+    def test_jump_closes_at_tick_without_intermediate_bars(self):
+        bars = []
+        bg = rbg.BarGenerator(bars.append, range_size=2.0)
+        feed(bg, [(0, 100, 0), (1, 100.5, 1), (2, 106, 2), (3, 107, 3)])
+        self.assertEqual(prices(bars), [(100, 106, 100, 106)])
+        self.assertEqual(bars[0].high_price - bars[0].low_price, 6)
+        self.assertEqual(bg.current_bar().open_price, 106)
+
+    def test_session_reset(self):
+        bars = []
+        bg = rbg.BarGenerator(bars.append, range_size=2.0, range_session_reset=True)
+        # 周二 14:59 收盘前的K线未走完区间，21:00 夜盘属于下一交易日
+        feed(bg, [(5 * 3600 + 59 * 60, 100, 0), (5 * 3600 + 59 * 60 + 1, 101, 1), (12 * 3600, 100.5, 2)])
+        self.assertEqual(prices(bars), [(100, 101, 100, 101)])
+        self.assertEqual(bg.current_bar().open_price, 100.5)
+
+
+
+class ImbalanceBarTest(unittest.TestCase):
+    def test_unchanged_price_inherits_previous_sign(self):
+        bars = []
+        bg = rbg.BarGenerator(bars.append, imbalance_threshold=10.0)
+        # 上涨 3 手，平价 4 手沿用买方向；下跌 2 手，之后平价的成交都沿用卖方向
+        feed(bg, [(0, 100, 0), (1, 101, 3), (2, 101, 7), (3, 100, 9), (4, 100, 15)])
+        self.assertEqual(bars, [])
+        self.assertEqual((bg.current_bar().buy_volume, bg.current_bar().sell_volume), (7, 8))
+
+        feed(bg, [(5, 100, 30)])
+        self.assertEqual(len(bars), 1)
+        self.assertEqual((bars[0].buy_volume, bars[0].sell_volume, bars[0].volume), (7, 23, 30))
+
+
+if __name__ == "__main__":
+    unittest.main()
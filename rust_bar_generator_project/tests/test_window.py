@@ -0,0 +1,319 @@
+import datetime as dt
+import unittest
+import zoneinfo
+
+import rust_bar_generator as rbg
+
+from helpers import T0, host_timezone, make_bar
+
+SHANGHAI = zoneinfo.ZoneInfo("Asia/Shanghai")
+NEW_YORK = zoneinfo.ZoneInfo("America/New_York")
+
+
+class FillWindowGapsTest(unittest.TestCase):
+    START = dt.datetime(2024, 1, 2, 9, 40, tzinfo=SHANGHAI)
+    # 09:42、09:47 没有K线
+    MINUTES = [0, 1, 3, 4, 5, 6, 8, 9, 10, 11]
+
+    def feed(self):
+        windows = []
+        bg = rbg.BarGenerator(None, 5, windows.append, "1m", fill_window_gaps=3, strict_bar_order=True)
+        for minute in self.MINUTES:
+            bg.update_bar(make_bar(minute, start=self.START))
+        return windows
+
+    def test_missing_minutes_filled(self):
+        windows = self.feed()
+        self.assertEqual(
+            [(bar.datetime.minute, bar.volume, bar.high_price, bar.low_price) for bar in windows],
+            [(40, 1, 0, 0), (41, 4, 5, 1), (46, 4, 10, 6)],
+        )
+
+    def test_filled_bars_independent_of_host_timezone(self):
+        with host_timezone("UTC"):
+            windows = self.feed()
+        self.assertEqual(
+            [(bar.datetime.hour, bar.datetime.minute, bar.volume) for bar in windows],
+            [(9, 40, 1), (9, 41, 4), (9, 46, 4)],
+        )
+
+
+
+def feed_bars(interval, window, bar_interval, step, count, start):
+    windows = []
+    bg = rbg.BarGenerator(None, window, windows.append, interval)
+    for i in range(count):
+        bg.update_bar(rbg.RustBarData(
+            symbol="rb", exchange="SHFE", gateway_name="CTP", datetime=start + step * i, interval=bar_interval,
+            volume=1, open_price=i, high_price=i, low_price=i, close_price=i,
+        ))
+    return [(bar.datetime, bar.open_price, bar.close_price, bar.volume) for bar in windows]
+
+
+class FirstWindowTest(unittest.TestCase):
+    def test_window_one_passes_bars_through(self):
+        windows = []
+        bg = rbg.BarGenerator(None, 1, windows.append, "1m")
+        bars = [
+            make_bar(minute, volume=minute + 1, open_price=10 + minute, high_price=12 + minute,
+                     low_price=9 + minute, close_price=11 + minute)
+            for minute in range(4)
+        ]
+        for bar in bars:
+            bg.update_bar(bar)
+
+        fields = lambda bar: (bar.datetime, bar.open_price, bar.high_price, bar.low_price, bar.close_price, bar.volume)
+        self.assertEqual([fields(bar) for bar in windows], [fields(bar) for bar in bars])
+
+    def test_bars_an_hour_apart_are_separate_windows(self):
+        windows = []
+        bg = rbg.BarGenerator(None, 1, windows.append, "1m")
+        for minute in (5, 65, 66):
+            bg.update_bar(make_bar(minute))
+        self.assertEqual([(bar.datetime.hour, bar.datetime.minute) for bar in windows], [(9, 5), (10, 5), (10, 6)])
+
+    def test_first_bar_on_boundary_completes_alone(self):
+        windows = feed_bars("1m", 3, "1m", dt.timedelta(minutes=1), 7, T0)
+        self.assertEqual([(bar[0].minute, bar[1], bar[2], bar[3]) for bar in windows], [(0, 0, 0, 1), (1, 1, 3, 3), (4, 4, 6, 3)])
+
+    def test_two_week_window_first_bar_on_boundary(self):
+        # 2024-01-01 所在周的绝对周序号为偶数
+        windows = feed_bars(rbg.RustInterval.WEEKLY, 2, rbg.RustInterval.DAILY, dt.timedelta(days=1), 30, dt.datetime(2024, 1, 1))
+        self.assertEqual([bar[3] for bar in windows], [8, 14])
+
+    def test_daily_count_window_first_window_covers_one_day(self):
+        windows = feed_bars(rbg.RustInterval.DAILY, 2, rbg.RustInterval.HOUR, dt.timedelta(hours=4), 40, dt.datetime(2024, 1, 1))
+        self.assertEqual([(bar[0].day, bar[3]) for bar in windows], [(2, 7), (3, 12), (5, 12)])
+
+
+
+class DailyWindowTest(unittest.TestCase):
+    def test_two_trading_day_windows_across_weekend(self):
+        windows = []
+        bg = rbg.BarGenerator(None, 2, windows.append, rbg.RustInterval.DAILY)
+        start = dt.datetime(2024, 1, 1)
+        days = [start + dt.timedelta(days=i) for i in range(14) if (start + dt.timedelta(days=i)).weekday() < 5]
+        for i, day in enumerate(days):
+            bg.update_bar(rbg.RustBarData(
+                symbol="rb", exchange="SHFE", gateway_name="CTP", datetime=day, interval=rbg.RustInterval.DAILY,
+                volume=1, open_price=i, high_price=i, low_price=i, close_price=i,
+            ))
+        # 按交易日计数：周五与下周一组成一个窗口，周末不占位
+        self.assertEqual([(bar.open_price, bar.close_price) for bar in windows], [(0, 1), (2, 3), (4, 5), (6, 7), (8, 9)])
+        self.assertEqual({(bar.volume, bar.sub_bar_count, bar.is_partial) for bar in windows}, {(2, 2, False)})
+
+    def test_weekly_expected_sub_bars_counts_business_days(self):
+        windows = []
+        bg = rbg.BarGenerator(None, 1, windows.append, rbg.RustInterval.WEEKLY)
+        start = dt.datetime(2024, 1, 1)
+        for i in range(15):
+            day = start + dt.timedelta(days=i)
+            if day.weekday() < 5:
+                bg.update_bar(make_bar(0, start=day, interval="1d"))
+        # 第一根K线单独构成第一个窗口（见 FirstWindowTest），之后的完整周含 5 个交易日
+        self.assertEqual([(bar.sub_bar_count, bar.expected_sub_bars) for bar in windows], [(1, 5), (5, 5), (5, 5)])
+
+
+class WeeklyWindowTest(unittest.TestCase):
+    def test_two_week_window_across_iso_week_53(self):
+        # 2020 年有 ISO 第 53 周（12-28 至 2021-01-03）
+        windows = feed_bars(rbg.RustInterval.WEEKLY, 2, rbg.RustInterval.DAILY, dt.timedelta(days=1), 70, dt.datetime(2020, 11, 30))
+        self.assertEqual([bar[3] for bar in windows], [1, 14, 14, 14, 14])
+        labels = [bar[0] for bar in windows[1:]]
+        self.assertEqual([later - earlier for earlier, later in zip(labels, labels[1:])], [dt.timedelta(days=14)] * 3)
+        # 2020-12-22 至 2021-01-04 的窗口跨过第 53 周，仍是完整的两周
+        self.assertIn(dt.datetime(2021, 1, 5), labels)
+
+
+class WindowValidationTest(unittest.TestCase):
+    def test_zero_window_raises(self):
+        with self.assertRaisesRegex(ValueError, "window must be >= 1"):
+            rbg.BarGenerator(None, 0, lambda bar: None, "1m")
+        bg = rbg.BarGenerator(None, 5, lambda bar: None, "1m")
+        with self.assertRaisesRegex(ValueError, "window must be >= 1"):
+            bg.add_window(0, "1m", lambda bar: None)
+        with self.assertRaisesRegex(ValueError, "window must be >= 1"):
+            rbg.recommended_interval_slice("1m", 0)
+
+    def test_uneven_slice_warns(self):
+        with self.assertLogs("rust_bar_generator", "WARNING") as logs:
+            rbg.BarGenerator(None, 7, lambda bar: None, "1m")
+        self.assertIn("window=7", logs.output[0])
+        bg = rbg.BarGenerator(None, 5, lambda bar: None, "1m")
+        with self.assertLogs("rust_bar_generator", "WARNING") as logs:
+            bg.add_window(5, "1h", lambda bar: None)
+        self.assertIn("window=5", logs.output[0])
+
+    def test_even_slice_does_not_warn(self):
+        with self.assertNoLogs("rust_bar_generator", "WARNING"):
+            rbg.BarGenerator(None, 15, lambda bar: None, "1m")
+            rbg.BarGenerator(None, 120, lambda bar: None, "1m")
+            rbg.BarGenerator(None, 7, lambda bar: None, "1m", interval_slice=False)
+
+
+class AnchorTest(unittest.TestCase):
+    """返回每个窗口第一根和最后一根K线的时间，窗口在落在边界上的K线处完成（含该K线）"""
+
+    def window_spans(self, start, count, step, interval, window, bar_interval="1m", **kwargs):
+        windows = []
+        bg = rbg.BarGenerator(None, window, windows.append, interval, **kwargs)
+        for i in range(count):
+            bg.update_bar(make_bar(0, price=i, start=start + step * i, interval=bar_interval))
+        return [(start + step * int(bar.open_price), start + step * int(bar.close_price)) for bar in windows]
+
+    def closes(self, spans):
+        return [last.strftime("%H:%M") for _, last in spans]
+
+    def test_us_equity_session_anchored_at_0930(self):
+        start = dt.datetime(2024, 1, 2, 9, 30, tzinfo=NEW_YORK)
+        minute = dt.timedelta(minutes=1)
+        spans = self.window_spans(start, 150, minute, "1m", 60, tz="America/New_York", anchor="09:30")
+        self.assertEqual(self.closes(spans), ["09:30", "10:30", "11:30"])
+        self.assertEqual(spans[1][0], start + minute)
+        # 默认从零点数分钟，整点切分
+        default = self.window_spans(start, 150, minute, "1m", 60, tz="America/New_York")
+        self.assertEqual(self.closes(default), ["10:00", "11:00"])
+
+    def test_night_session_anchored_at_2100_across_midnight(self):
+        start = dt.datetime(2024, 1, 2, 21, 0)
+        minute = dt.timedelta(minutes=1)
+        # 21:00 至次日 02:30 的夜盘
+        spans = self.window_spans(start, 331, minute, "1m", 120, anchor="21:00")
+        self.assertEqual(self.closes(spans), ["21:00", "23:00", "01:00"])
+        self.assertEqual(spans[2], (dt.datetime(2024, 1, 2, 23, 1), dt.datetime(2024, 1, 3, 1, 0)))
+        self.assertEqual(self.closes(self.window_spans(start, 331, minute, "1m", 120)), ["22:00", "00:00", "02:00"])
+
+    def test_hourly_window_anchored_at_2100_across_midnight(self):
+        start = dt.datetime(2024, 1, 2, 21, 0)
+        hour = dt.timedelta(hours=1)
+        spans = self.window_spans(start, 6, hour, "1h", 2, bar_interval="1h", anchor="21:00")
+        self.assertEqual(self.closes(spans), ["21:00", "23:00", "01:00"])
+        self.assertEqual(spans[2], (dt.datetime(2024, 1, 3, 0, 0), dt.datetime(2024, 1, 3, 1, 0)))
+        self.assertEqual(self.closes(self.window_spans(start, 6, hour, "1h", 2, bar_interval="1h")), ["22:00", "00:00", "02:00"])
+
+
+class DuplicateBarTest(unittest.TestCase):
+    def feed(self, policy, bars):
+        windows = []
+        bg = rbg.BarGenerator(None, 5, windows.append, "1m", on_duplicate_bar=policy)
+        for bar in bars:
+            bg.update_bar(bar)
+        return windows
+
+    def repeated_minute(self, policy):
+        # 09:02 重复推送一次，第二根成交量 10、最高价 50
+        bars = [make_bar(1), make_bar(2), make_bar(2, volume=10, high_price=50), make_bar(3), make_bar(4), make_bar(5)]
+        windows = self.feed(policy, bars)
+        self.assertEqual(len(windows), 1)
+        return windows[0].volume, windows[0].high_price, windows[0].sub_bar_count
+
+    def test_skip(self):
+        self.assertEqual(self.repeated_minute("skip"), (5, 5, 5))
+
+    def test_replace(self):
+        self.assertEqual(self.repeated_minute("replace"), (14, 50, 5))
+
+    def test_add(self):
+        self.assertEqual(self.repeated_minute("add"), (15, 50, 6))
+
+    def test_replace_after_window_pushed_is_skipped(self):
+        bars = [make_bar(m) for m in range(1, 6)] + [make_bar(5, volume=10)] + [make_bar(m) for m in range(6, 11)]
+        self.assertEqual([bar.volume for bar in self.feed("replace", bars)], [5, 5])
+
+    def test_default_is_skip(self):
+        windows = []
+        bg = rbg.BarGenerator(None, 5, windows.append, "1m")
+        for bar in [make_bar(1), make_bar(2), make_bar(2, volume=10), make_bar(3), make_bar(4), make_bar(5)]:
+            bg.update_bar(bar)
+        self.assertEqual([bar.volume for bar in windows], [5])
+
+
+class MonthlyWindowTest(unittest.TestCase):
+    def feed_weekdays(self, window, start, end):
+        windows = []
+        bg = rbg.BarGenerator(None, window, windows.append, rbg.RustInterval.MONTHLY)
+        day = start
+        while day <= end:
+            if day.weekday() < 5:
+                bg.update_bar(make_bar(0, price=day.toordinal(), start=day, interval="1d"))
+            day += dt.timedelta(days=1)
+        return [
+            (bar.datetime, dt.date.fromordinal(int(bar.open_price)), dt.date.fromordinal(int(bar.close_price)))
+            for bar in windows
+        ]
+
+    def test_month_window_completes_before_next_month(self):
+        windows = self.feed_weekdays(1, dt.datetime(2024, 10, 28), dt.datetime(2025, 1, 3))
+        # 下个月的第一根日K线开始新窗口，12 月的窗口标签跨年为次年 1 月 1 日
+        self.assertEqual(windows, [
+            (dt.datetime(2024, 11, 1), dt.date(2024, 10, 28), dt.date(2024, 10, 31)),
+            (dt.datetime(2024, 12, 1), dt.date(2024, 11, 1), dt.date(2024, 11, 29)),
+            (dt.datetime(2025, 1, 1), dt.date(2024, 12, 2), dt.date(2024, 12, 31)),
+        ])
+
+    def test_quarter_window_across_year_wrap(self):
+        windows = self.feed_weekdays(3, dt.datetime(2024, 9, 27), dt.datetime(2025, 1, 3))
+        self.assertEqual(windows, [
+            (dt.datetime(2024, 10, 1), dt.date(2024, 9, 27), dt.date(2024, 9, 30)),
+            (dt.datetime(2025, 1, 1), dt.date(2024, 10, 1), dt.date(2024, 12, 31)),
+        ])
+
+
+class FloorCeilDatetimeTest(unittest.TestCase):
+    def test_monthly_boundaries(self):
+        monthly = rbg.RustInterval.MONTHLY
+        cases = [
+            (dt.datetime(2024, 11, 15, 10, 30), dt.datetime(2024, 12, 1), dt.datetime(2024, 12, 1)),
+            # 12 月的下一个月跨年
+            (dt.datetime(2024, 12, 31, 23, 59), dt.datetime(2025, 1, 1), dt.datetime(2025, 1, 1)),
+            # 恰好在月初：floor 为该月窗口的标签（下月 1 日），ceil 原样返回
+            (dt.datetime(2024, 12, 1), dt.datetime(2025, 1, 1), dt.datetime(2024, 12, 1)),
+        ]
+        for value, floor, ceil in cases:
+            self.assertEqual(rbg.floor_datetime(value, monthly), floor, value)
+            self.assertEqual(rbg.ceil_datetime(value, monthly), ceil, value)
+
+    def test_aware_input_uses_tz(self):
+        # UTC 12-31 16:30 为上海时间 2025-01-01 00:30
+        value = dt.datetime(2024, 12, 31, 16, 30, tzinfo=dt.timezone.utc)
+        self.assertEqual(rbg.floor_datetime(value, rbg.RustInterval.MONTHLY), dt.datetime(2025, 2, 1, tzinfo=SHANGHAI))
+        self.assertEqual(rbg.ceil_datetime(value, rbg.RustInterval.MONTHLY), dt.datetime(2025, 2, 1, tzinfo=SHANGHAI))
+        self.assertEqual(
+            rbg.floor_datetime(value, rbg.RustInterval.MONTHLY, tz="UTC"), dt.datetime(2025, 1, 1, tzinfo=dt.timezone.utc)
+        )
+
+
+class DstTest(unittest.TestCase):
+    def test_bars_across_fall_back(self):
+        # 2024-11-03 06:00 UTC 纽约时间从 01:59 EDT 回到 01:00 EST，01:xx 出现两次
+        start = dt.datetime(2024, 11, 3, 5, 58, tzinfo=dt.timezone.utc)
+        windows = []
+        bg = rbg.BarGenerator(None, 1, windows.append, "1m", tz="America/New_York")
+        for minute in range(5):
+            bg.update_bar(make_bar(minute, start=start))
+        self.assertEqual([(bar.datetime.hour, bar.datetime.minute) for bar in windows], [(1, 58), (1, 59), (1, 0), (1, 1), (1, 2)])
+
+    def test_ambiguous_and_missing_naive_times(self):
+        windows = []
+        with host_timezone("America/New_York"):
+            bg = rbg.BarGenerator(None, 1, windows.append, "1m", tz="America/New_York")
+            for bar_time in [
+                dt.datetime(2024, 3, 10, 1, 59),
+                dt.datetime(2024, 3, 10, 2, 30),            # 不存在的本地时间，与 timestamp() 一样按切换前的偏移解释
+                dt.datetime(2024, 11, 3, 1, 30),            # 有歧义，fold=0 为第一次出现
+                dt.datetime(2024, 11, 3, 1, 30, fold=1),    # 第二次出现，晚一小时
+                dt.datetime(2024, 11, 3, 1, 31, fold=1),
+            ]:
+                # datetime 加减会把 fold 重置为 0，直接用 bar_time 构造
+                bg.update_bar(rbg.RustBarData(
+                    symbol="rb", exchange="SHFE", gateway_name="CTP", datetime=bar_time, interval="1m",
+                    volume=1, open_price=1, high_price=1, low_price=1, close_price=1,
+                ))
+        self.assertEqual(
+            [(bar.datetime.month, bar.datetime.hour, bar.datetime.minute) for bar in windows],
+            [(3, 1, 59), (3, 3, 30), (11, 1, 30), (11, 1, 30), (11, 1, 31)],
+        )
+
+
+if __name__ == "__main__":
+    unittest.main()